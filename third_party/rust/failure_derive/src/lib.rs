@@ -1,17 +1,3 @@
-<<<<<<< HEAD
-extern crate proc_macro;
-extern crate syn;
-
-#[macro_use] extern crate synstructure;
-#[macro_use] extern crate quote;
-
-decl_derive!([Fail, attributes(fail, cause)] => fail_derive);
-
-fn fail_derive(s: synstructure::Structure) -> quote::Tokens {
-    let cause_body = s.each_variant(|v| {
-        if let Some(cause) = v.bindings().iter().find(is_cause) {
-            quote!(return Some(#cause))
-=======
 extern crate proc_macro2;
 extern crate syn;
 
@@ -34,106 +20,23 @@ fn fail_derive(s: synstructure::Structure) -> TokenStream {
     let cause_body = s.each_variant(|v| {
         if let Some(cause) = v.bindings().iter().find(is_cause) {
             quote!(return Some(::failure::AsFail::as_fail(#cause)))
->>>>>>> central
         } else {
             quote!(return None)
         }
     });
 
+    // Prefer a `Backtrace` field bound directly on this variant; otherwise
+    // fall back to the cause's backtrace (if any), so the outermost error
+    // still reports the original failure site when a wrapping layer didn't
+    // capture its own backtrace.
     let bt_body = s.each_variant(|v| {
         if let Some(bi) = v.bindings().iter().find(is_backtrace) {
             quote!(return Some(#bi))
         } else {
-            quote!(return None)
-        }
-    });
-
-<<<<<<< HEAD
-    #[cfg(feature = "std")]
-    let fail = s.bound_impl("::failure::Fail", quote! {
-        #[allow(unreachable_code)]
-        fn cause(&self) -> ::std::option::Option<&::failure::Fail> {
-            match *self { #cause_body }
-            None
+            quote!(return ::failure::Fail::cause(self).and_then(|cause| cause.backtrace()))
         }
-
-        #[allow(unreachable_code)]
-        fn backtrace(&self) -> ::std::option::Option<&::failure::Backtrace> {
-            match *self { #bt_body }
-            None
-        }
-    });
-
-    #[cfg(not(feature = "std"))]
-    let fail = s.bound_impl("::failure::Fail", quote! {
-        #[allow(unreachable_code)]
-        fn cause(&self) -> ::core::option::Option<&::failure::Fail> {
-            match *self { #cause_body }
-            None
-        }
-
-        #[allow(unreachable_code)]
-        fn backtrace(&self) -> ::core::option::Option<&::failure::Backtrace> {
-            match *self { #bt_body }
-            None
-        }
-    });
-
-    #[cfg(feature = "std")]
-    let display = display_body(&s).map(|display_body| {
-        s.bound_impl("::std::fmt::Display", quote! {
-            #[allow(unreachable_code)]
-            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-                match *self { #display_body }
-                write!(f, "An error has occurred.")
-            }
-        })
-    });
-
-    #[cfg(not(feature = "std"))]
-    let display = display_body(&s).map(|display_body| {
-        s.bound_impl("::core::fmt::Display", quote! {
-            #[allow(unreachable_code)]
-            fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
-                match *self { #display_body }
-                write!(f, "An error has occurred.")
-            }
-        })
     });
 
-    quote! {
-        #fail
-        #display
-    }
-}
-
-fn display_body(s: &synstructure::Structure) -> Option<quote::Tokens> {
-    let mut msgs = s.variants().iter().map(|v| find_error_msg(&v.ast().attrs));
-    if msgs.all(|msg| msg.is_none()) { return None; }
-
-    Some(s.each_variant(|v| {
-        let msg = find_error_msg(&v.ast().attrs).expect("All variants must have display attribute.");
-        if msg.is_empty() {
-            panic!("Expected at least one argument to fail attribute");
-        }
-
-        let s = match msg[0] {
-            syn::NestedMetaItem::MetaItem(syn::MetaItem::NameValue(ref i, ref lit)) if i == "display" => {
-                lit.clone()
-            }
-            _ => panic!("Fail attribute must begin `display = \"\"` to control the Display message."),
-        };
-        let args = msg[1..].iter().map(|arg| match *arg {
-            syn::NestedMetaItem::Literal(syn::Lit::Int(i, _)) => {
-                let bi = &v.bindings()[i as usize];
-                quote!(#bi)
-            }
-            syn::NestedMetaItem::MetaItem(syn::MetaItem::Word(ref id)) => {
-                if id.as_ref().starts_with("_") {
-                    if let Ok(idx) = id.as_ref()[1..].parse::<usize>() {
-                        let bi = &v.bindings()[idx];
-                        return quote!(#bi)
-=======
     let fail = s.unbound_impl(
         quote!(::failure::Fail),
         quote! {
@@ -150,6 +53,10 @@ fn display_body(s: &synstructure::Structure) -> Option<quote::Tokens> {
             }
         },
     );
+
+    // Chaining over an error's causes is already available via the `Fail`/
+    // `AsFail` trait's own `iter_chain`/`iter_causes` default methods, so
+    // this derive doesn't need to add forwarders for them.
     let display = display_body(&s).map(|display_body| {
         s.unbound_impl(
             quote!(::failure::_core::fmt::Display),
@@ -214,7 +121,6 @@ fn display_body(s: &synstructure::Structure) -> Option<quote::__rt::TokenStream>
                             }
                         };
                         return quote!(#bi);
->>>>>>> central
                     }
                 }
                 for bi in v.bindings() {
@@ -222,43 +128,34 @@ fn display_body(s: &synstructure::Structure) -> Option<quote::__rt::TokenStream>
                         return quote!(#bi);
                     }
                 }
-<<<<<<< HEAD
-                panic!("Couldn't find a field with this name!");
-=======
                 panic!(
                     "Couldn't find field `{}` in `{}::{}`",
                     id,
                     s.ast().ident,
                     v.ast().ident
                 );
->>>>>>> central
             }
             _ => panic!("Invalid argument to fail attribute!"),
         });
 
+        // In alternate mode (`{:#}`), append the error's own cause chain
+        // after its message, one "caused by:" line per link -- the same
+        // shape anyhow's `Display` produces -- so logging/telemetry code
+        // gets the full failure stack from a single format call.
         quote! {
-<<<<<<< HEAD
-            return write!(f, #s #(, #args)*)
-=======
-            return write!(f, #format_string #(, #args)*)
->>>>>>> central
+            write!(f, #format_string #(, #args)*)?;
+            if f.alternate() {
+                let mut source = ::failure::Fail::cause(self);
+                while let Some(err) = source {
+                    write!(f, "\ncaused by: {}", err)?;
+                    source = err.cause();
+                }
+            }
+            return Ok(())
         }
     }))
 }
 
-<<<<<<< HEAD
-fn find_error_msg(attrs: &[syn::Attribute]) -> Option<&[syn::NestedMetaItem]> {
-    let mut error_msg = None;
-    for attr in attrs {
-        if attr.name() == "fail" {
-            if error_msg.is_some() {
-                panic!("Cannot have two display attributes")
-            } else {
-                if let syn::MetaItem::List(_, ref list)  = attr.value {
-                    error_msg = Some(&list[..]);
-                } else {
-                    panic!("fail attribute must take a list in parantheses")
-=======
 fn find_error_msg(attrs: &[syn::Attribute]) -> Option<syn::MetaList> {
     let mut error_msg = None;
     for attr in attrs {
@@ -272,7 +169,6 @@ fn find_error_msg(attrs: &[syn::Attribute]) -> Option<syn::MetaList> {
                     } else {
                         panic!("fail attribute must take a list in parentheses")
                     }
->>>>>>> central
                 }
             }
         }
@@ -281,18 +177,6 @@ fn find_error_msg(attrs: &[syn::Attribute]) -> Option<syn::MetaList> {
 }
 
 fn is_backtrace(bi: &&synstructure::BindingInfo) -> bool {
-<<<<<<< HEAD
-        match bi.ast().ty {
-            syn::Ty::Path(None, syn::Path { segments: ref path, .. }) => {
-                path.last().map_or(false, |s| s.ident == "Backtrace" && s.parameters.is_empty())
-            }
-            _ => false
-        }
-}
-
-fn is_cause(bi: &&synstructure::BindingInfo) -> bool {
-    bi.ast().attrs.iter().any(|attr| attr.name() == "cause")
-=======
     match bi.ast().ty {
         syn::Type::Path(syn::TypePath {
             qself: None,
@@ -333,5 +217,4 @@ fn is_cause(bi: &&synstructure::BindingInfo) -> bool {
         }
     }
     found_cause
->>>>>>> central
 }
@@ -0,0 +1,39 @@
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+
+use std::io;
+
+use failure::Fail;
+
+#[derive(Fail, Debug)]
+#[fail(display = "an error has occurred: {}", inner)]
+struct WrapError {
+    #[fail(cause)]
+    inner: io::Error,
+}
+
+#[test]
+fn default_display_is_single_line() {
+    let inner = io::Error::from_raw_os_error(98);
+    let err = WrapError { inner };
+    assert_eq!(
+        format!("{}", err),
+        format!("an error has occurred: {}", io::Error::from_raw_os_error(98))
+    );
+}
+
+#[test]
+fn alternate_display_appends_cause_chain() {
+    let inner = io::Error::from_raw_os_error(98);
+    let err = WrapError { inner };
+    let rendered = format!("{:#}", err);
+    assert_eq!(
+        rendered,
+        format!(
+            "an error has occurred: {}\ncaused by: {}",
+            io::Error::from_raw_os_error(98),
+            io::Error::from_raw_os_error(98)
+        )
+    );
+}
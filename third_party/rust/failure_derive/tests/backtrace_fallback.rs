@@ -0,0 +1,35 @@
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+
+use std::io;
+
+use failure::{Backtrace, Fail};
+
+#[derive(Fail, Debug)]
+#[fail(display = "inner io error: {}", _0)]
+struct InnerError(#[fail(cause)] io::Error, Backtrace);
+
+#[derive(Fail, Debug)]
+#[fail(display = "outer error: {}", inner)]
+struct OuterError {
+    #[fail(cause)]
+    inner: InnerError,
+}
+
+#[test]
+fn backtrace_falls_back_to_cause() {
+    let inner = InnerError(io::Error::from_raw_os_error(98), Backtrace::new());
+    let outer = OuterError { inner };
+    assert!(outer.backtrace().is_some());
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "no backtrace anywhere")]
+struct NoBacktraceError;
+
+#[test]
+fn backtrace_is_none_when_no_cause_has_one() {
+    let err = NoBacktraceError;
+    assert!(err.backtrace().is_none());
+}
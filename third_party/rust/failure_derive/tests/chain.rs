@@ -0,0 +1,37 @@
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+
+use std::io;
+
+use failure::Fail;
+
+#[derive(Fail, Debug)]
+enum StoreError {
+    #[fail(display = "inner io error: {}", _0)]
+    Io(#[fail(cause)] io::Error),
+    #[fail(display = "store is unavailable")]
+    Unavailable,
+}
+
+#[test]
+fn iter_chain_starts_with_self() {
+    let err = StoreError::Io(io::Error::from_raw_os_error(98));
+    let messages: Vec<String> = err.iter_chain().map(|e| e.to_string()).collect();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0], err.to_string());
+}
+
+#[test]
+fn iter_causes_skips_self() {
+    let err = StoreError::Io(io::Error::from_raw_os_error(98));
+    let messages: Vec<String> = err.iter_causes().map(|e| e.to_string()).collect();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0], io::Error::from_raw_os_error(98).to_string());
+}
+
+#[test]
+fn iter_causes_is_empty_without_a_cause() {
+    let err = StoreError::Unavailable;
+    assert_eq!(err.iter_causes().count(), 0);
+}
@@ -1,16 +1,9 @@
 extern crate failure;
-<<<<<<< HEAD
-#[macro_use] extern crate failure_derive;
-
-use std::fmt::{self, Display};
-use failure::Fail;
-=======
 #[macro_use]
 extern crate failure_derive;
 
 use failure::Fail;
 use std::fmt::{self, Display};
->>>>>>> central
 
 #[derive(Debug, Fail)]
 struct Foo;
@@ -0,0 +1,308 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Adapts `lmdb` to the `Backend*` traits. This is the only backend today,
+//! so every existing consumer keeps working unmodified: `LmdbEnvironment` is
+//! just `lmdb::Environment` re-exported under the name `Store`/`Reader`/
+//! `Writer` expect their backend parameter to carry.
+
+use std::os::raw::c_uint;
+use std::path::Path;
+
+use lmdb;
+use lmdb::{
+    Cursor,
+    Database,
+    DatabaseFlags,
+    Environment,
+    EnvironmentBuilder,
+    EnvironmentFlags as LmdbEnvironmentFlags,
+    InactiveTransaction,
+    Iter as LmdbIter,
+    RoCursor,
+    RoTransaction,
+    RwTransaction,
+    Transaction,
+    WriteFlags as LmdbWriteFlags,
+};
+
+use error::StoreError;
+
+use super::traits::{
+    BackendDatabase,
+    BackendEnvironment,
+    BackendEnvironmentBuilder,
+    BackendIter,
+    BackendRoCursor,
+    BackendRoTransaction,
+    BackendRoTransactionRenew,
+    BackendRoTransactionReset,
+    BackendRwTransaction,
+    EnvironmentFlags,
+    WriteFlags,
+};
+
+/// `lmdb::Environment`, under the name this module's traits expect.
+pub type LmdbEnvironment = Environment;
+
+/// Wraps `lmdb::EnvironmentBuilder` so it can implement
+/// `BackendEnvironmentBuilder`, keeping its existing `new`/`set_max_dbs`
+/// methods available directly (without needing the trait in scope) for
+/// callers of the original, pre-backend-abstraction API.
+pub struct LmdbEnvironmentBuilder(EnvironmentBuilder);
+
+impl LmdbEnvironmentBuilder {
+    pub fn new() -> LmdbEnvironmentBuilder {
+        LmdbEnvironmentBuilder(Environment::new())
+    }
+
+    pub fn set_max_dbs(&mut self, max_dbs: c_uint) -> &mut LmdbEnvironmentBuilder {
+        self.0.set_max_dbs(max_dbs);
+        self
+    }
+
+    pub fn set_map_size(&mut self, size: usize) -> &mut LmdbEnvironmentBuilder {
+        self.0.set_map_size(size);
+        self
+    }
+
+    pub fn set_max_readers(&mut self, max_readers: c_uint) -> &mut LmdbEnvironmentBuilder {
+        self.0.set_max_readers(max_readers);
+        self
+    }
+
+    pub fn set_flags(&mut self, flags: EnvironmentFlags) -> &mut LmdbEnvironmentBuilder {
+        let mut lmdb_flags = LmdbEnvironmentFlags::empty();
+        if flags.contains(EnvironmentFlags::NO_SYNC) {
+            lmdb_flags.toggle(LmdbEnvironmentFlags::NO_SYNC);
+        }
+        if flags.contains(EnvironmentFlags::MAP_ASYNC) {
+            lmdb_flags.toggle(LmdbEnvironmentFlags::MAP_ASYNC);
+        }
+        if flags.contains(EnvironmentFlags::NO_TLS) {
+            lmdb_flags.toggle(LmdbEnvironmentFlags::NO_TLS);
+        }
+        self.0.set_flags(lmdb_flags);
+        self
+    }
+
+    pub fn open(&self, path: &Path) -> Result<Environment, StoreError> {
+        self.0.open(path).map_err(|e| match e {
+            lmdb::Error::Other(2) => StoreError::DirectoryDoesNotExistError(path.into()),
+            e => StoreError::LmdbError(e),
+        })
+    }
+}
+
+impl<'env> BackendEnvironmentBuilder<'env> for LmdbEnvironmentBuilder {
+    type Environment = Environment;
+    type Error = StoreError;
+
+    fn new() -> Self {
+        LmdbEnvironmentBuilder::new()
+    }
+
+    fn set_max_dbs(&mut self, max_dbs: c_uint) -> &mut Self {
+        LmdbEnvironmentBuilder::set_max_dbs(self, max_dbs)
+    }
+
+    fn set_map_size(&mut self, size: usize) -> &mut Self {
+        LmdbEnvironmentBuilder::set_map_size(self, size)
+    }
+
+    fn set_max_readers(&mut self, max_readers: c_uint) -> &mut Self {
+        LmdbEnvironmentBuilder::set_max_readers(self, max_readers)
+    }
+
+    fn set_flags(&mut self, flags: EnvironmentFlags) -> &mut Self {
+        LmdbEnvironmentBuilder::set_flags(self, flags)
+    }
+
+    fn open(&self, path: &Path) -> Result<Environment, StoreError> {
+        LmdbEnvironmentBuilder::open(self, path)
+    }
+}
+
+impl BackendDatabase for Database {}
+
+impl<'env> BackendIter<'env> for LmdbIter<'env> {}
+
+/// Bundles a cursor together with the iterator borrowed from it, the same
+/// way the pre-backend `readwrite::Iter` did, so the cursor stays alive for
+/// as long as the iterator is in use.
+pub struct LmdbRoCursor<'env>(RoCursor<'env>);
+
+impl<'env> BackendRoCursor<'env> for LmdbRoCursor<'env> {
+    type Iter = LmdbIter<'env>;
+
+    fn into_iter(mut self) -> Self::Iter {
+        // We call Cursor.iter() instead of Cursor.iter_start() because the
+        // latter panics at "called `Result::unwrap()` on an `Err` value:
+        // NotFound" when there are no items in the store, whereas the former
+        // returns an iterator that yields no items.
+        self.0.iter()
+    }
+
+    fn into_iter_from(mut self, key: &[u8]) -> Self::Iter {
+        self.0.iter_from(key)
+    }
+
+    fn into_iter_dup_of(mut self, key: &[u8]) -> Self::Iter {
+        self.0.iter_dup_of(key)
+    }
+}
+
+impl<'env> BackendRoTransaction<'env> for RoTransaction<'env> {
+    type Database = Database;
+    type RoCursor = LmdbRoCursor<'env>;
+
+    fn get(&'env self, db: Database, key: &[u8]) -> Result<Option<&'env [u8]>, StoreError> {
+        match Transaction::get(self, db, &key) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(StoreError::LmdbError(e)),
+        }
+    }
+
+    fn open_ro_cursor(&'env self, db: Database) -> Result<Self::RoCursor, StoreError> {
+        Transaction::open_ro_cursor(self, db).map(LmdbRoCursor).map_err(StoreError::LmdbError)
+    }
+
+    fn entries(&'env self, db: Database) -> Result<usize, StoreError> {
+        Transaction::stat(self, db).map(|stat| stat.entries()).map_err(StoreError::LmdbError)
+    }
+
+    fn abort(self) {
+        Transaction::abort(self);
+    }
+}
+
+impl<'env> BackendRoTransactionReset<'env> for RoTransaction<'env> {
+    type Inactive = LmdbInactiveTransaction<'env>;
+
+    fn reset(self) -> Self::Inactive {
+        LmdbInactiveTransaction(RoTransaction::reset(self))
+    }
+}
+
+/// `lmdb::InactiveTransaction`, wrapped so it can implement
+/// `BackendRoTransactionRenew`.
+pub struct LmdbInactiveTransaction<'env>(InactiveTransaction<'env>);
+
+impl<'env> BackendRoTransactionRenew<'env> for LmdbInactiveTransaction<'env> {
+    type Active = RoTransaction<'env>;
+
+    fn renew(self) -> Result<Self::Active, StoreError> {
+        self.0.renew().map_err(StoreError::LmdbError)
+    }
+}
+
+impl<'env> BackendRoTransaction<'env> for RwTransaction<'env> {
+    type Database = Database;
+    type RoCursor = LmdbRoCursor<'env>;
+
+    fn get(&'env self, db: Database, key: &[u8]) -> Result<Option<&'env [u8]>, StoreError> {
+        match Transaction::get(self, db, &key) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(StoreError::LmdbError(e)),
+        }
+    }
+
+    fn open_ro_cursor(&'env self, db: Database) -> Result<Self::RoCursor, StoreError> {
+        Transaction::open_ro_cursor(self, db).map(LmdbRoCursor).map_err(StoreError::LmdbError)
+    }
+
+    fn entries(&'env self, db: Database) -> Result<usize, StoreError> {
+        Transaction::stat(self, db).map(|stat| stat.entries()).map_err(StoreError::LmdbError)
+    }
+
+    fn abort(self) {
+        Transaction::abort(self);
+    }
+}
+
+impl<'env> BackendRwTransaction<'env> for RwTransaction<'env> {
+    fn put(&mut self, db: Database, key: &[u8], value: &[u8], flags: WriteFlags) -> Result<(), StoreError> {
+        let mut lmdb_flags = LmdbWriteFlags::empty();
+        if flags.contains(WriteFlags::NO_OVERWRITE) {
+            lmdb_flags.toggle(LmdbWriteFlags::NO_OVERWRITE);
+        }
+        if flags.contains(WriteFlags::NO_DUP_DATA) {
+            lmdb_flags.toggle(LmdbWriteFlags::NO_DUP_DATA);
+        }
+        if flags.contains(WriteFlags::APPEND) {
+            lmdb_flags.toggle(LmdbWriteFlags::APPEND);
+        }
+        if flags.contains(WriteFlags::APPEND_DUP) {
+            lmdb_flags.toggle(LmdbWriteFlags::APPEND_DUP);
+        }
+        RwTransaction::put(self, db, &key, &value, lmdb_flags).map_err(|e| match e {
+            lmdb::Error::KeyExist => StoreError::KeyExistsError,
+            e => StoreError::LmdbError(e),
+        })
+    }
+
+    fn del(&mut self, db: Database, key: &[u8]) -> Result<(), StoreError> {
+        RwTransaction::del(self, db, &key, None).map_err(StoreError::LmdbError)
+    }
+
+    fn del_value(&mut self, db: Database, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        RwTransaction::del(self, db, &key, Some(value)).map_err(StoreError::LmdbError)
+    }
+
+    fn clear(&mut self, db: Database) -> Result<(), StoreError> {
+        RwTransaction::clear_db(self, db).map_err(StoreError::LmdbError)
+    }
+
+    fn commit(self) -> Result<(), StoreError> {
+        Transaction::commit(self).map_err(StoreError::LmdbError)
+    }
+}
+
+impl<'env> BackendEnvironment<'env> for Environment {
+    type Database = Database;
+    type Error = StoreError;
+    type RoTransaction = RoTransaction<'env>;
+    type RwTransaction = RwTransaction<'env>;
+
+    fn create_db(&self, name: Option<&str>, integer_key: bool, dup_sort: bool) -> Result<Database, StoreError> {
+        let mut flags = DatabaseFlags::empty();
+        if integer_key {
+            flags.toggle(DatabaseFlags::INTEGER_KEY);
+        }
+        if dup_sort {
+            flags.toggle(DatabaseFlags::DUP_SORT);
+        }
+        self.create_db(name, flags).map_err(|e| match e {
+            lmdb::Error::BadRslot => StoreError::open_during_transaction(),
+            e => e.into(),
+        })
+    }
+
+    fn open_db(&self, name: Option<&str>) -> Result<Database, StoreError> {
+        self.open_db(name).map_err(|e| match e {
+            lmdb::Error::BadRslot => StoreError::open_during_transaction(),
+            e => e.into(),
+        })
+    }
+
+    fn begin_ro_txn(&'env self) -> Result<RoTransaction<'env>, StoreError> {
+        self.begin_ro_txn().map_err(StoreError::LmdbError)
+    }
+
+    fn begin_rw_txn(&'env self) -> Result<RwTransaction<'env>, StoreError> {
+        self.begin_rw_txn().map_err(StoreError::LmdbError)
+    }
+
+    fn sync(&self, force: bool) -> Result<(), StoreError> {
+        Environment::sync(self, force).map_err(StoreError::LmdbError)
+    }
+}
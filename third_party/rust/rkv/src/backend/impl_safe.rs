@@ -0,0 +1,489 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A pure-Rust backend that stores everything in Rust data structures and
+//! persists it via `serde`/`bincode` under a small self-verifying header
+//! (magic, format version, BLAKE3 digest of the payload), for environments
+//! where linking the C LMDB library isn't an option (e.g. sandboxed or
+//! cross-compiled targets) or where a corrupt mmap'd file is unacceptable.
+//!
+//! Every database is a `BTreeMap<Box<[u8]>, Vec<Box<[u8]>>>`; a dup-sort
+//! database keeps its value vector sorted and de-duplicated, while a
+//! non-dup-sort store just keeps it at length 1. Readers see a cheaply-
+//! cloned `Arc` snapshot of the committed state, so a long-lived reader is
+//! isolated from writes that commit after it started, the same guarantee
+//! LMDB gives. There can only be one writer at a time, enforced by
+//! `write_mutex`; it stages its changes in its own clone of the state and
+//! only swaps it in, and persists it to `data.safe.bin`, on `commit`.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::os::raw::c_uint;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+    MutexGuard,
+    RwLock,
+};
+
+use bincode::{
+    deserialize,
+    serialize,
+};
+
+use blake3;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use error::StoreError;
+
+use super::traits::{
+    BackendDatabase,
+    BackendEnvironment,
+    BackendEnvironmentBuilder,
+    BackendIter,
+    BackendRoCursor,
+    BackendRoTransaction,
+    BackendRoTransactionRenew,
+    BackendRoTransactionReset,
+    BackendRwTransaction,
+    EnvironmentFlags,
+    WriteFlags,
+};
+
+/// Builds a `SafeModeEnvironment`. There's no reader/database table to size
+/// up front the way LMDB's `set_max_dbs` does, and no memory map, reader
+/// limit, or durability flags to tune either, so those are all no-ops here --
+/// `SafeModeState` just grows as databases are created, and every write is
+/// already flushed to disk eagerly.
+#[derive(Default)]
+pub struct SafeModeEnvironmentBuilder;
+
+impl<'env> BackendEnvironmentBuilder<'env> for SafeModeEnvironmentBuilder {
+    type Environment = SafeModeEnvironment;
+    type Error = StoreError;
+
+    fn new() -> Self {
+        SafeModeEnvironmentBuilder
+    }
+
+    fn set_max_dbs(&mut self, _max_dbs: c_uint) -> &mut Self {
+        self
+    }
+
+    fn set_map_size(&mut self, _size: usize) -> &mut Self {
+        self
+    }
+
+    fn set_max_readers(&mut self, _max_readers: c_uint) -> &mut Self {
+        self
+    }
+
+    fn set_flags(&mut self, _flags: EnvironmentFlags) -> &mut Self {
+        self
+    }
+
+    fn open(&self, path: &Path) -> Result<SafeModeEnvironment, StoreError> {
+        SafeModeEnvironment::new(path)
+    }
+}
+
+/// Identifies the on-disk snapshot format, so a future incompatible change
+/// can be detected cleanly instead of failing deserialization confusingly.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"RKVS";
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_HEADER_LEN: usize = 4 + 4 + 32 + 8;
+
+type Key = Box<[u8]>;
+type Values = Vec<Box<[u8]>>;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct SafeModeState {
+    /// Name and dup-sort-ness of each open database, indexed by the
+    /// `SafeModeDatabase` id assigned to it, so that both survive a reload
+    /// of the snapshot file.
+    names: Vec<String>,
+    dup_sort: Vec<bool>,
+    /// One `BTreeMap` per open database, indexed the same way. A non
+    /// dup-sort store keeps its value `Vec` at length 1.
+    dbs: Vec<BTreeMap<Key, Values>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SafeModeDatabase(usize);
+
+impl BackendDatabase for SafeModeDatabase {}
+
+pub struct SafeModeEnvironment {
+    path: PathBuf,
+    state: RwLock<Arc<SafeModeState>>,
+    write_mutex: Mutex<()>,
+}
+
+impl SafeModeEnvironment {
+    fn snapshot(&self) -> Arc<SafeModeState> {
+        Arc::clone(&*self.state.read().unwrap())
+    }
+}
+
+impl SafeModeEnvironment {
+    pub fn new(path: &Path) -> Result<SafeModeEnvironment, StoreError> {
+        let state = match fs::read(Self::snapshot_path(path)) {
+            Ok(bytes) => Self::decode_snapshot(&bytes)?,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => SafeModeState::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(SafeModeEnvironment {
+            path: path.into(),
+            state: RwLock::new(Arc::new(state)),
+            write_mutex: Mutex::new(()),
+        })
+    }
+
+    fn snapshot_path(path: &Path) -> PathBuf {
+        path.join("data.safe.bin")
+    }
+
+    fn db_index(&self, name: Option<&str>) -> Option<usize> {
+        let name = name.unwrap_or("");
+        self.state.read().unwrap().names.iter().position(|n| n == name)
+    }
+
+    /// Checks the header written by `persist` -- magic, format version, and
+    /// a BLAKE3 digest of the payload -- before trusting the payload to
+    /// `bincode`, so a truncated or bit-flipped snapshot is caught here
+    /// rather than surfacing as a confusing decode error (or worse, a
+    /// silently wrong `Value`) from `reader.get`/`iter.next`.
+    fn decode_snapshot(bytes: &[u8]) -> Result<SafeModeState, StoreError> {
+        if bytes.len() < SNAPSHOT_HEADER_LEN {
+            return Err(StoreError::DataCorrupted("snapshot file is shorter than its header".to_owned()));
+        }
+        let (magic, rest) = bytes.split_at(4);
+        if magic != SNAPSHOT_MAGIC {
+            return Err(StoreError::DataCorrupted("snapshot file has an unrecognized header".to_owned()));
+        }
+        let (version_bytes, rest) = rest.split_at(4);
+        let version = u32::from_le_bytes(version_bytes.try_into().unwrap());
+        if version != SNAPSHOT_VERSION {
+            return Err(StoreError::DataCorrupted(format!("unsupported snapshot format version {}", version)));
+        }
+        let (digest_bytes, rest) = rest.split_at(32);
+        let (len_bytes, payload) = rest.split_at(8);
+        let len = u64::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+        if payload.len() != len {
+            return Err(StoreError::DataCorrupted("snapshot payload length doesn't match its header".to_owned()));
+        }
+
+        let expected = blake3::Hash::from(<[u8; 32]>::try_from(digest_bytes).unwrap());
+        if blake3::hash(payload) != expected {
+            return Err(StoreError::DataCorrupted("snapshot payload failed its integrity check".to_owned()));
+        }
+
+        deserialize(payload).map_err(|e| StoreError::FileInvalid(e.to_string()))
+    }
+
+    fn persist(&self, state: &SafeModeState) -> Result<(), StoreError> {
+        let payload = serialize(state).map_err(|e| StoreError::FileInvalid(e.to_string()))?;
+        let digest = blake3::hash(&payload);
+
+        let mut bytes = Vec::with_capacity(SNAPSHOT_HEADER_LEN + payload.len());
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(digest.as_bytes());
+        bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+
+        let tmp = self.path.join("data.safe.bin.tmp");
+        fs::write(&tmp, &bytes)?;
+        fs::rename(&tmp, Self::snapshot_path(&self.path))?;
+        Ok(())
+    }
+
+    /// Flushes the current state to `data.safe.bin`, mirroring LMDB's
+    /// `mdb_env_sync`. `create_db` and `commit` already persist eagerly, so
+    /// this is normally redundant -- it exists as an explicit flush point for
+    /// callers that want one, and `Drop` calls it as a last-resort safety
+    /// net in case some future write path forgets to.
+    pub fn sync(&self) -> Result<(), StoreError> {
+        self.persist(&self.snapshot())
+    }
+}
+
+impl Drop for SafeModeEnvironment {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+impl<'env> BackendEnvironment<'env> for SafeModeEnvironment {
+    type Database = SafeModeDatabase;
+    type Error = StoreError;
+    type RoTransaction = SafeModeRoTransaction<'env>;
+    type RwTransaction = SafeModeRwTransaction<'env>;
+
+    fn create_db(&self, name: Option<&str>, _integer_key: bool, dup_sort: bool) -> Result<SafeModeDatabase, StoreError> {
+        if let Some(index) = self.db_index(name) {
+            return Ok(SafeModeDatabase(index));
+        }
+
+        // Held for the rest of this function -- the same mutex `begin_rw_txn`
+        // holds for its transaction's whole lifetime -- so a db can't be
+        // added here while a write transaction has the prior state staged,
+        // per this module's "one writer at a time" invariant; otherwise the
+        // transaction's `commit` would overwrite `state` with a clone that
+        // never saw this db and silently revert its creation.
+        let _guard = self.write_mutex.lock().unwrap();
+
+        // Someone else may have created this db while we were waiting for
+        // the lock.
+        if let Some(index) = self.db_index(name) {
+            return Ok(SafeModeDatabase(index));
+        }
+
+        let mut state = (**self.state.read().unwrap()).clone();
+        state.names.push(name.unwrap_or("").to_owned());
+        state.dup_sort.push(dup_sort);
+        state.dbs.push(BTreeMap::new());
+        let index = state.names.len() - 1;
+        self.persist(&state)?;
+        *self.state.write().unwrap() = Arc::new(state);
+        Ok(SafeModeDatabase(index))
+    }
+
+    fn open_db(&self, name: Option<&str>) -> Result<SafeModeDatabase, StoreError> {
+        self.db_index(name).map(SafeModeDatabase).ok_or_else(|| StoreError::FileInvalid(format!("no such store: {:?}", name)))
+    }
+
+    fn begin_ro_txn(&'env self) -> Result<SafeModeRoTransaction<'env>, StoreError> {
+        Ok(SafeModeRoTransaction {
+            env: self,
+            snapshot: self.snapshot(),
+        })
+    }
+
+    fn begin_rw_txn(&'env self) -> Result<SafeModeRwTransaction<'env>, StoreError> {
+        let guard = self.write_mutex.lock().unwrap();
+        let staged = (**self.state.read().unwrap()).clone();
+        Ok(SafeModeRwTransaction {
+            env: self,
+            _guard: guard,
+            staged,
+        })
+    }
+
+    fn sync(&self, _force: bool) -> Result<(), StoreError> {
+        self.persist(&self.snapshot())
+    }
+}
+
+pub struct SafeModeRoTransaction<'env> {
+    env: &'env SafeModeEnvironment,
+    snapshot: Arc<SafeModeState>,
+}
+
+impl<'env> BackendRoTransaction<'env> for SafeModeRoTransaction<'env> {
+    type Database = SafeModeDatabase;
+    type RoCursor = SafeModeRoCursor<'env>;
+
+    fn get(&'env self, db: SafeModeDatabase, key: &[u8]) -> Result<Option<&'env [u8]>, StoreError> {
+        Ok(self.snapshot.dbs[db.0].get(key).and_then(|values| values.first()).map(AsRef::as_ref))
+    }
+
+    fn open_ro_cursor(&'env self, db: SafeModeDatabase) -> Result<SafeModeRoCursor<'env>, StoreError> {
+        Ok(SafeModeRoCursor {
+            entries: flatten(&self.snapshot.dbs[db.0]),
+        })
+    }
+
+    fn entries(&'env self, db: SafeModeDatabase) -> Result<usize, StoreError> {
+        Ok(self.snapshot.dbs[db.0].values().map(Vec::len).sum())
+    }
+
+    fn abort(self) {}
+}
+
+impl<'env> BackendRoTransactionReset<'env> for SafeModeRoTransaction<'env> {
+    type Inactive = SafeModeInactiveTransaction<'env>;
+
+    fn reset(self) -> Self::Inactive {
+        SafeModeInactiveTransaction {
+            env: self.env,
+        }
+    }
+}
+
+/// A reserved-but-dormant `SafeModeRoTransaction`. There's no reader-table
+/// slot to hold onto here -- the snapshot is just an `Arc` -- so all this
+/// really does is drop the old snapshot until `renew` takes a fresh one.
+pub struct SafeModeInactiveTransaction<'env> {
+    env: &'env SafeModeEnvironment,
+}
+
+impl<'env> BackendRoTransactionRenew<'env> for SafeModeInactiveTransaction<'env> {
+    type Active = SafeModeRoTransaction<'env>;
+
+    fn renew(self) -> Result<Self::Active, StoreError> {
+        Ok(SafeModeRoTransaction {
+            env: self.env,
+            snapshot: self.env.snapshot(),
+        })
+    }
+}
+
+/// Flattens a database into one `(key, value)` pair per value, in key order
+/// and, within a key, in value order — what a dup-aware cursor walks.
+fn flatten(db: &BTreeMap<Key, Values>) -> Vec<(&[u8], &[u8])> {
+    db.iter().flat_map(|(k, values)| values.iter().map(move |v| (k.as_ref(), v.as_ref()))).collect()
+}
+
+pub struct SafeModeRwTransaction<'env> {
+    env: &'env SafeModeEnvironment,
+    _guard: MutexGuard<'env, ()>,
+    staged: SafeModeState,
+}
+
+impl<'env> BackendRoTransaction<'env> for SafeModeRwTransaction<'env> {
+    type Database = SafeModeDatabase;
+    type RoCursor = SafeModeRoCursor<'env>;
+
+    fn get(&'env self, db: SafeModeDatabase, key: &[u8]) -> Result<Option<&'env [u8]>, StoreError> {
+        Ok(self.staged.dbs[db.0].get(key).and_then(|values| values.first()).map(AsRef::as_ref))
+    }
+
+    fn open_ro_cursor(&'env self, db: SafeModeDatabase) -> Result<SafeModeRoCursor<'env>, StoreError> {
+        Ok(SafeModeRoCursor {
+            entries: flatten(&self.staged.dbs[db.0]),
+        })
+    }
+
+    fn entries(&'env self, db: SafeModeDatabase) -> Result<usize, StoreError> {
+        Ok(self.staged.dbs[db.0].values().map(Vec::len).sum())
+    }
+
+    fn abort(self) {}
+}
+
+impl<'env> BackendRwTransaction<'env> for SafeModeRwTransaction<'env> {
+    fn put(&mut self, db: SafeModeDatabase, key: &[u8], value: &[u8], flags: WriteFlags) -> Result<(), StoreError> {
+        if flags.contains(WriteFlags::NO_OVERWRITE) && self.staged.dbs[db.0].contains_key(key) {
+            return Err(StoreError::KeyExistsError);
+        }
+
+        if self.staged.dup_sort[db.0] {
+            let values = self.staged.dbs[db.0].entry(key.into()).or_insert_with(Vec::new);
+            match values.binary_search_by(|v| v.as_ref().cmp(value)) {
+                Ok(_) if flags.contains(WriteFlags::NO_DUP_DATA) => return Err(StoreError::KeyExistsError),
+                Ok(_) => {},
+                Err(index) => values.insert(index, value.into()),
+            }
+        } else {
+            self.staged.dbs[db.0].insert(key.into(), vec![value.into()]);
+        }
+
+        // APPEND/APPEND_DUP are a fast-path hint for sequential bulk loads;
+        // a `BTreeMap` insert is already O(log n) regardless of key order,
+        // so there's no faster path here to opt into.
+        Ok(())
+    }
+
+    fn del(&mut self, db: SafeModeDatabase, key: &[u8]) -> Result<(), StoreError> {
+        self.staged.dbs[db.0].remove(key);
+        Ok(())
+    }
+
+    fn del_value(&mut self, db: SafeModeDatabase, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        if let Some(values) = self.staged.dbs[db.0].get_mut(key) {
+            if let Ok(index) = values.binary_search_by(|v| v.as_ref().cmp(value)) {
+                values.remove(index);
+                if values.is_empty() {
+                    self.staged.dbs[db.0].remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, db: SafeModeDatabase) -> Result<(), StoreError> {
+        self.staged.dbs[db.0].clear();
+        Ok(())
+    }
+
+    fn commit(mut self) -> Result<(), StoreError> {
+        // Merge in any dbs `create_db` has added since `self.staged` was
+        // cloned from `state` in `begin_rw_txn`, rather than overwriting
+        // `state` with `self.staged` outright -- `write_mutex` now makes
+        // this a no-op in practice (`create_db` can't run concurrently with
+        // an open write transaction), but committing this way keeps the
+        // invariant true by construction rather than by lock discipline
+        // alone.
+        let current = self.env.state.read().unwrap();
+        for index in self.staged.names.len()..current.names.len() {
+            self.staged.names.push(current.names[index].clone());
+            self.staged.dup_sort.push(current.dup_sort[index]);
+            self.staged.dbs.push(BTreeMap::new());
+        }
+        drop(current);
+
+        self.env.persist(&self.staged)?;
+        *self.env.state.write().unwrap() = Arc::new(self.staged);
+        Ok(())
+    }
+}
+
+pub struct SafeModeRoCursor<'env> {
+    entries: Vec<(&'env [u8], &'env [u8])>,
+}
+
+pub struct SafeModeIter<'env> {
+    entries: std::vec::IntoIter<(&'env [u8], &'env [u8])>,
+}
+
+impl<'env> Iterator for SafeModeIter<'env> {
+    type Item = (&'env [u8], Result<&'env [u8], StoreError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|(k, v)| (k, Ok(v)))
+    }
+}
+
+impl<'env> BackendIter<'env> for SafeModeIter<'env> {}
+
+impl<'env> BackendRoCursor<'env> for SafeModeRoCursor<'env> {
+    type Iter = SafeModeIter<'env>;
+
+    fn into_iter(self) -> Self::Iter {
+        SafeModeIter {
+            entries: self.entries.into_iter(),
+        }
+    }
+
+    fn into_iter_from(self, key: &[u8]) -> Self::Iter {
+        let start = self.entries.iter().position(|(k, _)| *k >= key).unwrap_or_else(|| self.entries.len());
+        SafeModeIter {
+            entries: self.entries[start..].to_vec().into_iter(),
+        }
+    }
+
+    fn into_iter_dup_of(self, key: &[u8]) -> Self::Iter {
+        let matching: Vec<_> = self.entries.into_iter().filter(|(k, _)| *k == key).collect();
+        SafeModeIter {
+            entries: matching.into_iter(),
+        }
+    }
+}
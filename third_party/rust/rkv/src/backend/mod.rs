@@ -0,0 +1,45 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Decouples `Store`, `Reader`, `Writer`, `IntegerStore`, and `Iter` from any
+//! particular storage engine.
+//!
+//! `impl_lmdb` adapts the `lmdb` crate to the traits in this module;
+//! `impl_safe` is a second, pure-Rust backend for environments where LMDB's
+//! mmap semantics don't work. Any engine that can satisfy `BackendDatabase`,
+//! `BackendRoTransaction`, `BackendRwTransaction`, `BackendRoCursor`, and
+//! `BackendEnvironment` can be substituted without changing `Store`,
+//! `Reader`, `Writer`, `IntegerStore`, or their call sites.
+
+mod impl_lmdb;
+mod impl_safe;
+mod traits;
+
+pub use self::impl_lmdb::{
+    LmdbEnvironment,
+    LmdbEnvironmentBuilder,
+};
+pub use self::impl_safe::{
+    SafeModeDatabase,
+    SafeModeEnvironment,
+    SafeModeEnvironmentBuilder,
+};
+pub use self::traits::{
+    BackendDatabase,
+    BackendEnvironment,
+    BackendEnvironmentBuilder,
+    BackendIter,
+    BackendRoCursor,
+    BackendRoTransaction,
+    BackendRoTransactionRenew,
+    BackendRoTransactionReset,
+    BackendRwTransaction,
+    WriteFlags,
+};
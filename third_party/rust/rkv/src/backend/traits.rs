@@ -0,0 +1,243 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::fmt::Debug;
+use std::ops::{
+    BitOr,
+    BitOrAssign,
+};
+use std::os::raw::c_uint;
+use std::path::Path;
+
+use error::StoreError;
+
+/// Flags governing how `BackendRwTransaction::put` treats an existing
+/// key/value, expressed independently of any one backend's native flags so
+/// that every backend -- including non-LMDB ones -- can honor the same
+/// semantics.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteFlags(u32);
+
+impl WriteFlags {
+    /// Fail with `StoreError::KeyExistsError` rather than overwrite a key
+    /// that already exists.
+    pub const NO_OVERWRITE: WriteFlags = WriteFlags(0b0001);
+
+    /// For a dup-sort database only: fail with `StoreError::KeyExistsError`
+    /// rather than insert a `(key, value)` pair that's already present,
+    /// instead of silently no-op'ing it.
+    pub const NO_DUP_DATA: WriteFlags = WriteFlags(0b0010);
+
+    /// A hint that `key` sorts after every key already in the database, so
+    /// the backend can use a faster sequential-append insertion path.
+    /// Backends that have no such fast path may ignore it.
+    pub const APPEND: WriteFlags = WriteFlags(0b0100);
+
+    /// Like `APPEND`, but for a dup-sort database's per-key value list.
+    pub const APPEND_DUP: WriteFlags = WriteFlags(0b1000);
+
+    pub fn empty() -> WriteFlags {
+        WriteFlags(0)
+    }
+
+    pub fn contains(self, other: WriteFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for WriteFlags {
+    type Output = WriteFlags;
+
+    fn bitor(self, other: WriteFlags) -> WriteFlags {
+        WriteFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for WriteFlags {
+    fn bitor_assign(&mut self, other: WriteFlags) {
+        self.0 |= other.0;
+    }
+}
+
+/// Flags controlling an environment's open-time durability/locking behavior,
+/// expressed independently of any one backend's native flags (as
+/// `WriteFlags` does for `put`).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvironmentFlags(u32);
+
+impl EnvironmentFlags {
+    /// Don't flush system buffers to disk when committing a transaction.
+    /// Faster, but a system crash (not just an application crash) can lose
+    /// or corrupt the last committed transactions.
+    pub const NO_SYNC: EnvironmentFlags = EnvironmentFlags(0b0001);
+
+    /// When `NO_SYNC` (or a writemap) is in effect, flush asynchronously
+    /// rather than waiting for the flush to complete.
+    pub const MAP_ASYNC: EnvironmentFlags = EnvironmentFlags(0b0010);
+
+    /// Don't use thread-local storage for read transactions, so a read
+    /// transaction started on one thread may be used (one at a time) from
+    /// another.
+    pub const NO_TLS: EnvironmentFlags = EnvironmentFlags(0b0100);
+
+    pub fn empty() -> EnvironmentFlags {
+        EnvironmentFlags(0)
+    }
+
+    pub fn contains(self, other: EnvironmentFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for EnvironmentFlags {
+    type Output = EnvironmentFlags;
+
+    fn bitor(self, other: EnvironmentFlags) -> EnvironmentFlags {
+        EnvironmentFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for EnvironmentFlags {
+    fn bitor_assign(&mut self, other: EnvironmentFlags) {
+        self.0 |= other.0;
+    }
+}
+
+/// A handle to an open table within an environment. Cheap to copy (for LMDB
+/// it's a C integer); a non-LMDB backend can use an index into its own
+/// table registry instead.
+pub trait BackendDatabase: Copy + Clone + Debug {}
+
+/// The raw `(key, value)` pairs yielded while walking a `BackendRoCursor`.
+/// `read_transform` in `readwrite.rs` is responsible for turning the value
+/// half into a `Value`; this trait stays below that layer.
+pub trait BackendIter<'env>: Iterator<Item = (&'env [u8], Result<&'env [u8], StoreError>)> {}
+
+/// A cursor positioned over a `BackendDatabase`, consumed to produce an
+/// iterator ordered lexicographically by key.
+pub trait BackendRoCursor<'env> {
+    type Iter: BackendIter<'env>;
+
+    /// Iterate every entry in the database, starting at the first key.
+    fn into_iter(self) -> Self::Iter;
+
+    /// Iterate every entry whose key is `>= key`.
+    fn into_iter_from(self, key: &[u8]) -> Self::Iter;
+
+    /// Iterate every value stored under exactly `key` in a dup-sort
+    /// database, in sorted order. Meaningless (and meaningless only) for a
+    /// database that isn't dup-sort; `MultiStore` is the only caller.
+    fn into_iter_dup_of(self, key: &[u8]) -> Self::Iter;
+}
+
+/// A read-only transaction.
+pub trait BackendRoTransaction<'env> {
+    type Database: BackendDatabase;
+    type RoCursor: BackendRoCursor<'env>;
+
+    fn get(&'env self, db: Self::Database, key: &[u8]) -> Result<Option<&'env [u8]>, StoreError>;
+    fn open_ro_cursor(&'env self, db: Self::Database) -> Result<Self::RoCursor, StoreError>;
+
+    /// The number of entries in `db`, without iterating them -- for LMDB,
+    /// the count `mdb_stat` reports off the database's own metadata.
+    fn entries(&'env self, db: Self::Database) -> Result<usize, StoreError>;
+
+    fn abort(self);
+}
+
+/// A `BackendRoTransaction` that can be reset, as LMDB's `mdb_txn_reset`:
+/// release the snapshot (and, for LMDB, the slot it holds in the reader
+/// table) while keeping that slot reserved, so that `renew`ing it later is
+/// cheaper than beginning a brand new read transaction. Implemented only by
+/// an environment's `RoTransaction`, not its `RwTransaction` -- resetting a
+/// write transaction isn't a thing.
+pub trait BackendRoTransactionReset<'env>: BackendRoTransaction<'env> + Sized {
+    type Inactive: BackendRoTransactionRenew<'env, Active = Self>;
+
+    fn reset(self) -> Self::Inactive;
+}
+
+/// The inactive half of `BackendRoTransactionReset`: a reserved-but-dormant
+/// transaction slot, good for nothing but `renew`.
+pub trait BackendRoTransactionRenew<'env> {
+    type Active;
+
+    /// Brings the transaction back to life against the latest committed
+    /// state, as LMDB's `mdb_txn_renew`. The resulting transaction sees a
+    /// fresh snapshot, not the one in effect when `reset` was called.
+    fn renew(self) -> Result<Self::Active, StoreError>;
+}
+
+/// A read-write transaction. Writes made through it are invisible to other
+/// transactions (including other `BackendRoTransaction`s opened against the
+/// same environment) until `commit` succeeds.
+pub trait BackendRwTransaction<'env>: BackendRoTransaction<'env> {
+    fn put(&mut self, db: Self::Database, key: &[u8], value: &[u8], flags: WriteFlags) -> Result<(), StoreError>;
+    fn del(&mut self, db: Self::Database, key: &[u8]) -> Result<(), StoreError>;
+
+    /// Deletes a single `value` among the possibly-several values stored
+    /// under `key` in a dup-sort database, leaving any other values under
+    /// that key untouched. Deletes the whole key if `value` is its last
+    /// remaining value.
+    fn del_value(&mut self, db: Self::Database, key: &[u8], value: &[u8]) -> Result<(), StoreError>;
+
+    /// Deletes every entry in `db`, leaving the (now-empty) database itself
+    /// open. Cheaper and less racy than enumerating and `del`-ing each key,
+    /// since it's one operation in this one transaction rather than many.
+    fn clear(&mut self, db: Self::Database) -> Result<(), StoreError>;
+
+    fn commit(self) -> Result<(), StoreError>;
+}
+
+/// An opened environment: the entry point for creating/opening databases and
+/// beginning transactions against them.
+pub trait BackendEnvironment<'env> {
+    type Database: BackendDatabase;
+    type Error: Into<StoreError> + Debug;
+    type RoTransaction: BackendRoTransaction<'env, Database = Self::Database> + BackendRoTransactionReset<'env>;
+    type RwTransaction: BackendRwTransaction<'env, Database = Self::Database>;
+
+    fn create_db(&self, name: Option<&str>, integer_key: bool, dup_sort: bool) -> Result<Self::Database, Self::Error>;
+    fn open_db(&self, name: Option<&str>) -> Result<Self::Database, Self::Error>;
+    fn begin_ro_txn(&'env self) -> Result<Self::RoTransaction, Self::Error>;
+    fn begin_rw_txn(&'env self) -> Result<Self::RwTransaction, Self::Error>;
+
+    /// Flushes any buffered writes to disk, as LMDB's `mdb_env_sync`. `force`
+    /// requests a flush even if the environment was opened with `NO_SYNC` or
+    /// `MAP_ASYNC`; a backend with nothing to flush may treat this as a
+    /// no-op.
+    fn sync(&self, force: bool) -> Result<(), Self::Error>;
+}
+
+/// Builds a `BackendEnvironment`, mirroring LMDB's env-creation flow: set
+/// whatever knobs the backend supports (today, the open-database capacity,
+/// map size, max readers, and environment flags), then `open` a path into a
+/// live environment. A backend with nothing to configure can make these
+/// setters no-ops.
+pub trait BackendEnvironmentBuilder<'env>: Sized {
+    type Environment: BackendEnvironment<'env>;
+    type Error: Into<StoreError> + Debug;
+
+    fn new() -> Self;
+    fn set_max_dbs(&mut self, max_dbs: c_uint) -> &mut Self;
+
+    /// Sets the size, in bytes, of the memory map backing the environment.
+    /// This is the hard ceiling on the environment's total size; it must be
+    /// raised before the store fills, or writes fail with `MapFull`.
+    fn set_map_size(&mut self, size: usize) -> &mut Self;
+
+    /// Sets the maximum number of threads/reader slots for the environment.
+    fn set_max_readers(&mut self, max_readers: c_uint) -> &mut Self;
+
+    /// Sets environment-wide flags, such as `NO_SYNC`/`MAP_ASYNC`/`NO_TLS`.
+    fn set_flags(&mut self, flags: EnvironmentFlags) -> &mut Self;
+
+    fn open(&self, path: &Path) -> Result<Self::Environment, Self::Error>;
+}
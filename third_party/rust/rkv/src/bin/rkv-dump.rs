@@ -0,0 +1,109 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A small CLI around `rkv::dump`: prints a store as human-readable text, or
+//! emits/consumes the binary dump format, so a database can be snapshotted,
+//! diffed against another environment, or moved between the LMDB and
+//! safe-mode backends without writing a throwaway Rust program to do it.
+//!
+//! Usage:
+//!   rkv-dump print  <path> [store]
+//!   rkv-dump dump   <path> [store] <out-file>
+//!   rkv-dump load   <path> [store] <in-file>
+//!
+//! `path` is an environment directory; `store` names a sub-database and
+//! defaults to the unnamed store. `load` always targets the LMDB backend;
+//! pass `--safe` before `path` to target the safe-mode backend instead.
+
+extern crate rkv;
+
+use std::env as std_env;
+use std::fs::File;
+use std::process;
+
+use rkv::backend::{
+    LmdbEnvironment,
+    SafeModeEnvironment,
+};
+use rkv::{
+    dump,
+    Rkv,
+    StoreOptions,
+};
+
+fn usage() -> ! {
+    eprintln!("usage: rkv-dump [--safe] print|dump|load <path> [store] [file]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args: Vec<String> = std_env::args().skip(1).collect();
+
+    let safe = if args.first().map(String::as_str) == Some("--safe") {
+        args.remove(0);
+        true
+    } else {
+        false
+    };
+
+    if args.len() < 2 {
+        usage();
+    }
+
+    let command = args.remove(0);
+    let path = args.remove(0);
+
+    let result = if safe {
+        run(&Rkv::<SafeModeEnvironment>::new_safe(path.as_ref()).expect("open"), &command, &args)
+    } else {
+        run(&Rkv::<LmdbEnvironment>::new(path.as_ref()).expect("open"), &command, &args)
+    };
+
+    if let Err(e) = result {
+        eprintln!("rkv-dump: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run<'env, E>(k: &'env Rkv<E>, command: &str, args: &[String]) -> Result<(), rkv::StoreError>
+where
+    E: rkv::backend::BackendEnvironment<'env>,
+{
+    match command {
+        "print" => {
+            let store = k.open_single(args.first().map(String::as_str), StoreOptions::default())?;
+            let reader = k.read::<&str>()?;
+            for (key, value) in reader.iter_start(&store)? {
+                println!("{:?} = {:?}", String::from_utf8_lossy(key), value?);
+            }
+            Ok(())
+        },
+        "dump" => {
+            let store = k.open_single(args.first().map(String::as_str), StoreOptions::default())?;
+            let out_path = args.get(1).unwrap_or_else(|| usage());
+            let mut out = File::create(out_path).map_err(rkv::StoreError::IoError)?;
+            let reader = k.read::<&str>()?;
+            let count = dump::dump(&reader, &store, &mut out)?;
+            eprintln!("dumped {} entries", count);
+            Ok(())
+        },
+        "load" => {
+            let store = k.open_single(args.first().map(String::as_str), StoreOptions::create())?;
+            let in_path = args.get(1).unwrap_or_else(|| usage());
+            let mut input = File::open(in_path).map_err(rkv::StoreError::IoError)?;
+            let mut writer = k.write::<Vec<u8>>()?;
+            let count = dump::load(&mut writer, &store, &mut input)?;
+            writer.commit()?;
+            eprintln!("loaded {} entries", count);
+            Ok(())
+        },
+        _ => usage(),
+    }
+}
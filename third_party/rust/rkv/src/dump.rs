@@ -0,0 +1,183 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Portable dump/load for a single store's contents: a length-prefixed
+//! stream of `(key, value)` pairs, where each value is the same tagged byte
+//! encoding `Value::to_bytes`/`from_tagged_slice` already use on disk. Since
+//! that encoding carries its own type tag, `load` doesn't need to know the
+//! key/value types ahead of time, which is what lets a dump move between
+//! backends (LMDB, SafeMode) and architectures, not just within one.
+
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+
+use backend::BackendEnvironment;
+
+use error::StoreError;
+
+use readwrite::{
+    Reader,
+    Store,
+    Writer,
+};
+
+use value::Value;
+
+const MAGIC: &[u8; 8] = b"RKVDUMP1";
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut len_bytes = [0u8; 4];
+    r.read_exact(&mut len_bytes)?;
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Dumps every `(key, value)` pair in `store`, in key order, to `out`.
+/// Returns the number of entries written.
+pub fn dump<'env, 's, E, K, W>(reader: &'s Reader<'env, E, K>, store: &'s Store<E::Database>, out: &mut W) -> Result<usize, StoreError>
+where
+    'env: 's,
+    E: BackendEnvironment<'env>,
+    K: AsRef<[u8]>,
+    W: Write,
+{
+    out.write_all(MAGIC).map_err(StoreError::IoError)?;
+
+    let mut count = 0usize;
+    for (key, value) in reader.iter_start(store)? {
+        let value = value?.ok_or_else(|| StoreError::FileInvalid("dumped entry has no value".to_owned()))?;
+        write_bytes(out, key).map_err(StoreError::IoError)?;
+        write_bytes(out, &value.to_bytes().map_err(StoreError::DataError)?).map_err(StoreError::IoError)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Loads a stream written by `dump` into `store` via `writer`, leaving
+/// `writer` uncommitted so the caller can combine several stores' loads (or
+/// further writes) into one transaction. Returns the number of entries
+/// loaded.
+pub fn load<'env, 's, E, R>(writer: &'s mut Writer<'env, E, Vec<u8>>, store: &'s Store<E::Database>, input: &mut R) -> Result<usize, StoreError>
+where
+    E: BackendEnvironment<'env>,
+    R: Read,
+{
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).map_err(StoreError::IoError)?;
+    if &magic != MAGIC {
+        return Err(StoreError::FileInvalid("not an rkv dump (bad magic)".to_owned()));
+    }
+
+    let mut count = 0usize;
+    loop {
+        let key = match read_bytes(input) {
+            Ok(key) => key,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(StoreError::IoError(e)),
+        };
+        let value_bytes = read_bytes(input).map_err(StoreError::IoError)?;
+        let value = Value::from_tagged_slice(&value_bytes).map_err(StoreError::DataError)?;
+        writer.put(store, key, &value)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate byteorder;
+    extern crate tempfile;
+
+    use self::byteorder::{
+        ByteOrder,
+        LittleEndian,
+    };
+    use self::tempfile::Builder;
+    use std::fs;
+
+    use super::*;
+    use *;
+
+    fn u16_to_u8(src: &[u16]) -> Vec<u8> {
+        let mut dst = vec![0; 2 * src.len()];
+        LittleEndian::write_u16_into(src, &mut dst);
+        dst
+    }
+
+    /// Writes the UTF-8/UTF-16/blob values exercised by `test_blob` and
+    /// `test_iter` into one store, dumps it, loads the dump into a second,
+    /// freshly-created environment, and asserts the two stores hold
+    /// byte-exact-equal entries.
+    #[test]
+    fn test_dump_load_round_trip() {
+        let src_root = Builder::new().prefix("test_dump_load_round_trip_src").tempdir().expect("tempdir");
+        fs::create_dir_all(src_root.path()).expect("dir created");
+        let src = Rkv::new(src_root.path()).expect("new succeeded");
+        let src_store = src.open_or_create("sk").expect("opened");
+
+        let u16_array = [1000, 10000, 54321, 65535];
+        {
+            let mut writer = src.write().expect("writer");
+            writer.put(&src_store, "foo", &Value::I64(1234)).expect("wrote");
+            writer.put(&src_store, "noo", &Value::F64(1234.0.into())).expect("wrote");
+            writer.put(&src_store, "bar", &Value::Bool(true)).expect("wrote");
+            writer.put(&src_store, "baz", &Value::Str("héllo, yöu")).expect("wrote");
+            writer.put(&src_store, "blob", &Value::Blob(&[1, 2, 3, 4])).expect("wrote");
+            writer.put(&src_store, "utf16", &Value::Blob(&u16_to_u8(&u16_array))).expect("wrote");
+            writer.commit().expect("committed");
+        }
+
+        let mut bytes = Vec::new();
+        {
+            let reader = src.read().expect("reader");
+            dump(&reader, &src_store, &mut bytes).expect("dumped");
+        }
+
+        let dest_root = Builder::new().prefix("test_dump_load_round_trip_dest").tempdir().expect("tempdir");
+        fs::create_dir_all(dest_root.path()).expect("dir created");
+        let dest = Rkv::new(dest_root.path()).expect("new succeeded");
+        let dest_store = dest.open_or_create("sk").expect("opened");
+
+        {
+            let mut writer = dest.write().expect("writer");
+            load(&mut writer, &dest_store, &mut bytes.as_slice()).expect("loaded");
+            writer.commit().expect("committed");
+        }
+
+        let src_reader = src.read().expect("reader");
+        let dest_reader = dest.read().expect("reader");
+        let mut src_iter = src_reader.iter_start(&src_store).expect("iter");
+        let mut dest_iter = dest_reader.iter_start(&dest_store).expect("iter");
+        loop {
+            let src_entry = src_iter.next();
+            let dest_entry = dest_iter.next();
+            match (src_entry, dest_entry) {
+                (None, None) => break,
+                (Some((src_key, src_val)), Some((dest_key, dest_val))) => {
+                    assert_eq!(src_key, dest_key);
+                    assert_eq!(src_val.expect("value"), dest_val.expect("value"));
+                },
+                _ => panic!("dumped and loaded stores have different lengths"),
+            }
+        }
+    }
+}
@@ -8,304 +8,344 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-<<<<<<< HEAD
-use std::os::raw::{
-    c_uint,
-};
-=======
 use std::os::raw::c_uint;
->>>>>>> central
 
 use std::path::{
     Path,
     PathBuf,
 };
 
-use lmdb;
-
-use lmdb::{
-    DatabaseFlags,
-    Environment,
-    EnvironmentBuilder,
-<<<<<<< HEAD
-    RoTransaction,
-    RwTransaction,
-};
-
-use error::{
-    StoreError,
-};
-
-use integer::{
-    IntegerStore,
-=======
+use backend::{
+    BackendEnvironment,
+    BackendEnvironmentBuilder,
+    LmdbEnvironment,
+    LmdbEnvironmentBuilder,
+    SafeModeEnvironment,
+    SafeModeEnvironmentBuilder,
 };
 
 use error::StoreError;
 
 use integer::{
+    IntegerMultiStore,
     IntegerReader,
     IntegerStore,
     IntegerWriter,
     Key,
->>>>>>> central
     PrimitiveInt,
 };
 
+use migrator::MigrateError;
+
 use readwrite::{
-<<<<<<< HEAD
-    Store,
-=======
+    MultiStore,
     Reader,
     Store,
     Writer,
->>>>>>> central
 };
 
+/// Governs how `open_single`/`open_multi` treat a store that may or may not
+/// already exist.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StoreOptions {
+    /// If `true`, create the store if it doesn't already exist (as
+    /// `open_or_create*` do); if `false`, fail with
+    /// `StoreError::FileInvalid` if it doesn't (as `open` does).
+    pub create: bool,
+
+    /// If `true`, the store's keys are interpreted as native-endian integers
+    /// (LMDB's `INTEGER_KEY`) rather than arbitrary byte strings.
+    pub integer_key: bool,
+}
+
+impl StoreOptions {
+    /// Shorthand for `StoreOptions { create: true, integer_key: false }`.
+    pub fn create() -> StoreOptions {
+        StoreOptions {
+            create: true,
+            integer_key: false,
+        }
+    }
+}
+
 pub static DEFAULT_MAX_DBS: c_uint = 5;
 
-/// Wrapper around an `lmdb::Environment`.
+/// Wrapper around a `BackendEnvironment`, generic over which storage engine
+/// backs it. Defaults to `LmdbEnvironment` so existing callers that write
+/// `Rkv` (with no type argument) keep compiling unchanged; a caller that
+/// wants a different backend writes `Rkv<SomeOtherEnvironment>` and builds it
+/// via `Rkv::from_builder`/`Rkv::with_capacity_from` instead of `Rkv::new`.
 #[derive(Debug)]
-pub struct Rkv {
+pub struct Rkv<E = LmdbEnvironment> {
     path: PathBuf,
-    env: Environment,
+    env: E,
 }
 
-/// Static methods.
-impl Rkv {
-    pub fn environment_builder() -> EnvironmentBuilder {
-        Environment::new()
-    }
-
-<<<<<<< HEAD
-    /// Return a new Rkv environment from the provided builder.
-    pub fn from_env(env: EnvironmentBuilder, path: &Path) -> Result<Rkv, StoreError> {
-=======
-    /// Return a new Rkv environment that supports up to `DEFAULT_MAX_DBS` open databases.
-    pub fn new(path: &Path) -> Result<Rkv, StoreError> {
-        Rkv::with_capacity(path, DEFAULT_MAX_DBS)
-    }
-
-    /// Return a new Rkv environment from the provided builder.
-    pub fn from_env(path: &Path, env: EnvironmentBuilder) -> Result<Rkv, StoreError> {
->>>>>>> central
+/// Backend-generic construction. Unlike the `LmdbEnvironment`-specific
+/// methods below, these take the builder as a value (or via an explicit
+/// turbofish) since there's no default to fall back on.
+impl<E> Rkv<E> {
+    /// Return a new Rkv environment, opened via the given builder, that
+    /// supports up to `DEFAULT_MAX_DBS` open databases.
+    pub fn from_builder<'env, B>(path: &Path, builder: B) -> Result<Rkv<E>, StoreError>
+    where
+        B: BackendEnvironmentBuilder<'env, Environment = E>,
+    {
         if !path.is_dir() {
             return Err(StoreError::DirectoryDoesNotExistError(path.into()));
         }
 
         Ok(Rkv {
             path: path.into(),
-<<<<<<< HEAD
-            env: env.open(path)
-                    .map_err(|e|
-                        match e {
-                            lmdb::Error::Other(2) => StoreError::DirectoryDoesNotExistError(path.into()),
-                            e => StoreError::LmdbError(e),
-                        })?,
+            env: builder.open(path).map_err(Into::into)?,
         })
     }
 
+    /// Return a new Rkv environment, built from scratch via `B`, that
+    /// supports the specified number of open databases. For control over map
+    /// size, max readers, or environment flags, build and configure `B`
+    /// directly (see `environment_builder`) and use `from_builder` instead.
+    pub fn with_capacity_from<'env, B>(path: &Path, max_dbs: c_uint) -> Result<Rkv<E>, StoreError>
+    where
+        B: BackendEnvironmentBuilder<'env, Environment = E>,
+    {
+        let mut builder = B::new();
+        builder.set_max_dbs(max_dbs);
+        Rkv::from_builder(path, builder)
+    }
+}
+
+/// Static methods for the default (LMDB) backend. These existed before `Rkv`
+/// became backend-generic, so they stay free of any explicit type argument.
+impl Rkv<LmdbEnvironment> {
+    pub fn environment_builder() -> LmdbEnvironmentBuilder {
+        LmdbEnvironmentBuilder::new()
+    }
+
     /// Return a new Rkv environment that supports up to `DEFAULT_MAX_DBS` open databases.
-    pub fn new(path: &Path) -> Result<Rkv, StoreError> {
+    pub fn new(path: &Path) -> Result<Rkv<LmdbEnvironment>, StoreError> {
         Rkv::with_capacity(path, DEFAULT_MAX_DBS)
     }
 
-=======
-            env: env.open(path).map_err(|e| match e {
-                lmdb::Error::Other(2) => StoreError::DirectoryDoesNotExistError(path.into()),
-                e => StoreError::LmdbError(e),
-            })?,
-        })
+    /// Return a new Rkv environment from the provided builder.
+    pub fn from_env(path: &Path, builder: LmdbEnvironmentBuilder) -> Result<Rkv<LmdbEnvironment>, StoreError> {
+        Rkv::from_builder(path, builder)
     }
 
->>>>>>> central
     /// Return a new Rkv environment that supports the specified number of open databases.
-    pub fn with_capacity(path: &Path, max_dbs: c_uint) -> Result<Rkv, StoreError> {
-        if !path.is_dir() {
-            return Err(StoreError::DirectoryDoesNotExistError(path.into()));
-        }
-
-<<<<<<< HEAD
-        let mut builder = Environment::new();
-        builder.set_max_dbs(max_dbs);
+    pub fn with_capacity(path: &Path, max_dbs: c_uint) -> Result<Rkv<LmdbEnvironment>, StoreError> {
+        Rkv::with_capacity_from::<LmdbEnvironmentBuilder>(path, max_dbs)
+    }
 
-        // Future: set flags, maximum size, etc. here if necessary.
-        Rkv::from_env(builder, path)
-=======
-        let mut builder = Rkv::environment_builder();
-        builder.set_max_dbs(max_dbs);
+    /// Migrates the `data.mdb` at `path`, if it was written by a build of a
+    /// different word size than this one, into the host's layout in place,
+    /// leaving the original alongside it as `data.mdb.bak`. Returns the
+    /// number of records moved. See `migrator` for the gory details.
+    pub fn migrate(path: &Path) -> Result<usize, MigrateError> {
+        migrator::migrate_lmdb_data_file(path)
+    }
+}
 
-        // Future: set flags, maximum size, etc. here if necessary.
-        Rkv::from_env(path, builder)
->>>>>>> central
+impl<E> Rkv<E> {
+    /// Migrates the foreign-word-size `data.mdb` at `src_path` into `self`
+    /// via the normal `Writer::put` path, leaving `src_path` untouched.
+    /// Unlike `migrate`, `self` need not be an `LmdbEnvironment` and need not
+    /// live at `src_path`. Returns the number of records moved.
+    pub fn migrate_into<'env>(&'env self, src_path: &Path) -> Result<usize, MigrateError>
+    where
+        E: BackendEnvironment<'env>,
+    {
+        migrator::migrate_lmdb_data_file_into(src_path, self)
     }
 }
 
-/// Store creation methods.
-impl Rkv {
-<<<<<<< HEAD
-    pub fn create_or_open_default(&self) -> Result<Store<&str>, StoreError> {
-        self.create_or_open(None)
+/// Static methods for the pure-Rust "safe mode" backend, mirroring the
+/// `LmdbEnvironment` constructors above so a consumer can pick a backend at
+/// construction time without otherwise touching its call sites.
+impl Rkv<SafeModeEnvironment> {
+    /// Return a new Rkv environment backed by `SafeModeEnvironment`, which
+    /// requires no C library and stores its data as a `serde`/`bincode`
+    /// snapshot rather than an mmap'd file.
+    pub fn new_safe(path: &Path) -> Result<Rkv<SafeModeEnvironment>, StoreError> {
+        Rkv::from_builder(path, SafeModeEnvironmentBuilder::new())
     }
+}
 
-    pub fn create_or_open<'s, T, K>(&self, name: T) -> Result<Store<K>, StoreError>
-    where T: Into<Option<&'s str>>,
-          K: AsRef<[u8]> {
-        let flags = DatabaseFlags::empty();
-        self.create_or_open_with_flags(name, flags)
+/// Store creation methods.
+impl<E> Rkv<E> {
+    /// Opens (or, if `opts.create`, creates) a single-valued store. The
+    /// common entry point `open_or_create`/`open`/`open_or_create_integer`
+    /// all delegate to, now that there's a `StoreOptions` to carry the
+    /// `create`/`integer_key` combination instead of a method per
+    /// combination.
+    pub fn open_single<'env, 's, T>(&'env self, name: T, opts: StoreOptions) -> Result<Store<E::Database>, StoreError>
+    where
+        E: BackendEnvironment<'env>,
+        T: Into<Option<&'s str>>,
+    {
+        let db = if opts.create {
+            self.env.create_db(name.into(), opts.integer_key, false).map_err(Into::into)?
+        } else {
+            self.env.open_db(name.into()).map_err(Into::into)?
+        };
+        Ok(Store::new(db))
     }
 
-    pub fn create_or_open_integer<'s, T, K>(&self, name: T) -> Result<IntegerStore<K>, StoreError>
-    where T: Into<Option<&'s str>>,
-          K: PrimitiveInt {
-        let mut flags = DatabaseFlags::empty();
-        flags.toggle(DatabaseFlags::INTEGER_KEY);
-        let db = self.env.create_db(name.into(), flags)
-                         .map_err(|e| match e {
-                             lmdb::Error::BadRslot => StoreError::open_during_transaction(),
-                             _ => e.into(),
-                         })?;
-        Ok(IntegerStore::new(db))
+    /// Like `open_single`, but the resulting store allows more than one value
+    /// per key (LMDB's `MDB_DUPSORT`). Use `Writer::get_multi`, `put_multi`,
+    /// `delete_all`, and `delete_value` to work with it.
+    pub fn open_multi<'env, 's, T>(&'env self, name: T, opts: StoreOptions) -> Result<MultiStore<E::Database>, StoreError>
+    where
+        E: BackendEnvironment<'env>,
+        T: Into<Option<&'s str>>,
+    {
+        let db = if opts.create {
+            self.env.create_db(name.into(), opts.integer_key, true).map_err(Into::into)?
+        } else {
+            self.env.open_db(name.into()).map_err(Into::into)?
+        };
+        Ok(MultiStore::new(db))
     }
 
-    pub fn create_or_open_with_flags<'s, T, K>(&self, name: T, flags: DatabaseFlags) -> Result<Store<K>, StoreError>
-    where T: Into<Option<&'s str>>,
-          K: AsRef<[u8]> {
-        let db = self.env.create_db(name.into(), flags)
-                         .map_err(|e| match e {
-                             lmdb::Error::BadRslot => StoreError::open_during_transaction(),
-                             _ => e.into(),
-                         })?;
-=======
-    pub fn open_or_create_default(&self) -> Result<Store, StoreError> {
+    pub fn open_or_create_default<'env>(&'env self) -> Result<Store<E::Database>, StoreError>
+    where
+        E: BackendEnvironment<'env>,
+    {
         self.open_or_create(None)
     }
 
-    pub fn open_or_create<'s, T>(&self, name: T) -> Result<Store, StoreError>
+    pub fn open_or_create<'env, 's, T>(&'env self, name: T) -> Result<Store<E::Database>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         T: Into<Option<&'s str>>,
     {
-        let flags = DatabaseFlags::empty();
-        self.open_or_create_with_flags(name, flags)
+        self.open_single(name, StoreOptions::create())
     }
 
-    pub fn open_or_create_integer<'s, T>(&self, name: T) -> Result<IntegerStore, StoreError>
+    pub fn open_or_create_integer<'env, 's, T>(&'env self, name: T) -> Result<IntegerStore<E::Database>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         T: Into<Option<&'s str>>,
     {
-        let mut flags = DatabaseFlags::empty();
-        flags.toggle(DatabaseFlags::INTEGER_KEY);
-        let db = self.env.create_db(name.into(), flags).map_err(|e| match e {
-            lmdb::Error::BadRslot => StoreError::open_during_transaction(),
-            _ => e.into(),
-        })?;
+        let db = self.env.create_db(name.into(), true, false).map_err(Into::into)?;
         Ok(IntegerStore::new(db))
     }
 
-    pub fn open_or_create_with_flags<'s, T>(&self, name: T, flags: DatabaseFlags) -> Result<Store, StoreError>
+    /// Alias for `open_or_create_integer`, named to read well with the key
+    /// type as a turbofish at the call site: `k.open_integer::<u64>("name")`.
+    pub fn open_integer<'env, 's, T, K>(&'env self, name: T) -> Result<IntegerStore<E::Database>, StoreError>
+    where
+        E: BackendEnvironment<'env>,
+        T: Into<Option<&'s str>>,
+        K: PrimitiveInt,
+    {
+        self.open_or_create_integer(name)
+    }
+
+    /// Like `open_or_create`, but the resulting store allows more than one
+    /// value per key (LMDB's `MDB_DUPSORT`). Use `Writer::get_multi`,
+    /// `put_multi`, `delete_all`, and `delete_value` to work with it.
+    pub fn open_or_create_multi<'env, 's, T>(&'env self, name: T) -> Result<MultiStore<E::Database>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         T: Into<Option<&'s str>>,
     {
-        let db = self.env.create_db(name.into(), flags).map_err(|e| match e {
-            lmdb::Error::BadRslot => StoreError::open_during_transaction(),
-            _ => e.into(),
-        })?;
-        Ok(Store::new(db))
+        self.open_multi(name, StoreOptions::create())
+    }
+
+    /// Like `open_or_create_multi`, but with integer keys (LMDB's
+    /// `INTEGER_KEY`), mirroring `open_or_create_integer`'s relationship to
+    /// `open_or_create`.
+    pub fn open_or_create_integer_multi<'env, 's, T>(&'env self, name: T) -> Result<IntegerMultiStore<E::Database>, StoreError>
+    where
+        E: BackendEnvironment<'env>,
+        T: Into<Option<&'s str>>,
+    {
+        let db = self.env.create_db(name.into(), true, true).map_err(Into::into)?;
+        Ok(IntegerMultiStore::new(db))
     }
 
     /// Open an existing database, unlike other `open_or_create_*` functions, it
     /// opens the given database by using a read transaction, which means other
     /// in-flight write transaction will not block this call. This is preferred
     /// to be used in the read_only scenarios.
-    pub fn open<'s, T>(&self, name: T) -> Result<Store, StoreError>
+    pub fn open<'env, 's, T>(&'env self, name: T) -> Result<Store<E::Database>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         T: Into<Option<&'s str>>,
     {
-        let db = self.env.open_db(name.into()).map_err(|e| match e {
-            lmdb::Error::BadRslot => StoreError::open_during_transaction(),
-            _ => e.into(),
-        })?;
->>>>>>> central
-        Ok(Store::new(db))
+        self.open_single(name, StoreOptions::default())
     }
 }
 
 /// Read and write accessors.
-impl Rkv {
-<<<<<<< HEAD
-    pub fn read(&self) -> Result<RoTransaction, lmdb::Error> {
-        self.env.begin_ro_txn()
-    }
-
-    pub fn write(&self) -> Result<RwTransaction, lmdb::Error> {
-        self.env.begin_rw_txn()
-=======
-    pub fn read<K>(&self) -> Result<Reader<K>, StoreError>
+impl<E> Rkv<E> {
+    pub fn read<'env, K>(&'env self) -> Result<Reader<'env, E, K>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         K: AsRef<[u8]>,
     {
-        let txn = self.env.begin_ro_txn()?;
+        let txn = self.env.begin_ro_txn().map_err(Into::into)?;
         Ok(Reader::new(txn))
     }
 
-    pub fn write<K>(&self) -> Result<Writer<K>, StoreError>
+    pub fn write<'env, K>(&'env self) -> Result<Writer<'env, E, K>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         K: AsRef<[u8]>,
     {
-        let txn = self.env.begin_rw_txn()?;
+        let txn = self.env.begin_rw_txn().map_err(Into::into)?;
         Ok(Writer::new(txn))
     }
 
-    pub fn read_int<K>(&self) -> Result<IntegerReader<K>, StoreError>
+    pub fn read_int<'env, K>(&'env self) -> Result<IntegerReader<'env, E, K>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         K: PrimitiveInt,
     {
         let reader = self.read::<Key<K>>()?;
         Ok(IntegerReader::new(reader))
     }
 
-    pub fn write_int<K>(&self) -> Result<IntegerWriter<K>, StoreError>
+    pub fn write_int<'env, K>(&'env self) -> Result<IntegerWriter<'env, E, K>, StoreError>
     where
+        E: BackendEnvironment<'env>,
         K: PrimitiveInt,
     {
         let write = self.write::<Key<K>>()?;
         Ok(IntegerWriter::new(write))
->>>>>>> central
+    }
+
+    /// Flushes any buffered writes to disk. Only useful if the environment
+    /// was opened with `EnvironmentFlags::NO_SYNC`/`MAP_ASYNC` (via
+    /// `environment_builder`/`from_builder`), since otherwise every commit is
+    /// already durable; `force` requests a flush even if those flags would
+    /// otherwise defer it.
+    pub fn sync<'env>(&'env self, force: bool) -> Result<(), StoreError>
+    where
+        E: BackendEnvironment<'env>,
+    {
+        self.env.sync(force).map_err(Into::into)
     }
 }
 
 #[cfg(test)]
 mod tests {
-<<<<<<< HEAD
-    extern crate tempdir;
-    extern crate byteorder;
-=======
     extern crate byteorder;
     extern crate tempfile;
->>>>>>> central
+
+    use lmdb::Database;
 
     use self::byteorder::{
         ByteOrder,
         LittleEndian,
     };
 
-<<<<<<< HEAD
-    use self::tempdir::{
-        TempDir,
-    };
-=======
     use self::tempfile::Builder;
->>>>>>> central
 
     use std::{
         fs,
         str,
-<<<<<<< HEAD
-    };
-
-    use super::*;
-    use ::*;
-=======
         thread,
     };
 
@@ -316,16 +356,11 @@ mod tests {
 
     use super::*;
     use *;
->>>>>>> central
 
     /// We can't open a directory that doesn't exist.
     #[test]
     fn test_open_fails() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_open_fails").expect("tempdir");
-=======
         let root = Builder::new().prefix("test_open_fails").tempdir().expect("tempdir");
->>>>>>> central
         assert!(root.path().exists());
 
         let nope = root.path().join("nope/");
@@ -340,11 +375,6 @@ mod tests {
         };
     }
 
-<<<<<<< HEAD
-    #[test]
-    fn test_open() {
-        let root = TempDir::new("test_open").expect("tempdir");
-=======
     fn check_rkv(k: &Rkv) {
         let _ = k.open_or_create_default().expect("created default");
 
@@ -358,21 +388,11 @@ mod tests {
     #[test]
     fn test_open() {
         let root = Builder::new().prefix("test_open").tempdir().expect("tempdir");
->>>>>>> central
         println!("Root path: {:?}", root.path());
         fs::create_dir_all(root.path()).expect("dir created");
         assert!(root.path().is_dir());
 
         let k = Rkv::new(root.path()).expect("new succeeded");
-<<<<<<< HEAD
-        let _ = k.create_or_open_default().expect("created default");
-
-        let yyy: Store<&str> = k.create_or_open("yyy").expect("opened");
-        let reader = yyy.read(&k).expect("reader");
-
-        let result = reader.get("foo");
-        assert_eq!(None, result.expect("success but no value"));
-=======
 
         check_rkv(&k);
     }
@@ -409,40 +429,15 @@ mod tests {
         // This should really return an error rather than panicking, per
         // <https://github.com/mozilla/lmdb-rs/issues/6>.
         let _zzz = k.open_or_create("zzz").expect("opened");
->>>>>>> central
     }
 
     #[test]
     fn test_round_trip_and_transactions() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_round_trip_and_transactions").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-
-        let sk: Store<&str> = k.create_or_open("sk").expect("opened");
-
-        {
-            let mut writer = sk.write(&k).expect("writer");
-            writer.put("foo", &Value::I64(1234)).expect("wrote");
-            writer.put("noo", &Value::F64(1234.0.into())).expect("wrote");
-            writer.put("bar", &Value::Bool(true)).expect("wrote");
-            writer.put("baz", &Value::Str("héllo, yöu")).expect("wrote");
-            assert_eq!(writer.get("foo").expect("read"), Some(Value::I64(1234)));
-            assert_eq!(writer.get("noo").expect("read"), Some(Value::F64(1234.0.into())));
-            assert_eq!(writer.get("bar").expect("read"), Some(Value::Bool(true)));
-            assert_eq!(writer.get("baz").expect("read"), Some(Value::Str("héllo, yöu")));
-
-            // Isolation. Reads won't return values.
-            let r = &k.read().unwrap();
-            assert_eq!(sk.get(r, "foo").expect("read"), None);
-            assert_eq!(sk.get(r, "bar").expect("read"), None);
-            assert_eq!(sk.get(r, "baz").expect("read"), None);
-=======
         let root = Builder::new().prefix("test_round_trip_and_transactions").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
 
-        let sk: Store = k.open_or_create("sk").expect("opened");
+        let sk = k.open_or_create("sk").expect("opened");
 
         {
             let mut writer = k.write().expect("writer");
@@ -460,28 +455,12 @@ mod tests {
             assert_eq!(r.get(&sk, "foo").expect("read"), None);
             assert_eq!(r.get(&sk, "bar").expect("read"), None);
             assert_eq!(r.get(&sk, "baz").expect("read"), None);
->>>>>>> central
         }
 
         // Dropped: tx rollback. Reads will still return nothing.
 
         {
             let r = &k.read().unwrap();
-<<<<<<< HEAD
-            assert_eq!(sk.get(r, "foo").expect("read"), None);
-            assert_eq!(sk.get(r, "bar").expect("read"), None);
-            assert_eq!(sk.get(r, "baz").expect("read"), None);
-        }
-
-        {
-            let mut writer = sk.write(&k).expect("writer");
-            writer.put("foo", &Value::I64(1234)).expect("wrote");
-            writer.put("bar", &Value::Bool(true)).expect("wrote");
-            writer.put("baz", &Value::Str("héllo, yöu")).expect("wrote");
-            assert_eq!(writer.get("foo").expect("read"), Some(Value::I64(1234)));
-            assert_eq!(writer.get("bar").expect("read"), Some(Value::Bool(true)));
-            assert_eq!(writer.get("baz").expect("read"), Some(Value::Str("héllo, yöu")));
-=======
             assert_eq!(r.get(&sk, "foo").expect("read"), None);
             assert_eq!(r.get(&sk, "bar").expect("read"), None);
             assert_eq!(r.get(&sk, "baz").expect("read"), None);
@@ -495,35 +474,12 @@ mod tests {
             assert_eq!(writer.get(&sk, "foo").expect("read"), Some(Value::I64(1234)));
             assert_eq!(writer.get(&sk, "bar").expect("read"), Some(Value::Bool(true)));
             assert_eq!(writer.get(&sk, "baz").expect("read"), Some(Value::Str("héllo, yöu")));
->>>>>>> central
 
             writer.commit().expect("committed");
         }
 
         // Committed. Reads will succeed.
         {
-<<<<<<< HEAD
-            let r = &k.read().unwrap();
-            assert_eq!(sk.get(r, "foo").expect("read"), Some(Value::I64(1234)));
-            assert_eq!(sk.get(r, "bar").expect("read"), Some(Value::Bool(true)));
-            assert_eq!(sk.get(r, "baz").expect("read"), Some(Value::Str("héllo, yöu")));
-        }
-
-        {
-            let mut writer = sk.write(&k).expect("writer");
-            writer.delete("foo").expect("deleted");
-            writer.delete("bar").expect("deleted");
-            writer.delete("baz").expect("deleted");
-            assert_eq!(writer.get("foo").expect("read"), None);
-            assert_eq!(writer.get("bar").expect("read"), None);
-            assert_eq!(writer.get("baz").expect("read"), None);
-
-            // Isolation. Reads still return values.
-            let r = &k.read().unwrap();
-            assert_eq!(sk.get(r, "foo").expect("read"), Some(Value::I64(1234)));
-            assert_eq!(sk.get(r, "bar").expect("read"), Some(Value::Bool(true)));
-            assert_eq!(sk.get(r, "baz").expect("read"), Some(Value::Str("héllo, yöu")));
-=======
             let r = k.read().unwrap();
             assert_eq!(r.get(&sk, "foo").expect("read"), Some(Value::I64(1234)));
             assert_eq!(r.get(&sk, "bar").expect("read"), Some(Value::Bool(true)));
@@ -544,28 +500,11 @@ mod tests {
             assert_eq!(r.get(&sk, "foo").expect("read"), Some(Value::I64(1234)));
             assert_eq!(r.get(&sk, "bar").expect("read"), Some(Value::Bool(true)));
             assert_eq!(r.get(&sk, "baz").expect("read"), Some(Value::Str("héllo, yöu")));
->>>>>>> central
         }
 
         // Dropped: tx rollback. Reads will still return values.
 
         {
-<<<<<<< HEAD
-            let r = &k.read().unwrap();
-            assert_eq!(sk.get(r, "foo").expect("read"), Some(Value::I64(1234)));
-            assert_eq!(sk.get(r, "bar").expect("read"), Some(Value::Bool(true)));
-            assert_eq!(sk.get(r, "baz").expect("read"), Some(Value::Str("héllo, yöu")));
-        }
-
-        {
-            let mut writer = sk.write(&k).expect("writer");
-            writer.delete("foo").expect("deleted");
-            writer.delete("bar").expect("deleted");
-            writer.delete("baz").expect("deleted");
-            assert_eq!(writer.get("foo").expect("read"), None);
-            assert_eq!(writer.get("bar").expect("read"), None);
-            assert_eq!(writer.get("baz").expect("read"), None);
-=======
             let r = k.read().unwrap();
             assert_eq!(r.get(&sk, "foo").expect("read"), Some(Value::I64(1234)));
             assert_eq!(r.get(&sk, "bar").expect("read"), Some(Value::Bool(true)));
@@ -580,19 +519,12 @@ mod tests {
             assert_eq!(writer.get(&sk, "foo").expect("read"), None);
             assert_eq!(writer.get(&sk, "bar").expect("read"), None);
             assert_eq!(writer.get(&sk, "baz").expect("read"), None);
->>>>>>> central
 
             writer.commit().expect("committed");
         }
 
         // Committed. Reads will succeed but return None to indicate a missing value.
         {
-<<<<<<< HEAD
-            let r = &k.read().unwrap();
-            assert_eq!(sk.get(r, "foo").expect("read"), None);
-            assert_eq!(sk.get(r, "bar").expect("read"), None);
-            assert_eq!(sk.get(r, "baz").expect("read"), None);
-=======
             let r = k.read().unwrap();
             assert_eq!(r.get(&sk, "foo").expect("read"), None);
             assert_eq!(r.get(&sk, "bar").expect("read"), None);
@@ -638,120 +570,72 @@ mod tests {
         // Open a reader on this store
         let _reader = k.read::<&str>().expect("reader");
         // Open the same store for read while the reader is in progress will panic
-        let store: Result<Store, StoreError> = k.open("sk");
+        let store: Result<Store<Database>, StoreError> = k.open("sk");
         match store {
             Err(StoreError::OpenAttemptedDuringTransaction(_thread_id)) => assert!(true),
             _ => panic!("should panic"),
->>>>>>> central
         }
     }
 
     #[test]
     fn test_read_before_write_num() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_read_before_write_num").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store<&str> = k.create_or_open("sk").expect("opened");
-=======
         let root = Builder::new().prefix("test_read_before_write_num").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store = k.open_or_create("sk").expect("opened");
->>>>>>> central
+        let sk = k.open_or_create("sk").expect("opened");
 
         // Test reading a number, modifying it, and then writing it back.
         // We have to be done with the Value::I64 before calling Writer::put,
         // as the Value::I64 borrows an immutable reference to the Writer.
         // So we extract and copy its primitive value.
 
-<<<<<<< HEAD
-        fn get_existing_foo(writer: &Writer<&str>) -> Option<i64> {
-            match writer.get("foo").expect("read") {
-=======
-        fn get_existing_foo(writer: &Writer<&str>, store: &Store) -> Option<i64> {
+        fn get_existing_foo(writer: &Writer<LmdbEnvironment, &str>, store: &Store<Database>) -> Option<i64> {
             match writer.get(store, "foo").expect("read") {
->>>>>>> central
                 Some(Value::I64(val)) => Some(val),
                 _ => None,
             }
         }
 
-<<<<<<< HEAD
-        let mut writer = sk.write(&k).expect("writer");
-        let mut existing = get_existing_foo(&writer).unwrap_or(99);
-        existing += 1;
-        writer.put("foo", &Value::I64(existing)).expect("success");
-
-        let updated = get_existing_foo(&writer).unwrap_or(99);
-=======
         let mut writer = k.write().expect("writer");
         let mut existing = get_existing_foo(&writer, &sk).unwrap_or(99);
         existing += 1;
         writer.put(&sk, "foo", &Value::I64(existing)).expect("success");
 
         let updated = get_existing_foo(&writer, &sk).unwrap_or(99);
->>>>>>> central
         assert_eq!(updated, 100);
         writer.commit().expect("commit");
     }
 
     #[test]
     fn test_read_before_write_str() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_read_before_write_str").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store<&str> = k.create_or_open("sk").expect("opened");
-=======
         let root = Builder::new().prefix("test_read_before_write_str").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store = k.open_or_create("sk").expect("opened");
->>>>>>> central
+        let sk = k.open_or_create("sk").expect("opened");
 
         // Test reading a string, modifying it, and then writing it back.
         // We have to be done with the Value::Str before calling Writer::put,
         // as the Value::Str (and its underlying &str) borrows an immutable
         // reference to the Writer.  So we copy it to a String.
 
-<<<<<<< HEAD
-        let mut writer = sk.write(&k).expect("writer");
-        let mut existing = match writer.get("foo").expect("read") {
-=======
         let mut writer = k.write().expect("writer");
         let mut existing = match writer.get(&sk, "foo").expect("read") {
->>>>>>> central
             Some(Value::Str(val)) => val,
             _ => "",
         }.to_string();
         existing.push('…');
-<<<<<<< HEAD
-        writer.put("foo", &Value::Str(&existing)).expect("write");
-=======
         writer.put(&sk, "foo", &Value::Str(&existing)).expect("write");
->>>>>>> central
         writer.commit().expect("commit");
     }
 
     #[test]
     fn test_concurrent_read_transactions_prohibited() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_concurrent_reads_prohibited").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let s: Store<&str> = k.create_or_open("s").expect("opened");
-
-        let _first = s.read(&k).expect("reader");
-        let second = s.read(&k);
-=======
         let root = Builder::new().prefix("test_concurrent_reads_prohibited").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
 
         let _first = k.read::<&str>().expect("reader");
         let second = k.read::<&str>();
->>>>>>> central
 
         match second {
             Err(StoreError::ReadTransactionAlreadyExists(t)) => {
@@ -765,49 +649,10 @@ mod tests {
 
     #[test]
     fn test_isolation() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_isolation").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let s: Store<&str> = k.create_or_open("s").expect("opened");
-
-        // Add one field.
-        {
-            let mut writer = s.write(&k).expect("writer");
-            writer.put("foo", &Value::I64(1234)).expect("wrote");
-            writer.commit().expect("committed");
-        }
-
-        // Both ways of reading see the value.
-        {
-            let reader = &k.read().unwrap();
-            assert_eq!(s.get(reader, "foo").expect("read"), Some(Value::I64(1234)));
-        }
-        {
-            let reader = s.read(&k).unwrap();
-            assert_eq!(reader.get("foo").expect("read"), Some(Value::I64(1234)));
-        }
-
-        // Establish a long-lived reader that outlasts a writer.
-        let reader = s.read(&k).expect("reader");
-        assert_eq!(reader.get("foo").expect("read"), Some(Value::I64(1234)));
-
-        // Start a write transaction.
-        let mut writer = s.write(&k).expect("writer");
-        writer.put("foo", &Value::I64(999)).expect("wrote");
-
-        // The reader and writer are isolated.
-        assert_eq!(reader.get("foo").expect("read"), Some(Value::I64(1234)));
-        assert_eq!(writer.get("foo").expect("read"), Some(Value::I64(999)));
-
-        // If we commit the writer, we still have isolation.
-        writer.commit().expect("committed");
-        assert_eq!(reader.get("foo").expect("read"), Some(Value::I64(1234)));
-=======
         let root = Builder::new().prefix("test_isolation").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
-        let s: Store = k.open_or_create("s").expect("opened");
+        let s = k.open_or_create("s").expect("opened");
 
         // Add one field.
         {
@@ -836,43 +681,25 @@ mod tests {
         // If we commit the writer, we still have isolation.
         writer.commit().expect("committed");
         assert_eq!(reader.get(&s, "foo").expect("read"), Some(Value::I64(1234)));
->>>>>>> central
 
         // A new reader sees the committed value. Note that LMDB doesn't allow two
         // read transactions to exist in the same thread, so we abort the previous one.
         reader.abort();
-<<<<<<< HEAD
-        let reader = s.read(&k).expect("reader");
-        assert_eq!(reader.get("foo").expect("read"), Some(Value::I64(999)));
-=======
         let reader = k.read().expect("reader");
         assert_eq!(reader.get(&s, "foo").expect("read"), Some(Value::I64(999)));
->>>>>>> central
     }
 
     #[test]
     fn test_blob() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_round_trip_blob").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store<&str> = k.create_or_open("sk").expect("opened");
-        let mut writer = sk.write(&k).expect("writer");
-
-        assert_eq!(writer.get("foo").expect("read"), None);
-        writer.put("foo", &Value::Blob(&[1, 2, 3, 4])).expect("wrote");
-        assert_eq!(writer.get("foo").expect("read"), Some(Value::Blob(&[1, 2, 3, 4])));
-=======
         let root = Builder::new().prefix("test_round_trip_blob").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store = k.open_or_create("sk").expect("opened");
+        let sk = k.open_or_create("sk").expect("opened");
         let mut writer = k.write().expect("writer");
 
         assert_eq!(writer.get(&sk, "foo").expect("read"), None);
         writer.put(&sk, "foo", &Value::Blob(&[1, 2, 3, 4])).expect("wrote");
         assert_eq!(writer.get(&sk, "foo").expect("read"), Some(Value::Blob(&[1, 2, 3, 4])));
->>>>>>> central
 
         fn u16_to_u8(src: &[u16]) -> Vec<u8> {
             let mut dst = vec![0; 2 * src.len()];
@@ -890,80 +717,21 @@ mod tests {
         // their [u16] backing storage to [u8].  Test that converting, writing,
         // reading, and converting back works as expected.
         let u16_array = [1000, 10000, 54321, 65535];
-<<<<<<< HEAD
-        assert_eq!(writer.get("bar").expect("read"), None);
-        writer.put("bar", &Value::Blob(&u16_to_u8(&u16_array))).expect("wrote");
-        let u8_array = match writer.get("bar").expect("read") {
-=======
         assert_eq!(writer.get(&sk, "bar").expect("read"), None);
         writer.put(&sk, "bar", &Value::Blob(&u16_to_u8(&u16_array))).expect("wrote");
         let u8_array = match writer.get(&sk, "bar").expect("read") {
->>>>>>> central
             Some(Value::Blob(val)) => val,
             _ => &[],
         };
         assert_eq!(u8_to_u16(u8_array), u16_array);
     }
 
-    #[test]
-    #[should_panic(expected = "not yet implemented")]
-    fn test_delete_value() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_delete_value").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store<&str> = k.create_or_open_with_flags("sk", DatabaseFlags::DUP_SORT).expect("opened");
-
-        let mut writer = sk.write(&k).expect("writer");
-        writer.put("foo", &Value::I64(1234)).expect("wrote");
-        writer.put("foo", &Value::I64(1235)).expect("wrote");
-        writer.delete_value("foo", &Value::I64(1234)).expect("deleted");
-=======
-        let root = Builder::new().prefix("test_delete_value").tempdir().expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store = k.open_or_create_with_flags("sk", DatabaseFlags::DUP_SORT).expect("opened");
-
-        let mut writer = k.write().expect("writer");
-        writer.put(&sk, "foo", &Value::I64(1234)).expect("wrote");
-        writer.put(&sk, "foo", &Value::I64(1235)).expect("wrote");
-        writer.delete_value(&sk, "foo", &Value::I64(1234)).expect("deleted");
->>>>>>> central
-    }
-
     #[test]
     fn test_iter() {
-<<<<<<< HEAD
-        let root = TempDir::new("test_iter").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store<&str> = k.create_or_open("sk").expect("opened");
-
-        // An iterator over an empty store returns no values.
-        {
-            let reader = sk.read(&k).unwrap();
-            let mut iter = reader.iter_start().unwrap();
-            assert!(iter.next().is_none());
-        }
-
-        let mut writer = sk.write(&k).expect("writer");
-        writer.put("foo", &Value::I64(1234)).expect("wrote");
-        writer.put("noo", &Value::F64(1234.0.into())).expect("wrote");
-        writer.put("bar", &Value::Bool(true)).expect("wrote");
-        writer.put("baz", &Value::Str("héllo, yöu")).expect("wrote");
-        writer.put("héllò, töűrîst", &Value::Str("Emil.RuleZ!")).expect("wrote");
-        writer.put("你好，遊客", &Value::Str("米克規則")).expect("wrote");
-        writer.commit().expect("committed");
-
-        let reader = sk.read(&k).unwrap();
-
-        // Reader.iter() returns (key, value) tuples ordered by key.
-        let mut iter = reader.iter_start().unwrap();
-=======
         let root = Builder::new().prefix("test_iter").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store = k.open_or_create("sk").expect("opened");
+        let sk = k.open_or_create("sk").expect("opened");
 
         // An iterator over an empty store returns no values.
         {
@@ -985,7 +753,6 @@ mod tests {
 
         // Reader.iter() returns (key, value) tuples ordered by key.
         let mut iter = reader.iter_start(&sk).unwrap();
->>>>>>> central
         let (key, val) = iter.next().unwrap();
         assert_eq!(str::from_utf8(key).expect("key"), "bar");
         assert_eq!(val.expect("value"), Some(Value::Bool(true)));
@@ -1012,11 +779,7 @@ mod tests {
 
         // Reader.iter_from() begins iteration at the first key equal to
         // or greater than the given key.
-<<<<<<< HEAD
-        let mut iter = reader.iter_from("moo").unwrap();
-=======
         let mut iter = reader.iter_from(&sk, "moo").unwrap();
->>>>>>> central
         let (key, val) = iter.next().unwrap();
         assert_eq!(str::from_utf8(key).expect("key"), "noo");
         assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
@@ -1027,11 +790,7 @@ mod tests {
 
         // Reader.iter_from() works as expected when the given key is a prefix
         // of a key in the store.
-<<<<<<< HEAD
-        let mut iter = reader.iter_from("no").unwrap();
-=======
         let mut iter = reader.iter_from(&sk, "no").unwrap();
->>>>>>> central
         let (key, val) = iter.next().unwrap();
         assert_eq!(str::from_utf8(key).expect("key"), "noo");
         assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
@@ -1042,37 +801,11 @@ mod tests {
     }
 
     #[test]
-<<<<<<< HEAD
-    #[should_panic(expected = "called `Result::unwrap()` on an `Err` value: NotFound")]
-    fn test_iter_from_key_greater_than_existing() {
-        let root = TempDir::new("test_iter_from_key_greater_than_existing").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store<&str> = k.create_or_open("sk").expect("opened");
-
-        let mut writer = sk.write(&k).expect("writer");
-        writer.put("foo", &Value::I64(1234)).expect("wrote");
-        writer.put("noo", &Value::F64(1234.0.into())).expect("wrote");
-        writer.put("bar", &Value::Bool(true)).expect("wrote");
-        writer.put("baz", &Value::Str("héllo, yöu")).expect("wrote");
-        writer.commit().expect("committed");
-
-        let reader = sk.read(&k).unwrap();
-
-        // There is no key greater than "nuu", so the underlying LMDB API panics
-        // when calling iter_from.  This is unfortunate, and I've requested
-        // https://github.com/danburkert/lmdb-rs/pull/29 to make the underlying
-        // API return a Result instead.
-        //
-        // Also see alternative https://github.com/danburkert/lmdb-rs/pull/30.
-        //
-        reader.iter_from("nuu").unwrap();
-=======
     fn test_iter_from_key_greater_than_existing() {
         let root = Builder::new().prefix("test_iter_from_key_greater_than_existing").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
-        let sk: Store = k.open_or_create("sk").expect("opened");
+        let sk = k.open_or_create("sk").expect("opened");
 
         let mut writer = k.write().expect("writer");
         writer.put(&sk, "foo", &Value::I64(1234)).expect("wrote");
@@ -1086,15 +819,126 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    /// `iter_from` must behave the same on the `SafeMode` backend as on
+    /// LMDB: keys ordered lexicographically by raw bytes, positioned at the
+    /// first key `>= prefix`, including multibyte UTF-8 keys.
+    #[test]
+    fn test_safe_mode_iter_from_matches_lmdb_ordering() {
+        let root = Builder::new().prefix("test_safe_mode_iter_from_matches_lmdb_ordering").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+        let k = Rkv::new_safe(root.path()).expect("new succeeded");
+        let sk = k.open_or_create("sk").expect("opened");
+
+        let mut writer = k.write().expect("writer");
+        writer.put(&sk, "héllò, töűrîst", &Value::Str("Emil.RuleZ!")).expect("wrote");
+        writer.put(&sk, "你好，遊客", &Value::Str("米克規則")).expect("wrote");
+        writer.put(&sk, "foo", &Value::I64(1234)).expect("wrote");
+        writer.commit().expect("committed");
+
+        let reader = k.read().unwrap();
+        let mut iter = reader.iter_from(&sk, "h").unwrap();
+        let (key, val) = iter.next().unwrap();
+        assert_eq!(str::from_utf8(key).expect("key"), "héllò, töűrîst");
+        assert_eq!(val.expect("value"), Some(Value::Str("Emil.RuleZ!")));
+        let (key, val) = iter.next().unwrap();
+        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
+        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_get_or_put_with() {
+        let root = Builder::new().prefix("test_get_or_put_with").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+        let k = Rkv::new(root.path()).expect("new succeeded");
+        let sk = k.open_or_create("sk").expect("opened");
+
+        let mut writer = k.write().expect("writer");
+        let mut calls = 0;
+        let v = writer
+            .get_or_put_with(&sk, "foo", || {
+                calls += 1;
+                Value::I64(1234)
+            })
+            .expect("inserted");
+        assert_eq!(v, Value::I64(1234));
+        assert_eq!(calls, 1);
+
+        // A second call with a different closure sees the existing value and
+        // doesn't invoke it.
+        let v = writer
+            .get_or_put_with(&sk, "foo", || {
+                calls += 1;
+                Value::I64(9999)
+            })
+            .expect("read existing");
+        assert_eq!(v, Value::I64(1234));
+        assert_eq!(calls, 1);
+        writer.commit().expect("committed");
+
+        let reader = k.read().expect("reader");
+        assert_eq!(reader.get(&sk, "foo").expect("read"), Some(Value::I64(1234)));
+    }
+
+    #[test]
+    fn test_get_or_try_put_with_failure_leaves_store_unchanged() {
+        let root = Builder::new().prefix("test_get_or_try_put_with_failure").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+        let k = Rkv::new(root.path()).expect("new succeeded");
+        let sk = k.open_or_create("sk").expect("opened");
+
+        let mut writer = k.write().expect("writer");
+        let result = writer.get_or_try_put_with(&sk, "foo", || Err(StoreError::KeyExistsError));
+        assert!(result.is_err());
+        assert_eq!(writer.get(&sk, "foo").expect("read"), None);
+    }
+
+    /// Like `test_iter`, but for a `MultiStore`: several values under the
+    /// same key should iterate in value order, and `delete_value` should
+    /// remove exactly one of them, leaving the others untouched.
+    #[test]
+    fn test_multi_iter_and_delete_value() {
+        let root = Builder::new().prefix("test_multi_iter_and_delete_value").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+        let k = Rkv::new(root.path()).expect("new succeeded");
+        let sk = k.open_or_create_multi("sk").expect("opened");
+
+        let mut writer = k.write().expect("writer");
+        writer.put_multi(&sk, "foo", &Value::I64(1234)).expect("wrote");
+        writer.put_multi(&sk, "foo", &Value::I64(123)).expect("wrote");
+        writer.put_multi(&sk, "foo", &Value::I64(12345)).expect("wrote");
+        writer.commit().expect("committed");
+
+        let reader = k.read().unwrap();
+        let mut iter = reader.get_multi(&sk, "foo").unwrap();
+        assert_eq!(iter.next().unwrap().1.expect("value"), Some(Value::I64(123)));
+        assert_eq!(iter.next().unwrap().1.expect("value"), Some(Value::I64(1234)));
+        assert_eq!(iter.next().unwrap().1.expect("value"), Some(Value::I64(12345)));
+        assert!(iter.next().is_none());
+
+        assert_eq!(reader.get_first(&sk, "foo").expect("read"), Some(Value::I64(123)));
+        reader.abort();
+
+        let mut writer = k.write().expect("writer");
+        writer.delete_value(&sk, "foo", &Value::I64(1234)).expect("deleted");
+        writer.commit().expect("committed");
+
+        let reader = k.read().unwrap();
+        let mut iter = reader.get_multi(&sk, "foo").unwrap();
+        assert_eq!(iter.next().unwrap().1.expect("value"), Some(Value::I64(123)));
+        assert_eq!(iter.next().unwrap().1.expect("value"), Some(Value::I64(12345)));
+        assert!(iter.next().is_none());
+    }
+
     #[test]
     fn test_multiple_store_read_write() {
         let root = Builder::new().prefix("test_multiple_store_read_write").tempdir().expect("tempdir");
         fs::create_dir_all(root.path()).expect("dir created");
         let k = Rkv::new(root.path()).expect("new succeeded");
 
-        let s1: Store = k.open_or_create("store_1").expect("opened");
-        let s2: Store = k.open_or_create("store_2").expect("opened");
-        let s3: Store = k.open_or_create("store_3").expect("opened");
+        let s1 = k.open_or_create("store_1").expect("opened");
+        let s2 = k.open_or_create("store_2").expect("opened");
+        let s3 = k.open_or_create("store_3").expect("opened");
 
         let mut writer = k.write().expect("writer");
         writer.put(&s1, "foo", &Value::Str("bar")).expect("wrote");
@@ -1126,118 +970,6 @@ mod tests {
         assert_eq!(reader.get(&s3, "key").expect("value"), None);
     }
 
-    #[test]
-    fn test_multiple_store_iter() {
-        let root = Builder::new().prefix("test_multiple_store_iter").tempdir().expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let s1: Store = k.open_or_create("store_1").expect("opened");
-        let s2: Store = k.open_or_create("store_2").expect("opened");
-
-        let mut writer = k.write().expect("writer");
-        // Write to "s1"
-        writer.put(&s1, "foo", &Value::I64(1234)).expect("wrote");
-        writer.put(&s1, "noo", &Value::F64(1234.0.into())).expect("wrote");
-        writer.put(&s1, "bar", &Value::Bool(true)).expect("wrote");
-        writer.put(&s1, "baz", &Value::Str("héllo, yöu")).expect("wrote");
-        writer.put(&s1, "héllò, töűrîst", &Value::Str("Emil.RuleZ!")).expect("wrote");
-        writer.put(&s1, "你好，遊客", &Value::Str("米克規則")).expect("wrote");
-        // Writer to "s2"
-        writer.put(&s2, "foo", &Value::I64(1234)).expect("wrote");
-        writer.put(&s2, "noo", &Value::F64(1234.0.into())).expect("wrote");
-        writer.put(&s2, "bar", &Value::Bool(true)).expect("wrote");
-        writer.put(&s2, "baz", &Value::Str("héllo, yöu")).expect("wrote");
-        writer.put(&s2, "héllò, töűrîst", &Value::Str("Emil.RuleZ!")).expect("wrote");
-        writer.put(&s2, "你好，遊客", &Value::Str("米克規則")).expect("wrote");
-        writer.commit().expect("committed");
-
-        let reader = k.read().unwrap();
-
-        // Iterate through the whole store in "s1"
-        let mut iter = reader.iter_start(&s1).unwrap();
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "bar");
-        assert_eq!(val.expect("value"), Some(Value::Bool(true)));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "baz");
-        assert_eq!(val.expect("value"), Some(Value::Str("héllo, yöu")));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "foo");
-        assert_eq!(val.expect("value"), Some(Value::I64(1234)));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "héllò, töűrîst");
-        assert_eq!(val.expect("value"), Some(Value::Str("Emil.RuleZ!")));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "noo");
-        assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
-        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
-        assert!(iter.next().is_none());
-
-        // Iterate through the whole store in "s2"
-        let mut iter = reader.iter_start(&s2).unwrap();
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "bar");
-        assert_eq!(val.expect("value"), Some(Value::Bool(true)));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "baz");
-        assert_eq!(val.expect("value"), Some(Value::Str("héllo, yöu")));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "foo");
-        assert_eq!(val.expect("value"), Some(Value::I64(1234)));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "héllò, töűrîst");
-        assert_eq!(val.expect("value"), Some(Value::Str("Emil.RuleZ!")));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "noo");
-        assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
-        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
-        assert!(iter.next().is_none());
-
-        // Iterate from a given key in "s1"
-        let mut iter = reader.iter_from(&s1, "moo").unwrap();
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "noo");
-        assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
-        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
-        assert!(iter.next().is_none());
-
-        // Iterate from a given key in "s2"
-        let mut iter = reader.iter_from(&s2, "moo").unwrap();
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "noo");
-        assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
-        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
-        assert!(iter.next().is_none());
-
-        // Iterate from a given prefix in "s1"
-        let mut iter = reader.iter_from(&s1, "no").unwrap();
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "noo");
-        assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
-        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
-        assert!(iter.next().is_none());
-
-        // Iterate from a given prefix in "s2"
-        let mut iter = reader.iter_from(&s2, "no").unwrap();
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "noo");
-        assert_eq!(val.expect("value"), Some(Value::F64(1234.0.into())));
-        let (key, val) = iter.next().unwrap();
-        assert_eq!(str::from_utf8(key).expect("key"), "你好，遊客");
-        assert_eq!(val.expect("value"), Some(Value::Str("米克規則")));
-        assert!(iter.next().is_none());
-    }
-
     #[test]
     fn test_store_multiple_thread() {
         let root = Builder::new().prefix("test_multiple_thread").tempdir().expect("tempdir");
@@ -1290,6 +1022,34 @@ mod tests {
         // equal to the sum of values written to the threads.
         let thread_sum: u64 = read_handles.into_iter().map(|handle| handle.join().expect("value")).sum();
         assert_eq!(thread_sum, (0..num_threads).sum());
->>>>>>> central
+    }
+
+    /// `put_many`/`get_many` replace the per-key thread spawning above with a
+    /// single write transaction and a rayon-parallel read, for the same
+    /// "many independent KV pairs" workload.
+    #[test]
+    fn test_put_many_and_get_many() {
+        let root = Builder::new().prefix("test_put_many_and_get_many").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+        let k = Rkv::new(root.path()).expect("new succeeded");
+        let s = k.open_or_create("s").expect("opened");
+
+        let num_keys = 100u64;
+        let keys: Vec<String> = (0..num_keys).map(|i| i.to_string()).collect();
+
+        let mut writer = k.write().expect("writer");
+        writer.put_many(&s, keys.iter().map(|key| (key.as_str(), Value::U64(key.parse().unwrap())))).expect("wrote");
+        writer.commit().expect("committed");
+
+        let reader = k.read().expect("reader");
+        let values = reader.get_many(&s, keys.iter().map(String::as_str));
+        let sum: u64 = values
+            .into_iter()
+            .map(|v| match v.expect("read") {
+                Some(Value::U64(value)) => value,
+                _ => panic!("value not found or unexpected type"),
+            })
+            .sum();
+        assert_eq!(sum, (0..num_keys).sum());
     }
 }
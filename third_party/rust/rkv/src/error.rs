@@ -0,0 +1,97 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::io;
+use std::path::PathBuf;
+use std::thread::ThreadId;
+
+use bincode::Error as BincodeError;
+use failure::Fail;
+
+#[derive(Debug, Fail)]
+pub enum DataError {
+    #[fail(display = "unknown type tag: {}", value)]
+    UnknownType { value: u8 },
+
+    #[fail(display = "unexpected type tag: expected {}, got {}", expected, actual)]
+    UnexpectedType { expected: String, actual: String },
+
+    #[fail(display = "empty data; expected tagged data")]
+    Empty,
+
+    #[fail(display = "invalid value: {:?}", _0)]
+    InvalidValue(String),
+
+    #[fail(display = "couldn't encode/decode value: {}", _0)]
+    DecodingError(#[cause] BincodeError),
+}
+
+impl From<BincodeError> for DataError {
+    fn from(e: BincodeError) -> DataError {
+        DataError::DecodingError(e)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum StoreError {
+    #[fail(display = "directory does not exist: {:?}", _0)]
+    DirectoryDoesNotExistError(PathBuf),
+
+    #[fail(display = "data error: {:?}", _0)]
+    DataError(#[cause] DataError),
+
+    #[fail(display = "lmdb error: {}", _0)]
+    LmdbError(#[cause] lmdb::Error),
+
+    #[fail(display = "I/O error: {}", _0)]
+    IoError(#[cause] io::Error),
+
+    #[fail(display = "read transaction already exists for thread {:?}", _0)]
+    ReadTransactionAlreadyExists(ThreadId),
+
+    #[fail(display = "opening a store while a transaction is in progress on thread {:?}", _0)]
+    OpenAttemptedDuringTransaction(ThreadId),
+
+    #[fail(display = "the file backing this store's data is corrupt: {}", _0)]
+    FileInvalid(String),
+
+    #[fail(display = "data corrupted: {}", _0)]
+    DataCorrupted(String),
+
+    #[fail(display = "key already exists")]
+    KeyExistsError,
+}
+
+impl StoreError {
+    pub fn open_during_transaction() -> StoreError {
+        StoreError::OpenAttemptedDuringTransaction(std::thread::current().id())
+    }
+}
+
+impl From<lmdb::Error> for StoreError {
+    fn from(e: lmdb::Error) -> StoreError {
+        match e {
+            lmdb::Error::BadRslot => StoreError::open_during_transaction(),
+            e => StoreError::LmdbError(e),
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> StoreError {
+        StoreError::IoError(e)
+    }
+}
+
+impl From<DataError> for StoreError {
+    fn from(e: DataError) -> StoreError {
+        StoreError::DataError(e)
+    }
+}
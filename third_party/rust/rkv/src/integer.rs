@@ -8,113 +8,74 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-<<<<<<< HEAD
-use std::marker::{
-    PhantomData,
-};
-
-use bincode::{
-    serialize,
-};
-
-use lmdb::{
-    Database,
-    RoTransaction,
-};
-
-use serde::{
-    Serialize,
-};
-=======
 use std::marker::PhantomData;
 
-use bincode::serialize;
-
-use serde::Serialize;
-
-use lmdb::Database;
->>>>>>> central
+use backend::{
+    BackendDatabase,
+    BackendEnvironment,
+};
 
 use error::{
     DataError,
     StoreError,
 };
 
-<<<<<<< HEAD
-use value::{
-    Value,
-};
-=======
 use value::Value;
->>>>>>> central
 
 use readwrite::{
+    Iter,
+    MultiStore,
     Reader,
     Store,
     Writer,
 };
 
-<<<<<<< HEAD
-use ::Rkv;
-
-
-=======
->>>>>>> central
 pub trait EncodableKey {
     fn to_bytes(&self) -> Result<Vec<u8>, DataError>;
 }
 
+/// A primitive integer usable as an `IntegerStore` key. Keys are encoded as
+/// fixed-width big-endian bytes, with the sign bit of signed types flipped,
+/// so that LMDB's lexicographic byte-string comparison orders them exactly
+/// as numeric comparison would -- `iter_from` walks keys in numeric order.
 pub trait PrimitiveInt: EncodableKey {}
 
-impl PrimitiveInt for u32 {}
+macro_rules! primitive_int {
+    ($ty:ty, $unsigned:ty, $flip:expr) => {
+        impl EncodableKey for $ty {
+            fn to_bytes(&self) -> Result<Vec<u8>, DataError> {
+                let flipped = (*self as $unsigned) ^ $flip;
+                Ok(flipped.to_be_bytes().to_vec())
+            }
+        }
 
-<<<<<<< HEAD
-impl<T> EncodableKey for T where T: Serialize {
-    fn to_bytes(&self) -> Result<Vec<u8>, DataError> {
-        serialize(self)         // TODO: limited key length.
-        .map_err(|e| e.into())
-    }
+        impl PrimitiveInt for $ty {}
+    };
 }
 
-struct Key<K> {
-=======
-impl<T> EncodableKey for T
-where
-    T: Serialize,
-{
-    fn to_bytes(&self) -> Result<Vec<u8>, DataError> {
-        serialize(self)         // TODO: limited key length.
-            .map_err(|e| e.into())
-    }
-}
+primitive_int!(u32, u32, 0);
+primitive_int!(i32, u32, 1u32 << 31);
+primitive_int!(u64, u64, 0);
+primitive_int!(i64, u64, 1u64 << 63);
 
 pub(crate) struct Key<K> {
->>>>>>> central
     bytes: Vec<u8>,
     phantom: PhantomData<K>,
 }
 
-<<<<<<< HEAD
-impl<K> AsRef<[u8]> for Key<K> where K: EncodableKey {
-=======
 impl<K> AsRef<[u8]> for Key<K>
 where
     K: EncodableKey,
 {
->>>>>>> central
     fn as_ref(&self) -> &[u8] {
         self.bytes.as_ref()
     }
 }
 
-<<<<<<< HEAD
-impl<K> Key<K> where K: EncodableKey {
-=======
 impl<K> Key<K>
 where
     K: EncodableKey,
 {
->>>>>>> central
     fn new(k: K) -> Result<Key<K>, DataError> {
         Ok(Key {
             bytes: k.to_bytes()?,
@@ -123,155 +84,167 @@ where
     }
 }
 
-<<<<<<< HEAD
-pub struct IntegerStore<K> where K: PrimitiveInt {
-    inner: Store<Key<K>>,
-}
-
-pub struct IntegerReader<'env, K> where K: PrimitiveInt {
-    inner: Reader<'env, Key<K>>,
-}
-
-impl<'env, K> IntegerReader<'env, K> where K: PrimitiveInt {
-    pub fn get<'s>(&'s self, k: K) -> Result<Option<Value<'s>>, StoreError> {
-        self.inner.get(Key::new(k)?)
-=======
-pub struct IntegerReader<'env, K>
+pub struct IntegerReader<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: PrimitiveInt,
 {
-    inner: Reader<'env, Key<K>>,
+    inner: Reader<'env, E, Key<K>>,
 }
 
-impl<'env, K> IntegerReader<'env, K>
+impl<'env, E, K> IntegerReader<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: PrimitiveInt,
 {
-    pub(crate) fn new(reader: Reader<Key<K>>) -> IntegerReader<K> {
+    pub(crate) fn new(reader: Reader<'env, E, Key<K>>) -> IntegerReader<'env, E, K> {
         IntegerReader {
             inner: reader,
         }
     }
 
-    pub fn get<'s>(&'s self, store: &'s IntegerStore, k: K) -> Result<Option<Value<'s>>, StoreError> {
+    pub fn get<'s>(&'s self, store: &'s IntegerStore<E::Database>, k: K) -> Result<Option<Value<'s>>, StoreError>
+    where
+        'env: 's,
+    {
         self.inner.get(&store.inner, Key::new(k)?)
->>>>>>> central
     }
 
-    pub fn abort(self) {
-        self.inner.abort();
+    pub fn iter_start<'s>(&'s self, store: &'s IntegerStore<E::Database>) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        self.inner.iter_start(&store.inner)
     }
-}
 
-<<<<<<< HEAD
-pub struct IntegerWriter<'env, K> where K: PrimitiveInt {
-    inner: Writer<'env, Key<K>>,
-}
+    pub fn iter_from<'s>(&'s self, store: &'s IntegerStore<E::Database>, k: K) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        self.inner.iter_from(&store.inner, Key::new(k)?)
+    }
+
+    pub fn get_multi<'s>(&'s self, store: &'s IntegerMultiStore<E::Database>, k: K) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        self.inner.get_multi(&store.inner, Key::new(k)?)
+    }
 
-impl<'env, K> IntegerWriter<'env, K> where K: PrimitiveInt {
-    pub fn get<'s>(&'s self, k: K) -> Result<Option<Value<'s>>, StoreError> {
-        self.inner.get(Key::new(k)?)
+    pub fn get_first<'s>(&'s self, store: &'s IntegerMultiStore<E::Database>, k: K) -> Result<Option<Value<'s>>, StoreError>
+    where
+        'env: 's,
+    {
+        self.inner.get_first(&store.inner, Key::new(k)?)
     }
 
-    pub fn put<'s>(&'s mut self, k: K, v: &Value) -> Result<(), StoreError> {
-        self.inner.put(Key::new(k)?, v)
-=======
-pub struct IntegerWriter<'env, K>
+    pub fn abort(self) {
+        self.inner.abort();
+    }
+}
+
+pub struct IntegerWriter<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: PrimitiveInt,
 {
-    inner: Writer<'env, Key<K>>,
+    inner: Writer<'env, E, Key<K>>,
 }
 
-impl<'env, K> IntegerWriter<'env, K>
+impl<'env, E, K> IntegerWriter<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: PrimitiveInt,
 {
-    pub(crate) fn new(writer: Writer<Key<K>>) -> IntegerWriter<K> {
+    pub(crate) fn new(writer: Writer<'env, E, Key<K>>) -> IntegerWriter<'env, E, K> {
         IntegerWriter {
             inner: writer,
         }
     }
 
-    pub fn get<'s>(&'s self, store: &'s IntegerStore, k: K) -> Result<Option<Value<'s>>, StoreError> {
+    pub fn get<'s>(&'s self, store: &'s IntegerStore<E::Database>, k: K) -> Result<Option<Value<'s>>, StoreError>
+    where
+        'env: 's,
+    {
         self.inner.get(&store.inner, Key::new(k)?)
     }
 
-    pub fn put<'s>(&'s mut self, store: &'s IntegerStore, k: K, v: &Value) -> Result<(), StoreError> {
+    pub fn put<'s>(&'s mut self, store: &'s IntegerStore<E::Database>, k: K, v: &Value) -> Result<(), StoreError> {
         self.inner.put(&store.inner, Key::new(k)?, v)
->>>>>>> central
     }
 
-    fn abort(self) {
-        self.inner.abort();
+    pub fn delete<'s>(&'s mut self, store: &'s IntegerStore<E::Database>, k: K) -> Result<(), StoreError> {
+        self.inner.delete(&store.inner, Key::new(k)?)
     }
-<<<<<<< HEAD
-}
 
-impl<K> IntegerStore<K> where K: PrimitiveInt {
-    pub fn new(db: Database) -> IntegerStore<K> {
-=======
+    pub fn get_multi<'s>(&'s self, store: &'s IntegerMultiStore<E::Database>, k: K) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        self.inner.get_multi(&store.inner, Key::new(k)?)
+    }
+
+    pub fn get_first<'s>(&'s self, store: &'s IntegerMultiStore<E::Database>, k: K) -> Result<Option<Value<'s>>, StoreError>
+    where
+        'env: 's,
+    {
+        self.inner.get_first(&store.inner, Key::new(k)?)
+    }
 
-    fn commit(self) -> Result<(), StoreError> {
+    pub fn put_multi(&mut self, store: &IntegerMultiStore<E::Database>, k: K, v: &Value) -> Result<(), StoreError> {
+        self.inner.put_multi(&store.inner, Key::new(k)?, v)
+    }
+
+    pub fn delete_all(&mut self, store: &IntegerMultiStore<E::Database>, k: K) -> Result<(), StoreError> {
+        self.inner.delete_all(&store.inner, Key::new(k)?)
+    }
+
+    /// Deletes a single `(k, v)` pair from an `IntegerMultiStore`, leaving
+    /// any other values stored under `k` untouched. See
+    /// `Writer::delete_value`.
+    pub fn delete_value(&mut self, store: &IntegerMultiStore<E::Database>, k: K, v: &Value) -> Result<(), StoreError> {
+        self.inner.delete_value(&store.inner, Key::new(k)?, v)
+    }
+
+    pub fn abort(self) {
+        self.inner.abort();
+    }
+
+    pub fn commit(self) -> Result<(), StoreError> {
         self.inner.commit()
     }
 }
 
-pub struct IntegerStore {
-    inner: Store,
+pub struct IntegerStore<D: BackendDatabase> {
+    inner: Store<D>,
 }
 
-impl IntegerStore {
-    pub fn new(db: Database) -> IntegerStore {
->>>>>>> central
+impl<D: BackendDatabase> IntegerStore<D> {
+    pub fn new(db: D) -> IntegerStore<D> {
         IntegerStore {
             inner: Store::new(db),
         }
     }
-<<<<<<< HEAD
-
-    pub fn read<'env>(&self, env: &'env Rkv) -> Result<IntegerReader<'env, K>, StoreError> {
-        Ok(IntegerReader {
-            inner: self.inner.read(env)?,
-        })
-    }
+}
 
-    pub fn write<'env>(&mut self, env: &'env Rkv) -> Result<IntegerWriter<'env, K>, StoreError> {
-        Ok(IntegerWriter {
-            inner: self.inner.write(env)?,
-        })
-    }
+/// Like `IntegerStore`, but opened with the dup-sort flag, mirroring
+/// `MultiStore`'s relationship to `Store`: a key may have more than one
+/// value, and `IntegerReader`/`IntegerWriter` expose `get_multi`/`put_multi`/
+/// `delete_all`/`delete_value` for it instead of `get`/`put`/`delete`.
+pub struct IntegerMultiStore<D: BackendDatabase> {
+    inner: MultiStore<D>,
+}
 
-    pub fn get<'env, 'tx>(&self, tx: &'tx RoTransaction<'env>, k: K) -> Result<Option<Value<'tx>>, StoreError> {
-        let key = Key::new(k)?;
-        self.inner.get(tx, key)
+impl<D: BackendDatabase> IntegerMultiStore<D> {
+    pub fn new(db: D) -> IntegerMultiStore<D> {
+        IntegerMultiStore {
+            inner: MultiStore::new(db),
+        }
     }
-=======
->>>>>>> central
 }
 
 #[cfg(test)]
 mod tests {
-<<<<<<< HEAD
-    extern crate tempdir;
-
-    use self::tempdir::TempDir;
-    use std::fs;
-
-    use super::*;
-
-    #[test]
-    fn test_integer_keys() {
-        let root = TempDir::new("test_integer_keys").expect("tempdir");
-        fs::create_dir_all(root.path()).expect("dir created");
-        let k = Rkv::new(root.path()).expect("new succeeded");
-        let mut s: IntegerStore<u32> = k.create_or_open_integer("s").expect("open");
-
-        let mut writer = s.write(&k).expect("writer");
-
-        writer.put(123, &Value::Str("hello!")).expect("write");
-        assert_eq!(writer.get(123).expect("read"), Some(Value::Str("hello!")));
-=======
     extern crate tempfile;
 
     use self::tempfile::Builder;
@@ -295,6 +268,34 @@ mod tests {
 
         let reader = k.read_int::<u32>().expect("reader");
         assert_eq!(reader.get(&s, 123).expect("read"), Some(Value::Str("hello!")));
->>>>>>> central
+    }
+
+    /// Integer keys are encoded as fixed-width big-endian bytes specifically
+    /// so that LMDB's lexicographic ordering matches numeric ordering. Keys
+    /// inserted out of order, straddling the 255/256 byte boundary, should
+    /// still iterate as 1, 2, 256 -- not 1, 256, 2, which is what naive
+    /// little-endian or variable-width encoding would produce.
+    #[test]
+    fn test_integer_keys_iterate_in_numeric_order() {
+        let root = Builder::new().prefix("test_integer_keys_iterate_in_numeric_order").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+        let k = Rkv::new(root.path()).expect("new succeeded");
+        let s = k.open_integer::<u32>("s").expect("open");
+
+        let mut writer = k.write_int::<u32>().expect("writer");
+        writer.put(&s, 256, &Value::Str("two-five-six")).expect("write");
+        writer.put(&s, 1, &Value::Str("one")).expect("write");
+        writer.put(&s, 2, &Value::Str("two")).expect("write");
+        writer.commit().expect("committed");
+
+        let reader = k.read_int::<u32>().expect("reader");
+        let mut iter = reader.iter_start(&s).unwrap();
+        let (_, val) = iter.next().unwrap();
+        assert_eq!(val.expect("value"), Some(Value::Str("one")));
+        let (_, val) = iter.next().unwrap();
+        assert_eq!(val.expect("value"), Some(Value::Str("two")));
+        let (_, val) = iter.next().unwrap();
+        assert_eq!(val.expect("value"), Some(Value::Str("two-five-six")));
+        assert!(iter.next().is_none());
     }
 }
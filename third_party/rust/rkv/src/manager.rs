@@ -0,0 +1,239 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Opening the same LMDB environment twice in one process, even from
+//! unrelated modules, corrupts its locking state. `Manager` is a process-wide
+//! registry of already-open `Rkv` handles, keyed by canonicalized path, so
+//! that independent consumers can share one environment per path without
+//! coordinating their open calls. `get`/`get_or_create` go through `MANAGER`'s
+//! `RwLock`; `get_fast` instead reads a separately-published `ArcSwap`
+//! snapshot, so a read-heavy "open once, read many" workload never contends
+//! on that lock.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    RwLock,
+};
+
+use arc_swap::ArcSwap;
+
+use backend::{LmdbEnvironment, SafeModeEnvironment};
+
+use env::Rkv;
+use error::StoreError;
+
+lazy_static! {
+    static ref MANAGER: RwLock<Manager> = RwLock::new(Manager::new());
+
+    /// A lock-free snapshot of `MANAGER`'s path -> environment map, published
+    /// by every `get_or_create` right after it updates `MANAGER` itself.
+    /// `get_fast` reads this directly via `ArcSwap::load`, so a flood of
+    /// concurrent readers on the common "open once, read many" workload
+    /// never contends with `MANAGER`'s `RwLock` -- only the (rare, already
+    /// serialized-by-the-RwLock) writer path pays the cost of publishing a
+    /// new snapshot.
+    static ref PUBLISHED: ArcSwap<BTreeMap<PathBuf, Arc<RwLock<Rkv<LmdbEnvironment>>>>> = ArcSwap::from_pointee(BTreeMap::new());
+
+    // The SafeMode backend gets its own singleton and published snapshot,
+    // mirroring the Lmdb ones above: the two backends never open the same
+    // path, so there's no reason to share (or serialize on) one registry.
+    static ref MANAGER_SAFE_MODE: RwLock<Manager<SafeModeEnvironment>> = RwLock::new(Manager::new());
+    static ref PUBLISHED_SAFE_MODE: ArcSwap<BTreeMap<PathBuf, Arc<RwLock<Rkv<SafeModeEnvironment>>>>> = ArcSwap::from_pointee(BTreeMap::new());
+}
+
+/// A process-wide singleton that hands out one `Arc<RwLock<Rkv>>` per
+/// canonicalized path, ensuring that two callers asking for the same
+/// environment get the same handle instead of each opening their own.
+pub struct Manager<E = LmdbEnvironment> {
+    environments: BTreeMap<PathBuf, Arc<RwLock<Rkv<E>>>>,
+}
+
+impl<E> Manager<E> {
+    fn new() -> Manager<E> {
+        Manager {
+            environments: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manager<LmdbEnvironment> {
+    /// Returns the process-wide `Manager` singleton.
+    pub fn singleton() -> &'static RwLock<Manager<LmdbEnvironment>> {
+        &MANAGER
+    }
+
+    /// Returns the already-open `Rkv` at `path`, if any, without creating one.
+    pub fn get(&self, path: &Path) -> Result<Option<Arc<RwLock<Rkv<LmdbEnvironment>>>>, StoreError> {
+        let canonical = canonicalize_path(path)?;
+        Ok(self.environments.get(&canonical).cloned())
+    }
+
+    /// Returns the already-open `Rkv` at `path`, creating and registering one
+    /// via `f` if none exists yet.
+    pub fn get_or_create<F>(&mut self, path: &Path, f: F) -> Result<Arc<RwLock<Rkv<LmdbEnvironment>>>, StoreError>
+    where
+        F: FnOnce(&Path) -> Result<Rkv<LmdbEnvironment>, StoreError>,
+    {
+        let canonical = canonicalize_path(path)?;
+        let publish_key = canonical.clone();
+        let result = match self.environments.entry(canonical) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let rkv = f(entry.key())?;
+                let rkv = entry.insert(Arc::new(RwLock::new(rkv))).clone();
+                // Merge into whatever's already published rather than
+                // replacing it with `self.environments`: this `Manager` may
+                // not be `MANAGER`'s singleton (tests construct their own
+                // via `Manager::new()`), so its own map is not necessarily a
+                // superset of what other `Manager` instances have published.
+                let published_rkv = rkv.clone();
+                PUBLISHED.rcu(|current| {
+                    let mut map = (**current).clone();
+                    map.insert(publish_key.clone(), published_rkv.clone());
+                    map
+                });
+                rkv
+            },
+        };
+        Ok(result)
+    }
+
+    /// Like `get`, but reads the path -> environment map without taking
+    /// `MANAGER`'s lock, via the snapshot `get_or_create` publishes on every
+    /// insertion. Since it's published strictly after `MANAGER` is updated,
+    /// a concurrent `get_fast` can only ever lag a fresh `get_or_create` by
+    /// the time it takes to publish, never return a stale `Arc` for a path
+    /// that was already registered.
+    pub fn get_fast(path: &Path) -> Result<Option<Arc<RwLock<Rkv<LmdbEnvironment>>>>, StoreError> {
+        let canonical = canonicalize_path(path)?;
+        Ok(PUBLISHED.load().get(&canonical).cloned())
+    }
+}
+
+impl Manager<SafeModeEnvironment> {
+    /// Returns the process-wide `Manager` singleton for the SafeMode backend.
+    pub fn singleton() -> &'static RwLock<Manager<SafeModeEnvironment>> {
+        &MANAGER_SAFE_MODE
+    }
+
+    /// Returns the already-open `Rkv` at `path`, if any, without creating one.
+    pub fn get(&self, path: &Path) -> Result<Option<Arc<RwLock<Rkv<SafeModeEnvironment>>>>, StoreError> {
+        let canonical = canonicalize_path(path)?;
+        Ok(self.environments.get(&canonical).cloned())
+    }
+
+    /// Returns the already-open `Rkv` at `path`, creating and registering one
+    /// via `f` if none exists yet.
+    pub fn get_or_create<F>(&mut self, path: &Path, f: F) -> Result<Arc<RwLock<Rkv<SafeModeEnvironment>>>, StoreError>
+    where
+        F: FnOnce(&Path) -> Result<Rkv<SafeModeEnvironment>, StoreError>,
+    {
+        let canonical = canonicalize_path(path)?;
+        let publish_key = canonical.clone();
+        let result = match self.environments.entry(canonical) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let rkv = f(entry.key())?;
+                let rkv = entry.insert(Arc::new(RwLock::new(rkv))).clone();
+                // See the Lmdb `get_or_create` above: merge into whatever's
+                // already published instead of overwriting it wholesale.
+                let published_rkv = rkv.clone();
+                PUBLISHED_SAFE_MODE.rcu(|current| {
+                    let mut map = (**current).clone();
+                    map.insert(publish_key.clone(), published_rkv.clone());
+                    map
+                });
+                rkv
+            },
+        };
+        Ok(result)
+    }
+
+    /// Like `get`, but reads the path -> environment map without taking
+    /// `MANAGER_SAFE_MODE`'s lock, via the snapshot `get_or_create` publishes
+    /// on every insertion.
+    pub fn get_fast(path: &Path) -> Result<Option<Arc<RwLock<Rkv<SafeModeEnvironment>>>>, StoreError> {
+        let canonical = canonicalize_path(path)?;
+        Ok(PUBLISHED_SAFE_MODE.load().get(&canonical).cloned())
+    }
+}
+
+fn canonicalize_path(path: &Path) -> Result<PathBuf, io::Error> {
+    path.canonicalize()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use self::tempfile::Builder;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_same_path_returns_same_environment() {
+        let root = Builder::new().prefix("test_manager_same_path").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+
+        let mut manager = Manager::new();
+        assert!(manager.get(root.path()).expect("get").is_none());
+
+        let first = manager.get_or_create(root.path(), Rkv::new).expect("created");
+        let second = manager.get_or_create(root.path(), Rkv::new).expect("fetched");
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let fetched = manager.get(root.path()).expect("get").expect("present");
+        assert!(Arc::ptr_eq(&first, &fetched));
+    }
+
+    #[test]
+    fn test_get_fast_sees_environment_published_by_get_or_create() {
+        let root = Builder::new().prefix("test_manager_get_fast").tempdir().expect("tempdir");
+        fs::create_dir_all(root.path()).expect("dir created");
+
+        assert!(Manager::get_fast(root.path()).expect("get_fast").is_none());
+
+        let mut manager = Manager::new();
+        let created = manager.get_or_create(root.path(), Rkv::new).expect("created");
+
+        let fetched = Manager::get_fast(root.path()).expect("get_fast").expect("present");
+        assert!(Arc::ptr_eq(&created, &fetched));
+    }
+
+    #[test]
+    fn test_get_fast_sees_environments_from_multiple_manager_instances() {
+        let first_root = Builder::new().prefix("test_manager_multi_a").tempdir().expect("tempdir");
+        fs::create_dir_all(first_root.path()).expect("dir created");
+        let second_root = Builder::new().prefix("test_manager_multi_b").tempdir().expect("tempdir");
+        fs::create_dir_all(second_root.path()).expect("dir created");
+
+        // Two independent `Manager` instances (as opposed to both going
+        // through the `MANAGER` singleton) each registering a different
+        // path must not clobber each other's entry in the shared published
+        // snapshot that `get_fast` reads.
+        let mut first_manager = Manager::new();
+        let first = first_manager.get_or_create(first_root.path(), Rkv::new).expect("created");
+        let mut second_manager = Manager::new();
+        let second = second_manager.get_or_create(second_root.path(), Rkv::new).expect("created");
+
+        let first_fetched = Manager::get_fast(first_root.path()).expect("get_fast").expect("present");
+        assert!(Arc::ptr_eq(&first, &first_fetched));
+        let second_fetched = Manager::get_fast(second_root.path()).expect("get_fast").expect("present");
+        assert!(Arc::ptr_eq(&second, &second_fetched));
+    }
+}
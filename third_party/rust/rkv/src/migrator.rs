@@ -0,0 +1,477 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Migrates an LMDB `data.mdb` file written by a build of a different word
+//! size (32-bit vs. 64-bit) into a fresh environment laid out for the host's
+//! word size. LMDB's meta page embeds `size_t`/pointer-width fields, so a
+//! file written by one word size is unreadable -- and can look corrupt -- to
+//! the LMDB library running under the other. This module parses the raw
+//! file by hand, well below the `lmdb` crate, to sidestep that: it never
+//! asks LMDB to open the foreign-architecture file.
+//!
+//! This doesn't use LMDB's C structs directly; the layout below is derived
+//! from LMDB's on-disk format as of the version this crate vendors, and is
+//! deliberately re-derived in Rust rather than bound via FFI, since reading
+//! a possibly-foreign-architecture file through structs sized for the host
+//! architecture is exactly the bug we're working around.
+
+use std::fs;
+use std::io;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use failure::Fail;
+
+use backend::BackendEnvironment;
+
+use env::Rkv;
+use error::StoreError;
+use value::Value;
+
+#[derive(Debug, Fail)]
+pub enum MigrateError {
+    #[fail(display = "I/O error: {}", _0)]
+    IoError(#[cause] io::Error),
+
+    #[fail(display = "{:?} is too small to contain an LMDB meta page", _0)]
+    FileTooSmall(PathBuf),
+
+    #[fail(display = "{:?} doesn't look like an LMDB data file: no meta page has a valid magic/version", _0)]
+    NotAnLmdbFile(PathBuf),
+
+    #[fail(display = "{:?} is already laid out for this host's word size; nothing to migrate", _0)]
+    NoMigrationNeeded(PathBuf),
+
+    #[fail(display = "{:?} is corrupt: {}", _0, _1)]
+    CorruptFile(PathBuf, String),
+
+    #[fail(display = "error writing migrated data: {}", _0)]
+    StoreError(#[cause] StoreError),
+}
+
+impl From<io::Error> for MigrateError {
+    fn from(e: io::Error) -> MigrateError {
+        MigrateError::IoError(e)
+    }
+}
+
+impl From<StoreError> for MigrateError {
+    fn from(e: StoreError) -> MigrateError {
+        MigrateError::StoreError(e)
+    }
+}
+
+const META_MAGIC: u32 = 0xBEEF_C0DE;
+const META_VERSION: u32 = 1;
+const PAGE_SIZE: usize = 4096;
+
+/// Whether the meta page we're reading was produced by a 32-bit or 64-bit
+/// build. This governs the width of every `size_t`/`pgno_t`-typed field
+/// that follows it, both in the meta page and in the node headers of every
+/// branch/leaf page the meta page's root points at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum WordSize {
+    Bits32,
+    Bits64,
+}
+
+impl WordSize {
+    fn host() -> WordSize {
+        if cfg!(target_pointer_width = "64") {
+            WordSize::Bits64
+        } else {
+            WordSize::Bits32
+        }
+    }
+
+    /// Width, in bytes, of a `size_t`/`pgno_t`/pointer field at this word size.
+    fn width(self) -> usize {
+        match self {
+            WordSize::Bits32 => 4,
+            WordSize::Bits64 => 8,
+        }
+    }
+
+    /// `offsetof(MDB_page, mp_ptrs)`: generic page header, before the node
+    /// pointer array -- `pgno_t mp_pgno` + `uint16_t mp_pad` + `uint16_t
+    /// mp_flags` + `uint16_t mp_lower` + `uint16_t mp_upper`.
+    fn page_header_size(self) -> usize {
+        self.width() + 8
+    }
+
+    /// Size of a root-level `MDB_db` record (`md_pad`, `md_flags`,
+    /// `md_depth`, three `pgno_t` page counts, one `size_t` entry count, and
+    /// the `pgno_t` root page), as embedded in the meta page.
+    fn db_record_size(self) -> usize {
+        8 + 3 * self.width() + self.width() + self.width()
+    }
+
+    /// Size of one `MDB_node` header, before its inline key/value bytes --
+    /// `mn_lo`/`mn_hi` (together a `pgno_t`-or-data-size split across two
+    /// 16-bit halves) plus one more 16-bit word that packs `mn_flags:4` and
+    /// `mn_ksize:12` as bitfields, rather than two independent `u16`s. This
+    /// is the same 6 bytes regardless of word size -- the node header, unlike
+    /// the meta page, has no `size_t`/pointer-width fields in it.
+    fn node_header_size(self) -> usize {
+        6
+    }
+}
+
+struct DbRecord {
+    root: u64,
+}
+
+struct MetaPage {
+    txnid: u64,
+    main_db: DbRecord,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Reads a `size_t`/`pgno_t`-width little-endian field at `offset` and
+/// widens it to a `u64`, regardless of whether `word` is 32 or 64 bits.
+fn read_word(data: &[u8], offset: usize, word: WordSize) -> u64 {
+    match word {
+        WordSize::Bits32 => u64::from(read_u32(data, offset)),
+        WordSize::Bits64 => {
+            let lo = u64::from(read_u32(data, offset));
+            let hi = u64::from(read_u32(data, offset + 4));
+            lo | (hi << 32)
+        },
+    }
+}
+
+/// Parses the `MDB_db` record for the main database (the second of the two
+/// `mm_dbs` entries in the meta page; the first is the free-list database,
+/// which this migrator has no need to read).
+fn read_main_db(data: &[u8], meta_offset: usize, word: WordSize) -> DbRecord {
+    // mm_magic(4) + mm_version(4) + mm_address(word) + mm_mapsize(word), then
+    // mm_dbs[0] (free db) followed by mm_dbs[1] (main db).
+    let dbs_offset = meta_offset + 8 + 2 * word.width();
+    let main_db_offset = dbs_offset + word.db_record_size();
+    // md_pad(4) + md_flags(2) + md_depth(2) + three pgno_t page counts, then
+    // md_entries (size_t), then md_root (pgno_t) -- the field we want.
+    let root_offset = main_db_offset + 8 + 3 * word.width() + word.width();
+    DbRecord {
+        root: read_word(data, root_offset, word),
+    }
+}
+
+/// Tries to parse the meta page at `page_index` (0 or 1) under the given
+/// word-size hypothesis, returning its contents if the page's magic and
+/// version fields check out -- the self-consistency check that lets us
+/// tell which of the two word-size interpretations is the right one.
+fn try_read_meta(data: &[u8], page_index: usize, word: WordSize) -> Option<MetaPage> {
+    let page_offset = page_index * PAGE_SIZE;
+    let meta_offset = page_offset + word.page_header_size();
+    if meta_offset + 8 > data.len() {
+        return None;
+    }
+    let magic = read_u32(data, meta_offset);
+    let version = read_u32(data, meta_offset + 4);
+    if magic != META_MAGIC || version != META_VERSION {
+        return None;
+    }
+    let main_db = read_main_db(data, meta_offset, word);
+    // mm_last_pg (pgno_t) immediately follows mm_dbs[0..2]; mm_txnid (a
+    // size_t-width txnid_t) immediately follows that.
+    let dbs_offset = meta_offset + 8 + 2 * word.width();
+    let txnid_offset = dbs_offset + 2 * word.db_record_size() + word.width();
+    if txnid_offset + word.width() > data.len() {
+        return None;
+    }
+    Some(MetaPage {
+        txnid: read_word(data, txnid_offset, word),
+        main_db,
+    })
+}
+
+/// Detects which word size produced this file by trying both
+/// interpretations of each meta page (0 and 1), keeping whichever
+/// self-consistently parses, and returns the root of the more recent of
+/// the two (the one with the higher transaction id) -- mirroring how LMDB
+/// itself picks the authoritative meta page on open.
+fn detect_layout(data: &[u8], path: &Path) -> Result<(WordSize, DbRecord), MigrateError> {
+    if data.len() < 2 * PAGE_SIZE {
+        return Err(MigrateError::FileTooSmall(path.into()));
+    }
+
+    let mut best: Option<(WordSize, MetaPage)> = None;
+    for &page_index in &[0usize, 1usize] {
+        for &word in &[WordSize::Bits32, WordSize::Bits64] {
+            if let Some(meta) = try_read_meta(data, page_index, word) {
+                if best.as_ref().map_or(true, |(_, b)| meta.txnid > b.txnid) {
+                    best = Some((word, meta));
+                }
+                break;
+            }
+        }
+    }
+
+    match best {
+        Some((word, meta)) => Ok((word, meta.main_db)),
+        None => Err(MigrateError::NotAnLmdbFile(path.into())),
+    }
+}
+
+/// Leaf/branch node flags, from LMDB's `MDB_node_flags`.
+const F_BIGDATA: u16 = 0x01;
+const F_SUBDATA: u16 = 0x02;
+
+/// Page flags, from LMDB's `MDB_page_flags`.
+const P_BRANCH: u16 = 0x01;
+
+struct Page<'a> {
+    data: &'a [u8],
+    flags: u16,
+    /// Byte offsets, into `data`, of each node on this page, in key order.
+    node_offsets: Vec<usize>,
+}
+
+fn read_page<'a>(data: &'a [u8], pgno: u64, word: WordSize, path: &Path) -> Result<Page<'a>, MigrateError> {
+    let offset = pgno as usize * PAGE_SIZE;
+    if offset + PAGE_SIZE > data.len() {
+        return Err(MigrateError::CorruptFile(path.into(), format!("page {} is past the end of the file", pgno)));
+    }
+    let page = &data[offset..offset + PAGE_SIZE];
+    let flags = read_u16(page, word.width() + 2);
+    let lower = read_u16(page, word.width() + 4) as usize;
+    let header_size = word.page_header_size();
+    if lower < header_size {
+        return Err(MigrateError::CorruptFile(path.into(), format!("page {} has an invalid header", pgno)));
+    }
+    let count = (lower - header_size) / 2;
+    let node_offsets = (0..count).map(|i| read_u16(page, header_size + i * 2) as usize).collect();
+    Ok(Page {
+        data: page,
+        flags,
+        node_offsets,
+    })
+}
+
+struct Node {
+    key: Vec<u8>,
+    /// Either inline value bytes, a child page number (branch node), or an
+    /// `MDB_db` record (sub-database leaf node).
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    ChildPage(u64),
+    Value(Vec<u8>),
+    Overflow {
+        pgno: u64,
+        size: u32,
+    },
+    SubDb(DbRecord),
+}
+
+fn read_node(page: &Page, node_offset: usize, word: WordSize) -> Node {
+    let p = page.data;
+    let lo = read_u16(p, node_offset);
+    let hi = read_u16(p, node_offset + 2);
+    // mn_flags and mn_ksize are bitfields packed into a single 16-bit word
+    // (flags in the low 4 bits, ksize in the high 12), not two separate
+    // u16 fields.
+    let packed = read_u16(p, node_offset + 4);
+    let flags = packed & 0x000F;
+    let ksize = (packed >> 4) as usize;
+    let data_offset = node_offset + word.node_header_size();
+    let key = p[data_offset..data_offset + ksize].to_vec();
+    let value_offset = data_offset + ksize;
+
+    let kind = if page.flags & P_BRANCH != 0 {
+        NodeKind::ChildPage(u64::from(lo) | (u64::from(hi) << 16))
+    } else if flags & F_BIGDATA != 0 {
+        let size = u32::from(lo) | (u32::from(hi) << 16);
+        let pgno = read_word(p, value_offset, word);
+        NodeKind::Overflow {
+            pgno,
+            size,
+        }
+    } else if flags & F_SUBDATA != 0 {
+        NodeKind::SubDb(read_main_db_record_inline(p, value_offset, word))
+    } else {
+        let size = u32::from(lo) | (u32::from(hi) << 16);
+        NodeKind::Value(p[value_offset..value_offset + size as usize].to_vec())
+    };
+
+    Node {
+        key,
+        kind,
+    }
+}
+
+/// Like `read_main_db`, but for an `MDB_db` record embedded inline as a
+/// sub-database leaf node's value, rather than in the meta page.
+fn read_main_db_record_inline(data: &[u8], offset: usize, word: WordSize) -> DbRecord {
+    let root_offset = offset + 8 + 3 * word.width() + word.width();
+    DbRecord {
+        root: read_word(data, root_offset, word),
+    }
+}
+
+fn read_overflow_value(data: &[u8], pgno: u64, size: u32) -> Vec<u8> {
+    let offset = pgno as usize * PAGE_SIZE;
+    data[offset..offset + size as usize].to_vec()
+}
+
+/// Walks every leaf entry reachable from `root`, calling `visit(key, value)`
+/// for each one, recursing into sub-databases via `on_subdb` (rather than
+/// treating them as ordinary values) so the caller can re-home their
+/// entries under the right named store.
+fn walk<F, S>(data: &[u8], root: u64, word: WordSize, path: &Path, visit: &mut F, on_subdb: &mut S) -> Result<(), MigrateError>
+where
+    F: FnMut(&[u8], &[u8]),
+    S: FnMut(&[u8], u64),
+{
+    let page = read_page(data, root, word, path)?;
+    let offsets = page.node_offsets.clone();
+    for node_offset in offsets {
+        let node = read_node(&page, node_offset, word);
+        match node.kind {
+            NodeKind::ChildPage(child) => walk(data, child, word, path, visit, on_subdb)?,
+            NodeKind::Value(value) => visit(&node.key, &value),
+            NodeKind::Overflow {
+                pgno,
+                size,
+            } => {
+                let value = read_overflow_value(data, pgno, size);
+                visit(&node.key, &value);
+            },
+            NodeKind::SubDb(db) => on_subdb(&node.key, db.root),
+        }
+    }
+    Ok(())
+}
+
+/// Reads every `(store name, key, value)` triple out of a foreign-word-size
+/// `data.mdb` file at `data_path`, without going through LMDB at all. `None`
+/// as a store name means the unnamed/default database. Shared by
+/// `migrate_lmdb_data_file` (which rewrites the file in place) and
+/// `migrate_lmdb_data_file_into` (which writes into a caller-supplied `Rkv`).
+fn read_entries(data_path: &Path) -> Result<Vec<(Option<String>, Vec<u8>, Vec<u8>)>, MigrateError> {
+    let data = fs::read(data_path)?;
+
+    let (word, main_db) = detect_layout(&data, data_path)?;
+    if word == WordSize::host() {
+        return Err(MigrateError::NoMigrationNeeded(data_path.into()));
+    }
+
+    let mut entries: Vec<(Option<String>, Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut sub_dbs: Vec<(String, u64)> = Vec::new();
+
+    walk(
+        &data,
+        main_db.root,
+        word,
+        data_path,
+        &mut |key, value| entries.push((None, key.to_vec(), value.to_vec())),
+        &mut |name, root| sub_dbs.push((String::from_utf8_lossy(name).into_owned(), root)),
+    )?;
+
+    for (name, root) in sub_dbs {
+        walk(
+            &data,
+            root,
+            word,
+            data_path,
+            &mut |key, value| entries.push((Some(name.clone()), key.to_vec(), value.to_vec())),
+            &mut |_, _| {
+                // LMDB doesn't nest named databases more than one level deep,
+                // so there's nothing further to recurse into here.
+            },
+        )?;
+    }
+
+    Ok(entries)
+}
+
+fn group_by_store(entries: Vec<(Option<String>, Vec<u8>, Vec<u8>)>) -> std::collections::HashMap<Option<String>, Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut by_store: std::collections::HashMap<Option<String>, Vec<(Vec<u8>, Vec<u8>)>> = std::collections::HashMap::new();
+    for (name, key, value) in entries {
+        by_store.entry(name).or_insert_with(Vec::new).push((key, value));
+    }
+    by_store
+}
+
+/// Migrates the `data.mdb` file at `path`, which was written by a build of
+/// a different word size, into the current architecture's layout, then
+/// atomically replaces it, leaving the original file alongside it as
+/// `data.mdb.bak`. Does nothing (and returns
+/// `MigrateError::NoMigrationNeeded`) if the file already matches the
+/// host's word size. Returns the number of `(key, value)` records moved.
+pub fn migrate_lmdb_data_file(path: &Path) -> Result<usize, MigrateError> {
+    let data_path = path.join("data.mdb");
+    let entries = read_entries(&data_path)?;
+    let record_count = entries.len();
+
+    let tmp_dir = path.join("data.mdb.migrating");
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+    }
+    fs::create_dir_all(&tmp_dir)?;
+
+    {
+        let rkv = Rkv::new(&tmp_dir)?;
+        for (name, rows) in group_by_store(entries) {
+            let store = rkv.open_or_create(name.as_ref().map(String::as_str))?;
+            let mut writer = rkv.write::<&[u8]>()?;
+            for (key, value) in rows {
+                writer.put(&store, key.as_slice(), &Value::Blob(&value))?;
+            }
+            writer.commit()?;
+        }
+    }
+
+    let backup_path = path.join("data.mdb.bak");
+    fs::rename(&data_path, &backup_path)?;
+
+    let migrated_data_file = tmp_dir.join("data.mdb");
+    fs::rename(&migrated_data_file, &data_path)?;
+    fs::remove_dir_all(&tmp_dir)?;
+
+    Ok(record_count)
+}
+
+/// Like `migrate_lmdb_data_file`, but writes the source environment's
+/// entries into an already-open destination `Rkv` -- of any backend,
+/// including one that isn't LMDB -- via its normal `Writer::put` path,
+/// rather than building a temporary environment and swapping it in place.
+/// Useful when the destination already exists or lives at a different path
+/// than the source. The source file is left untouched. Returns the number
+/// of `(key, value)` records moved.
+pub fn migrate_lmdb_data_file_into<'env, E>(src_path: &Path, dest: &'env Rkv<E>) -> Result<usize, MigrateError>
+where
+    E: BackendEnvironment<'env>,
+{
+    let data_path = src_path.join("data.mdb");
+    let entries = read_entries(&data_path)?;
+    let record_count = entries.len();
+
+    for (name, rows) in group_by_store(entries) {
+        let store = dest.open_or_create(name.as_ref().map(String::as_str))?;
+        let mut writer = dest.write::<&[u8]>()?;
+        for (key, value) in rows {
+            writer.put(&store, key.as_slice(), &Value::Blob(&value))?;
+        }
+        writer.commit()?;
+    }
+
+    Ok(record_count)
+}
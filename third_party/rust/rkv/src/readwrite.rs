@@ -8,156 +8,196 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use lmdb;
-
-<<<<<<< HEAD
-use std::marker::{
-    PhantomData,
-};
-=======
 use std::marker::PhantomData;
->>>>>>> central
-
-use lmdb::{
-    Cursor,
-    Database,
-    Iter as LmdbIter,
-    RoCursor,
-    RoTransaction,
-    RwTransaction,
-    Transaction,
-};
-
-<<<<<<< HEAD
-use lmdb::{
-    WriteFlags,
-};
 
-use error::{
-    StoreError,
-};
+use rayon::prelude::*;
 
-use value::{
-    Value,
+use backend::{
+    BackendDatabase,
+    BackendEnvironment,
+    BackendRoCursor,
+    BackendRoTransaction,
+    BackendRoTransactionRenew,
+    BackendRoTransactionReset,
+    BackendRwTransaction,
+    WriteFlags,
 };
 
-use ::Rkv;
-
-fn read_transform<'x>(val: Result<&'x [u8], lmdb::Error>) -> Result<Option<Value<'x>>, StoreError> {
-    match val {
-        Ok(bytes) => Value::from_tagged_slice(bytes).map(Some)
-                                                    .map_err(StoreError::DataError),
-=======
-use lmdb::WriteFlags;
-
 use error::StoreError;
 
 use value::Value;
 
-fn read_transform<'x>(val: Result<&'x [u8], lmdb::Error>) -> Result<Option<Value<'x>>, StoreError> {
-    match val {
-        Ok(bytes) => Value::from_tagged_slice(bytes).map(Some).map_err(StoreError::DataError),
->>>>>>> central
-        Err(lmdb::Error::NotFound) => Ok(None),
-        Err(e) => Err(StoreError::LmdbError(e)),
+fn read_transform<'x>(val: Result<Option<&'x [u8]>, StoreError>) -> Result<Option<Value<'x>>, StoreError> {
+    match val? {
+        Some(bytes) => Value::from_tagged_slice(bytes).map(Some).map_err(StoreError::DataError),
+        None => Ok(None),
     }
 }
 
-<<<<<<< HEAD
-pub struct Writer<'env, K> where K: AsRef<[u8]> {
-    tx: RwTransaction<'env>,
-    db: Database,
-    phantom: PhantomData<K>,
-}
-
-pub struct Reader<'env, K> where K: AsRef<[u8]> {
-    tx: RoTransaction<'env>,
-    db: Database,
-=======
-pub struct Writer<'env, K>
+pub struct Writer<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: AsRef<[u8]>,
 {
-    tx: RwTransaction<'env>,
+    tx: E::RwTransaction,
     phantom: PhantomData<K>,
 }
 
-pub struct Reader<'env, K>
+pub struct Reader<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: AsRef<[u8]>,
 {
-    tx: RoTransaction<'env>,
->>>>>>> central
+    tx: E::RoTransaction,
     phantom: PhantomData<K>,
 }
 
-pub struct Iter<'env> {
-    iter: LmdbIter<'env>,
-    cursor: RoCursor<'env>,
+pub struct Iter<'env, E>
+where
+    E: BackendEnvironment<'env>,
+{
+    iter: <<E::RoTransaction as BackendRoTransaction<'env>>::RoCursor as BackendRoCursor<'env>>::Iter,
 }
 
-<<<<<<< HEAD
-impl<'env, K> Writer<'env, K> where K: AsRef<[u8]> {
-    pub fn get<'s>(&'s self, k: K) -> Result<Option<Value<'s>>, StoreError> {
-        let bytes = self.tx.get(self.db, &k.as_ref());
-=======
-impl<'env, K> Writer<'env, K>
+impl<'env, E, K> Writer<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: AsRef<[u8]>,
 {
-    pub(crate) fn new(txn: RwTransaction) -> Writer<K> {
+    pub(crate) fn new(txn: E::RwTransaction) -> Writer<'env, E, K> {
         Writer {
             tx: txn,
             phantom: PhantomData,
         }
     }
 
-    pub fn get<'s>(&'s self, store: &'s Store, k: K) -> Result<Option<Value<'s>>, StoreError> {
-        let bytes = self.tx.get(store.db, &k.as_ref());
->>>>>>> central
-        read_transform(bytes)
+    pub fn get<'s>(&'s self, store: &'s Store<E::Database>, k: K) -> Result<Option<Value<'s>>, StoreError>
+    where
+        'env: 's,
+    {
+        read_transform(BackendRoTransaction::get(&self.tx, store.db, k.as_ref()))
     }
 
-    // TODO: flags
-<<<<<<< HEAD
-    pub fn put<'s>(&'s mut self, k: K, v: &Value) -> Result<(), StoreError> {
+    pub fn put<'s>(&'s mut self, store: &'s Store<E::Database>, k: K, v: &Value) -> Result<(), StoreError> {
+        self.put_with_flags(store, k, v, WriteFlags::empty())
+    }
+
+    pub fn put_with_flags<'s>(&'s mut self, store: &'s Store<E::Database>, k: K, v: &Value, flags: WriteFlags) -> Result<(), StoreError> {
         // TODO: don't allocate twice.
         let bytes = v.to_bytes()?;
-        self.tx
-            .put(self.db, &k.as_ref(), &bytes, WriteFlags::empty())
-            .map_err(StoreError::LmdbError)
+        self.tx.put(store.db, k.as_ref(), &bytes, flags)
+    }
+
+    pub fn delete<'s>(&'s mut self, store: &'s Store<E::Database>, k: K) -> Result<(), StoreError> {
+        self.tx.del(store.db, k.as_ref())
+    }
+
+    /// Deletes every entry in `store` in this one transaction, leaving the
+    /// store itself open for further use. Cheaper and less racy than a
+    /// caller enumerating and `delete`-ing each key itself.
+    pub fn clear<'s>(&'s mut self, store: &'s Store<E::Database>) -> Result<(), StoreError> {
+        self.tx.clear(store.db)
+    }
+
+    /// Inserts every `(k, v)` pair from `entries` in this one transaction --
+    /// a single `commit` at the end instead of one per pair is the only
+    /// speedup a write path can offer, since LMDB only ever allows one
+    /// writer at a time. For fanning bulk *reads* out across cores, see
+    /// `Reader::get_many`.
+    pub fn put_many<'s, I>(&'s mut self, store: &'s Store<E::Database>, entries: I) -> Result<(), StoreError>
+    where
+        I: IntoIterator<Item = (K, Value<'s>)>,
+    {
+        for (k, v) in entries {
+            self.put(store, k, &v)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the value already stored under `k`, or, if there is none,
+    /// calls `f`, stores and returns its result. `f` runs at most once, and
+    /// not at all on a hit, all within this one write transaction -- so a
+    /// caller doesn't need to worry about another writer inserting `k`
+    /// between a separate `get` and `put`.
+    pub fn get_or_put_with<'s, F>(&'s mut self, store: &'s Store<E::Database>, k: K, f: F) -> Result<Value<'s>, StoreError>
+    where
+        'env: 's,
+        K: Clone,
+        F: FnOnce() -> Value<'s>,
+    {
+        if let Some(v) = self.get(store, k.clone())? {
+            return Ok(v);
+        }
+        let v = f();
+        self.put(store, k, &v)?;
+        Ok(v)
+    }
+
+    /// Like `get_or_put_with`, but `f` can fail; a failing `f` leaves the
+    /// store unchanged.
+    pub fn get_or_try_put_with<'s, F, Err>(&'s mut self, store: &'s Store<E::Database>, k: K, f: F) -> Result<Value<'s>, Err>
+    where
+        'env: 's,
+        K: Clone,
+        F: FnOnce() -> Result<Value<'s>, Err>,
+        Err: From<StoreError>,
+    {
+        if let Some(v) = self.get(store, k.clone())? {
+            return Ok(v);
+        }
+        let v = f()?;
+        self.put(store, k, &v)?;
+        Ok(v)
+    }
+
+    pub fn get_multi<'s>(&'s self, store: &'s MultiStore<E::Database>, k: K) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        let cursor = self.tx.open_ro_cursor(store.db)?;
+        Ok(Iter {
+            iter: cursor.into_iter_dup_of(k.as_ref()),
+        })
     }
 
-    pub fn delete<'s>(&'s mut self, k: K) -> Result<(), StoreError> {
-        self.tx
-            .del(self.db, &k.as_ref(), None)
-            .map_err(StoreError::LmdbError)
+    /// Returns the first (in dup-sort order) value stored under `k`, or
+    /// `None` if `k` has no values.
+    pub fn get_first<'s>(&'s self, store: &'s MultiStore<E::Database>, k: K) -> Result<Option<Value<'env>>, StoreError>
+    where
+        'env: 's,
+    {
+        match self.get_multi(store, k)?.next() {
+            Some((_, val)) => val,
+            None => Ok(None),
+        }
     }
 
-    pub fn delete_value<'s>(&'s mut self, _k: K, _v: &Value) -> Result<(), StoreError> {
-=======
-    pub fn put<'s>(&'s mut self, store: &'s Store, k: K, v: &Value) -> Result<(), StoreError> {
+    pub fn put_multi(&mut self, store: &MultiStore<E::Database>, k: K, v: &Value) -> Result<(), StoreError> {
+        self.put_multi_with_flags(store, k, v, WriteFlags::empty())
+    }
+
+    pub fn put_multi_with_flags(&mut self, store: &MultiStore<E::Database>, k: K, v: &Value, flags: WriteFlags) -> Result<(), StoreError> {
         // TODO: don't allocate twice.
         let bytes = v.to_bytes()?;
-        self.tx.put(store.db, &k.as_ref(), &bytes, WriteFlags::empty()).map_err(StoreError::LmdbError)
+        self.tx.put(store.db, k.as_ref(), &bytes, flags)
     }
 
-    pub fn delete<'s>(&'s mut self, store: &'s Store, k: K) -> Result<(), StoreError> {
-        self.tx.del(store.db, &k.as_ref(), None).map_err(StoreError::LmdbError)
+    pub fn delete_all(&mut self, store: &MultiStore<E::Database>, k: K) -> Result<(), StoreError> {
+        self.tx.del(store.db, k.as_ref())
     }
 
-    pub fn delete_value<'s>(&'s mut self, _store: &'s Store, _k: K, _v: &Value) -> Result<(), StoreError> {
->>>>>>> central
-        // Even better would be to make this a method only on a dupsort store —
-        // it would need a little bit of reorganizing of types and traits,
-        // but when I see "If the database does not support sorted duplicate
-        // data items (MDB_DUPSORT) the data parameter is ignored" in the docs,
-        // I see a footgun that we can avoid by using the type system.
-        unimplemented!();
+    /// Deletes a single `(k, v)` pair, leaving any other values stored under
+    /// `k` untouched. Only available on a `MultiStore` — this is exactly the
+    /// footgun the old comment here described: calling this on a store that
+    /// isn't dup-sort silently does the wrong thing in LMDB, so the type
+    /// system now refuses to let you try.
+    pub fn delete_value(&mut self, store: &MultiStore<E::Database>, k: K, v: &Value) -> Result<(), StoreError> {
+        let bytes = v.to_bytes()?;
+        self.tx.del_value(store.db, k.as_ref(), &bytes)
     }
 
     pub fn commit(self) -> Result<(), StoreError> {
-        self.tx.commit().map_err(StoreError::LmdbError)
+        self.tx.commit()
     }
 
     pub fn abort(self) {
@@ -165,143 +205,252 @@ where
     }
 }
 
-<<<<<<< HEAD
-impl<'env, K> Reader<'env, K> where K: AsRef<[u8]> {
-    pub fn get<'s>(&'s self, k: K) -> Result<Option<Value<'s>>, StoreError> {
-        let bytes = self.tx.get(self.db, &k.as_ref());
-=======
-impl<'env, K> Reader<'env, K>
+impl<'env, E, K> Reader<'env, E, K>
 where
+    E: BackendEnvironment<'env>,
     K: AsRef<[u8]>,
 {
-    pub(crate) fn new(txn: RoTransaction) -> Reader<K> {
+    pub(crate) fn new(txn: E::RoTransaction) -> Reader<'env, E, K> {
         Reader {
             tx: txn,
             phantom: PhantomData,
         }
     }
 
-    pub fn get<'s>(&'s self, store: &'s Store, k: K) -> Result<Option<Value<'s>>, StoreError> {
-        let bytes = self.tx.get(store.db, &k.as_ref());
->>>>>>> central
-        read_transform(bytes)
+    pub fn get<'s>(&'s self, store: &'s Store<E::Database>, k: K) -> Result<Option<Value<'s>>, StoreError>
+    where
+        'env: 's,
+    {
+        read_transform(BackendRoTransaction::get(&self.tx, store.db, k.as_ref()))
+    }
+
+    /// The number of entries in `store`, read off the backend's own
+    /// metadata rather than by iterating every entry.
+    pub fn entries<'s>(&'s self, store: &'s Store<E::Database>) -> Result<usize, StoreError>
+    where
+        'env: 's,
+    {
+        self.tx.entries(store.db)
+    }
+
+    /// Like `get_many_with_chunk_size`, defaulting `keys_per_job` to roughly
+    /// one chunk per available core.
+    pub fn get_many<'s, I>(&'s self, store: &'s Store<E::Database>, keys: I) -> Vec<Result<Option<Value<'s>>, StoreError>>
+    where
+        'env: 's,
+        I: IntoIterator<Item = K>,
+        K: Clone + Send,
+        E::Database: Sync,
+        E::RoTransaction: Sync,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let keys_per_job = (keys.len() / rayon::current_num_threads().max(1)).max(1);
+        self.get_many_with_chunk_size(store, keys, keys_per_job)
+    }
+
+    /// A read transaction can be shared across threads, so `get_many` looks
+    /// up a whole key set by splitting it into `keys_per_job`-sized chunks
+    /// and resolving each chunk on a rayon thread, preserving the input
+    /// order in the returned `Vec`. Exposed with an explicit chunk size for
+    /// callers who've measured a better value than the per-core default
+    /// `get_many` picks.
+    pub fn get_many_with_chunk_size<'s, I>(&'s self, store: &'s Store<E::Database>, keys: I, keys_per_job: usize) -> Vec<Result<Option<Value<'s>>, StoreError>>
+    where
+        'env: 's,
+        I: IntoIterator<Item = K>,
+        K: Clone + Send,
+        E::Database: Sync,
+        E::RoTransaction: Sync,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        keys.par_chunks(keys_per_job.max(1))
+            .flat_map(|chunk| chunk.iter().map(|k| self.get(store, k.clone())).collect::<Vec<_>>())
+            .collect()
     }
 
     pub fn abort(self) {
         self.tx.abort();
     }
 
-<<<<<<< HEAD
-    pub fn iter_start<'s>(&'s self) -> Result<Iter<'s>, StoreError> {
-        let mut cursor = self.tx.open_ro_cursor(self.db).map_err(StoreError::LmdbError)?;
-=======
-    pub fn iter_start<'s>(&'s self, store: &'s Store) -> Result<Iter<'s>, StoreError> {
-        let mut cursor = self.tx.open_ro_cursor(store.db).map_err(StoreError::LmdbError)?;
->>>>>>> central
-
-        // We call Cursor.iter() instead of Cursor.iter_start() because
-        // the latter panics at "called `Result::unwrap()` on an `Err` value:
-        // NotFound" when there are no items in the store, whereas the former
-        // returns an iterator that yields no items.
-        //
-        // And since we create the Cursor and don't change its position, we can
-        // be sure that a call to Cursor.iter() will start at the beginning.
-        //
-        let iter = cursor.iter();
-
+    pub fn get_multi<'s>(&'s self, store: &'s MultiStore<E::Database>, k: K) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        let cursor = self.tx.open_ro_cursor(store.db)?;
         Ok(Iter {
-<<<<<<< HEAD
-            iter: iter,
-            cursor: cursor,
+            iter: cursor.into_iter_dup_of(k.as_ref()),
         })
     }
 
-    pub fn iter_from<'s>(&'s self, k: K) -> Result<Iter<'s>, StoreError> {
-        let mut cursor = self.tx.open_ro_cursor(self.db).map_err(StoreError::LmdbError)?;
-        let iter = cursor.iter_from(k);
+    /// Returns the first (in dup-sort order) value stored under `k`, or
+    /// `None` if `k` has no values.
+    pub fn get_first<'s>(&'s self, store: &'s MultiStore<E::Database>, k: K) -> Result<Option<Value<'env>>, StoreError>
+    where
+        'env: 's,
+    {
+        match self.get_multi(store, k)?.next() {
+            Some((_, val)) => val,
+            None => Ok(None),
+        }
+    }
+
+    pub fn iter_start<'s>(&'s self, store: &'s Store<E::Database>) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        let cursor = self.tx.open_ro_cursor(store.db)?;
         Ok(Iter {
-            iter: iter,
-            cursor: cursor,
-=======
-            iter,
-            cursor,
+            iter: cursor.into_iter(),
         })
     }
 
-    pub fn iter_from<'s>(&'s self, store: &'s Store, k: K) -> Result<Iter<'s>, StoreError> {
-        let mut cursor = self.tx.open_ro_cursor(store.db).map_err(StoreError::LmdbError)?;
-        let iter = cursor.iter_from(k);
+    pub fn iter_from<'s>(&'s self, store: &'s Store<E::Database>, k: K) -> Result<Iter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        let cursor = self.tx.open_ro_cursor(store.db)?;
         Ok(Iter {
-            iter,
-            cursor,
->>>>>>> central
+            iter: cursor.into_iter_from(k.as_ref()),
         })
     }
-}
 
-impl<'env> Iterator for Iter<'env> {
-    type Item = (&'env [u8], Result<Option<Value<'env>>, StoreError>);
+    /// Iterates every key with the given byte-string `prefix`, in key order,
+    /// stopping as soon as a key without that prefix is reached. LMDB's
+    /// cursor has no native notion of a bounded range, so this is `iter_from`
+    /// plus a `starts_with` check on each key.
+    pub fn iter_prefix<'s>(&'s self, store: &'s Store<E::Database>, prefix: K) -> Result<PrefixIter<'env, E>, StoreError>
+    where
+        'env: 's,
+    {
+        let prefix_bytes = prefix.as_ref().to_vec();
+        let inner = self.iter_from(store, prefix)?;
+        Ok(PrefixIter {
+            inner,
+            prefix: prefix_bytes,
+            done: false,
+        })
+    }
 
-    fn next(&mut self) -> Option<(&'env [u8], Result<Option<Value<'env>>, StoreError>)> {
-        match self.iter.next() {
-            None => None,
-            Some((key, bytes)) => Some((key, read_transform(Ok(bytes)))),
+    /// Releases the snapshot this `Reader` holds -- and, for the LMDB
+    /// backend, the reader-table slot that came with it -- without fully
+    /// tearing down the transaction the way `abort` does. Call `renew` on
+    /// the result to bring it back to life, which is cheaper for read-heavy
+    /// services than opening a brand new `Reader` on every lookup. No
+    /// `get`/`iter_*` can be called in between: the returned type doesn't
+    /// have them.
+    pub fn reset(self) -> InactiveReader<'env, E, K>
+    where
+        E::RoTransaction: BackendRoTransactionReset<'env>,
+    {
+        InactiveReader {
+            tx: self.tx.reset(),
+            phantom: PhantomData,
         }
     }
 }
 
-<<<<<<< HEAD
-/// Wrapper around an `lmdb::Database`.
-pub struct Store<K> where K: AsRef<[u8]> {
-    db: Database,
+/// A `Reader` that has been `reset`: its reader-table slot (for the LMDB
+/// backend) is still reserved, but it holds no snapshot and so can't be used
+/// for `get`/`iter_*` until `renew`ed.
+pub struct InactiveReader<'env, E, K>
+where
+    E: BackendEnvironment<'env>,
+    E::RoTransaction: BackendRoTransactionReset<'env>,
+    K: AsRef<[u8]>,
+{
+    tx: <E::RoTransaction as BackendRoTransactionReset<'env>>::Inactive,
     phantom: PhantomData<K>,
 }
 
-impl<K> Store<K> where K: AsRef<[u8]> {
-    pub fn new(db: Database) -> Store<K> {
-        Store {
-            db: db,
-            phantom: PhantomData,
-        }
+impl<'env, E, K> InactiveReader<'env, E, K>
+where
+    E: BackendEnvironment<'env>,
+    E::RoTransaction: BackendRoTransactionReset<'env>,
+    K: AsRef<[u8]>,
+{
+    /// Picks up the latest committed state, mirroring LMDB's
+    /// `mdb_txn_renew`. The resulting `Reader` sees a fresh, consistent
+    /// snapshot -- not the one that was in effect when `reset` was called.
+    pub fn renew(self) -> Result<Reader<'env, E, K>, StoreError> {
+        Ok(Reader::new(self.tx.renew()?))
     }
+}
 
-    pub fn read<'env>(&self, env: &'env Rkv) -> Result<Reader<'env, K>, StoreError> {
-        let tx = env.read()?;
-        Ok(Reader {
-            tx: tx,
-            db: self.db,
-            phantom: PhantomData,
-        })
-    }
+impl<'env, E> Iterator for Iter<'env, E>
+where
+    E: BackendEnvironment<'env>,
+{
+    type Item = (&'env [u8], Result<Option<Value<'env>>, StoreError>);
 
-    /// Note: there may be only one write transaction active at any given time,
-    /// so this will block if any other writers currently exist for this store.
-    pub fn write<'env>(&self, env: &'env Rkv) -> Result<Writer<'env, K>, lmdb::Error> {
-        let tx = env.write()?;
-        Ok(Writer {
-            tx: tx,
-            db: self.db,
-            phantom: PhantomData,
-        })
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(key, bytes)| (key, read_transform(bytes.map(Some))))
     }
+}
+
+/// An `Iter` bounded to keys sharing a given prefix, as returned by
+/// `Reader::iter_prefix`.
+pub struct PrefixIter<'env, E>
+where
+    E: BackendEnvironment<'env>,
+{
+    inner: Iter<'env, E>,
+    prefix: Vec<u8>,
+    done: bool,
+}
+
+impl<'env, E> Iterator for PrefixIter<'env, E>
+where
+    E: BackendEnvironment<'env>,
+{
+    type Item = (&'env [u8], Result<Option<Value<'env>>, StoreError>);
 
-    pub fn get<'env, 'tx>(&self, tx: &'tx RoTransaction<'env>, k: K) -> Result<Option<Value<'tx>>, StoreError> {
-        let bytes = tx.get(self.db, &k.as_ref());
-        read_transform(bytes)
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next() {
+            Some((key, val)) if key.starts_with(self.prefix.as_slice()) => Some((key, val)),
+            _ => {
+                self.done = true;
+                None
+            },
+        }
     }
-=======
-/// Wrapper around an `lmdb::Database`.  At this time, the underlying LMDB
-/// handle (within lmdb-rs::Database) is a C integer, so Copy is automatic.
+}
+
+/// Wrapper around a backend database handle. At this time, the underlying
+/// LMDB handle (within lmdb-rs::Database) is a C integer, so Copy is
+/// automatic; other backends are expected to keep their handles just as
+/// cheap.
 #[derive(Copy, Clone)]
-pub struct Store {
-    db: Database,
+pub struct Store<D: BackendDatabase> {
+    db: D,
 }
 
-impl Store {
-    pub fn new(db: Database) -> Store {
+impl<D: BackendDatabase> Store<D> {
+    pub fn new(db: D) -> Store<D> {
         Store {
             db,
         }
     }
->>>>>>> central
+}
+
+/// Like `Store`, but opened with the dup-sort flag: a key may have more than
+/// one value. `Writer`/`Reader` expose a distinct set of methods for it
+/// (`get_multi`, `put_multi`, `delete_all`, `delete_value`) so that the
+/// single-value behavior of `Store`'s `get`/`put`/`delete` — and in
+/// particular the single-value-assuming `delete_value`, which used to exist
+/// on `Store` and silently mishandled dup-sort data — aren't reachable on
+/// the wrong kind of store.
+#[derive(Copy, Clone)]
+pub struct MultiStore<D: BackendDatabase> {
+    db: D,
+}
+
+impl<D: BackendDatabase> MultiStore<D> {
+    pub fn new(db: D) -> MultiStore<D> {
+        MultiStore {
+            db,
+        }
+    }
 }
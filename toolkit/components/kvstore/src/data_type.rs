@@ -10,9 +10,11 @@ use libc::uint16_t;
 #[repr(u16)]
 pub enum DataType {
     INT32 = 2,
+    INT64 = 3,
     DOUBLE = 9,
     BOOL = 10,
     VOID = 13,
+    ARRAY = 20,
     WSTRING = 21,
     EMPTY = 255,
 }
@@ -31,8 +33,29 @@ pub enum DataType {
 // seems sufficient.)
 //
 pub const DATA_TYPE_INT32: uint16_t = DataType::INT32 as u16;
+pub const DATA_TYPE_INT64: uint16_t = DataType::INT64 as u16;
 pub const DATA_TYPE_DOUBLE: uint16_t = DataType::DOUBLE as u16;
 pub const DATA_TYPE_BOOL: uint16_t = DataType::BOOL as u16;
 pub const DATA_TYPE_VOID: uint16_t = DataType::VOID as u16;
+pub const DATA_TYPE_ARRAY: uint16_t = DataType::ARRAY as u16;
 pub const DATA_TYPE_WSTRING: uint16_t = DataType::WSTRING as u16;
 pub const DATA_TYPE_EMPTY: uint16_t = DataType::EMPTY as u16;
+
+/// Renders an `nsIVariant::dataType` tag as the `nsXPTTypeTag`/`nsIDataType`
+/// name it corresponds to (e.g. `VTYPE_INT32`), falling back to the
+/// bare number for tags this crate doesn't otherwise recognize -- used by
+/// `KeyValueErrorKind::UnsupportedType`'s `Display` impl so error messages
+/// name the type rather than printing an opaque integer.
+pub fn data_type_name(tag: uint16_t) -> String {
+    match tag {
+        DATA_TYPE_INT32 => "VTYPE_INT32".to_owned(),
+        DATA_TYPE_INT64 => "VTYPE_INT64".to_owned(),
+        DATA_TYPE_DOUBLE => "VTYPE_DOUBLE".to_owned(),
+        DATA_TYPE_BOOL => "VTYPE_BOOL".to_owned(),
+        DATA_TYPE_VOID => "VTYPE_VOID".to_owned(),
+        DATA_TYPE_ARRAY => "VTYPE_ARRAY".to_owned(),
+        DATA_TYPE_WSTRING => "VTYPE_WSTRING".to_owned(),
+        DATA_TYPE_EMPTY => "VTYPE_EMPTY".to_owned(),
+        other => other.to_string(),
+    }
+}
@@ -2,13 +2,16 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use data_type::data_type_name;
+use failure::{Backtrace, Fail};
 use libc::uint16_t;
 use nserror::{
-    nsresult, NS_ERROR_FAILURE, NS_ERROR_NOT_IMPLEMENTED, NS_ERROR_NO_INTERFACE,
+    nsresult, NsresultExt, NS_ERROR_FAILURE, NS_ERROR_NOT_IMPLEMENTED, NS_ERROR_NO_INTERFACE,
     NS_ERROR_NULL_POINTER, NS_ERROR_UNEXPECTED,
 };
 use rkv::StoreError;
 use std::{
+    fmt,
     str::Utf8Error,
     string::FromUtf16Error,
     sync::PoisonError,
@@ -16,7 +19,7 @@ use std::{
 use OwnedValue;
 
 #[derive(Debug, Fail)]
-pub enum KeyValueError {
+pub enum KeyValueErrorKind {
     #[fail(display = "error converting string: {:?}", _0)]
     ConvertBytes(Utf8Error),
 
@@ -26,7 +29,6 @@ pub enum KeyValueError {
     #[fail(display = "no interface '{}'", _0)]
     NoInterface(&'static str),
 
-    // TODO: use nsresult.error_name() to convert the number to its name.
     #[fail(display = "error result '{}'", _0)]
     Nsresult(nsresult),
 
@@ -34,65 +36,194 @@ pub enum KeyValueError {
     NullPointer,
 
     #[fail(display = "poison error getting read/write lock")]
-    PoisonError,
+    PoisonError { backtrace: Backtrace },
 
     #[fail(display = "error reading key/value pair")]
-    Read,
+    Read { backtrace: Backtrace },
 
-    #[fail(display = "store error: {:?}", _0)]
-    StoreError(StoreError),
+    #[fail(display = "store error: {:?}", error)]
+    StoreError {
+        #[fail(cause)]
+        error: StoreError,
+        backtrace: Backtrace,
+    },
+
+    #[fail(display = "unexpected value")]
+    UnexpectedValue,
 
-    // TODO: convert the number to its name.
     #[fail(display = "unsupported type: {}", _0)]
     UnsupportedType(uint16_t),
 
     #[fail(display = "unsupported value: {:?}", _0)]
     UnsupportedValue(OwnedValue),
+
+    #[fail(display = "not supported by the SafeMode backend")]
+    UnsupportedBackend,
 }
 
-impl From<nsresult> for KeyValueError {
-    fn from(result: nsresult) -> KeyValueError {
-        KeyValueError::Nsresult(result)
+impl KeyValueErrorKind {
+    pub fn store_error(error: StoreError) -> KeyValueErrorKind {
+        KeyValueErrorKind::StoreError {
+            error,
+            backtrace: Backtrace::new(),
+        }
+    }
+
+    pub fn read() -> KeyValueErrorKind {
+        KeyValueErrorKind::Read { backtrace: Backtrace::new() }
+    }
+
+    pub fn poison_error() -> KeyValueErrorKind {
+        KeyValueErrorKind::PoisonError { backtrace: Backtrace::new() }
     }
 }
 
-impl From<KeyValueError> for nsresult {
-    fn from(err: KeyValueError) -> nsresult {
-        match err {
-            KeyValueError::ConvertBytes(_) => NS_ERROR_FAILURE,
-            KeyValueError::ConvertString(_) => NS_ERROR_FAILURE,
-            KeyValueError::NoInterface(_) => NS_ERROR_NO_INTERFACE,
-            KeyValueError::Nsresult(result) => result,
-            KeyValueError::NullPointer => NS_ERROR_NULL_POINTER,
-            KeyValueError::PoisonError => NS_ERROR_UNEXPECTED,
-            KeyValueError::Read => NS_ERROR_FAILURE,
-            KeyValueError::StoreError(_) => NS_ERROR_FAILURE,
-            KeyValueError::UnsupportedType(_) => NS_ERROR_NOT_IMPLEMENTED,
-            KeyValueError::UnsupportedValue(_) => NS_ERROR_NOT_IMPLEMENTED,
+/// `KeyValueErrorKind`'s human-readable form, with its two former `// TODO`s
+/// resolved: an `nsresult` renders as its `NS_ERROR_*` name rather than a
+/// bare integer, and an unsupported `nsIVariant` type tag renders as its
+/// `nsIDataType`/`nsXPTTypeTag` name when recognized.
+impl fmt::Display for KeyValueErrorKindDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            KeyValueErrorKind::Nsresult(result) => write!(f, "error result '{}'", result.error_name()),
+            KeyValueErrorKind::UnsupportedType(tag) => write!(f, "unsupported type: {}", data_type_name(*tag)),
+            kind => write!(f, "{}", kind),
         }
     }
 }
 
+struct KeyValueErrorKindDisplay<'a>(&'a KeyValueErrorKind);
+
+impl From<nsresult> for KeyValueErrorKind {
+    fn from(result: nsresult) -> KeyValueErrorKind {
+        KeyValueErrorKind::Nsresult(result)
+    }
+}
+
+impl From<StoreError> for KeyValueErrorKind {
+    fn from(error: StoreError) -> KeyValueErrorKind {
+        KeyValueErrorKind::store_error(error)
+    }
+}
+
+impl From<Utf8Error> for KeyValueErrorKind {
+    fn from(err: Utf8Error) -> KeyValueErrorKind {
+        KeyValueErrorKind::ConvertBytes(err)
+    }
+}
+
+impl From<FromUtf16Error> for KeyValueErrorKind {
+    fn from(err: FromUtf16Error) -> KeyValueErrorKind {
+        KeyValueErrorKind::ConvertString(err)
+    }
+}
+
+impl<T> From<PoisonError<T>> for KeyValueErrorKind {
+    fn from(_: PoisonError<T>) -> KeyValueErrorKind {
+        KeyValueErrorKind::poison_error()
+    }
+}
+
+/// A `KeyValueErrorKind` together with, if a caller attached one via
+/// `with_context`, a description of which key or operation was in flight
+/// when it happened -- mirrors XULStore's `TracedError`/`ResultExt`, giving
+/// `From<KeyValueError> for nsresult` a chain worth logging before it
+/// collapses down to a bare `nsresult` at the XPCOM boundary.
+#[derive(Debug)]
+pub struct KeyValueError {
+    kind: KeyValueErrorKind,
+    context: Option<String>,
+}
+
+impl KeyValueError {
+    pub fn with_context(self, context: impl Into<String>) -> KeyValueError {
+        KeyValueError {
+            context: Some(context.into()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for KeyValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.context {
+            Some(context) => write!(f, "{}: {}", context, KeyValueErrorKindDisplay(&self.kind)),
+            None => write!(f, "{}", KeyValueErrorKindDisplay(&self.kind)),
+        }
+    }
+}
+
+impl Fail for KeyValueError {
+    fn cause(&self) -> Option<&Fail> {
+        self.kind.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.kind.backtrace()
+    }
+}
+
+impl From<KeyValueErrorKind> for KeyValueError {
+    fn from(kind: KeyValueErrorKind) -> KeyValueError {
+        KeyValueError {
+            kind,
+            context: None,
+        }
+    }
+}
+
+impl From<nsresult> for KeyValueError {
+    fn from(result: nsresult) -> KeyValueError {
+        KeyValueErrorKind::from(result).into()
+    }
+}
+
 impl From<StoreError> for KeyValueError {
-    fn from(err: StoreError) -> KeyValueError {
-        KeyValueError::StoreError(err)
+    fn from(error: StoreError) -> KeyValueError {
+        KeyValueErrorKind::from(error).into()
     }
 }
 
 impl From<Utf8Error> for KeyValueError {
     fn from(err: Utf8Error) -> KeyValueError {
-        KeyValueError::ConvertBytes(err)
+        KeyValueErrorKind::from(err).into()
     }
 }
 
 impl From<FromUtf16Error> for KeyValueError {
     fn from(err: FromUtf16Error) -> KeyValueError {
-        KeyValueError::ConvertString(err)
+        KeyValueErrorKind::from(err).into()
     }
 }
 
 impl<T> From<PoisonError<T>> for KeyValueError {
     fn from(err: PoisonError<T>) -> KeyValueError {
-        KeyValueError::PoisonError
+        KeyValueErrorKind::from(err).into()
+    }
+}
+
+impl From<KeyValueError> for nsresult {
+    fn from(err: KeyValueError) -> nsresult {
+        // Log the full chain -- kind plus whatever context a caller attached
+        // via `with_context` -- here, since it's this conversion's last
+        // chance before the caller only has a bare nsresult to go on.
+        if err.context.is_some() {
+            error!("{}", err);
+        }
+
+        match err.kind {
+            KeyValueErrorKind::ConvertBytes(_) => NS_ERROR_FAILURE,
+            KeyValueErrorKind::ConvertString(_) => NS_ERROR_FAILURE,
+            KeyValueErrorKind::NoInterface(_) => NS_ERROR_NO_INTERFACE,
+            KeyValueErrorKind::Nsresult(result) => result,
+            KeyValueErrorKind::NullPointer => NS_ERROR_NULL_POINTER,
+            KeyValueErrorKind::PoisonError { .. } => NS_ERROR_UNEXPECTED,
+            KeyValueErrorKind::Read { .. } => NS_ERROR_FAILURE,
+            KeyValueErrorKind::StoreError { .. } => NS_ERROR_FAILURE,
+            KeyValueErrorKind::UnexpectedValue => NS_ERROR_FAILURE,
+            KeyValueErrorKind::UnsupportedType(_) => NS_ERROR_NOT_IMPLEMENTED,
+            KeyValueErrorKind::UnsupportedValue(_) => NS_ERROR_NOT_IMPLEMENTED,
+            KeyValueErrorKind::UnsupportedBackend => NS_ERROR_NOT_IMPLEMENTED,
+        }
     }
 }
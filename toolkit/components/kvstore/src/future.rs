@@ -0,0 +1,102 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// A Rust-native async surface for in-tree callers who already hold a
+// `KeyValueDatabase` and want to `.await` key-value operations instead of
+// hand-writing an `nsIKeyValue*Callback` implementation.  These methods
+// aren't exposed via nsIKeyValueDatabase (a Future can't cross the XPCOM
+// ABI), so they live here as plain inherent methods rather than
+// `xpcom_method!`s.
+//
+// Internally they run the same rkv work the Get/Put Tasks do,
+// but via moz_task::spawn instead of Task/TaskRunnable: the work still
+// happens on the database thread, and the future still resolves on the
+// thread that called it, via the executor's waker integration.
+
+use error::KeyValueError;
+use moz_task::{spawn, TaskFuture};
+use nserror::nsresult;
+use nsstring::{nsACString, nsCString};
+use owned_value::{owned_to_value, value_to_owned, OwnedValue};
+use rkv::{Rkv, Store};
+use std::{
+    str,
+    sync::{Arc, RwLock},
+};
+use xpcom::{interfaces::nsIEventTarget, RefPtr};
+use KeyValueDatabase;
+
+impl KeyValueDatabase {
+    /// Gets `key`'s value, or `default_value` if it isn't present, without
+    /// going through an `nsIKeyValueVariantCallback`.
+    pub fn get_future(
+        &self,
+        key: &nsACString,
+        default_value: Option<OwnedValue>,
+    ) -> Result<TaskFuture<Result<Option<OwnedValue>, KeyValueError>>, nsresult> {
+        let (rkv, store) = self.as_lmdb()?;
+        get_future(
+            rkv,
+            store,
+            nsCString::from(key),
+            default_value,
+            self.queue.clone(),
+        )
+    }
+
+    /// Writes `key`'s value without going through an `nsIKeyValueVoidCallback`.
+    pub fn put_future(
+        &self,
+        key: &nsACString,
+        value: OwnedValue,
+    ) -> Result<TaskFuture<Result<(), KeyValueError>>, nsresult> {
+        let (rkv, store) = self.as_lmdb()?;
+        put_future(rkv, store, nsCString::from(key), value, self.queue.clone())
+    }
+}
+
+fn get_future(
+    rkv: Arc<RwLock<Rkv>>,
+    store: Store,
+    key: nsCString,
+    default_value: Option<OwnedValue>,
+    queue: RefPtr<nsIEventTarget>,
+) -> Result<TaskFuture<Result<Option<OwnedValue>, KeyValueError>>, nsresult> {
+    spawn(
+        "KVDatabase::GetFuture",
+        queue,
+        move || -> Result<Option<OwnedValue>, KeyValueError> {
+            let key = str::from_utf8(&key)?;
+            let env = rkv.read()?;
+            let reader = env.read()?;
+            let value = reader.get(&store, key)?;
+            if value.is_some() {
+                value_to_owned(value).map(Some)
+            } else {
+                Ok(default_value)
+            }
+        },
+    )
+}
+
+fn put_future(
+    rkv: Arc<RwLock<Rkv>>,
+    store: Store,
+    key: nsCString,
+    value: OwnedValue,
+    queue: RefPtr<nsIEventTarget>,
+) -> Result<TaskFuture<Result<(), KeyValueError>>, nsresult> {
+    spawn(
+        "KVDatabase::PutFuture",
+        queue,
+        move || -> Result<(), KeyValueError> {
+            let key = str::from_utf8(&key)?;
+            let env = rkv.read()?;
+            let mut writer = env.write()?;
+            writer.put(&store, key, &owned_to_value(&value)?)?;
+            writer.commit()?;
+            Ok(())
+        },
+    )
+}
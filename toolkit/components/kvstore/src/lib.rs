@@ -6,7 +6,9 @@
 extern crate failure;
 extern crate libc;
 extern crate lmdb;
+#[macro_use]
 extern crate log;
+extern crate moz_task;
 extern crate nserror;
 extern crate nsstring;
 extern crate ordered_float;
@@ -17,30 +19,35 @@ extern crate xpcom;
 
 mod data_type;
 mod error;
+mod future;
 mod owned_value;
 mod task;
 
-use error::KeyValueError;
+use error::{KeyValueError, KeyValueErrorKind};
 use libc::c_void;
-use nserror::{nsresult, NS_ERROR_FAILURE, NS_ERROR_NO_AGGREGATION, NS_OK};
-use nsstring::{nsACString, nsCString};
+use nserror::{nsresult, NS_ERROR_FAILURE, NS_ERROR_NO_AGGREGATION, NS_ERROR_NO_INTERFACE, NS_OK};
+use nsstring::{nsACString, nsCString, nsString};
 use owned_value::{variant_to_owned, OwnedValue};
 use rkv::{Rkv, Store};
 use std::{
     cell::RefCell,
-    ptr,
+    ptr, slice,
     sync::{Arc, RwLock},
-    vec::IntoIter,
 };
 use storage_variant::IntoVariant;
 use task::{
-    create_thread, DeleteTask, EnumerateTask, GetNextTask, GetOrCreateTask, GetTask,
-    HasMoreElementsTask, HasTask, PutTask, TaskRunnable,
+    create_background_task_queue, ClearTask, CountTask, DeleteRangeTask, DeleteTask, Env,
+    EnumerateLazyTask, EnumerateTask, EnumeratorIter, GetBagTask, GetNextTask, GetOrCreateTask,
+    GetTask, HasMoreElementsTask, HasTask, LazyCursor, PutBagTask, PutTask, TaskRunnable,
+    WriteManyTask, WriteOp,
 };
 use xpcom::{
+    getter_addrefs,
     interfaces::{
-        nsIKeyValueDatabaseCallback, nsIKeyValueEnumeratorCallback, nsIKeyValuePairCallback,
-        nsIKeyValueVariantCallback, nsIKeyValueVoidCallback, nsISupports, nsIThread, nsIVariant,
+        nsIEventTarget, nsIKeyValueDatabaseCallback, nsIKeyValueEnumeratorCallback,
+        nsIKeyValuePairCallback, nsIKeyValuePropertyBagCallback, nsIKeyValueVariantCallback,
+        nsIKeyValueVoidCallback, nsIProperty, nsIPropertyBag, nsISimpleEnumerator, nsISupports,
+        nsIVariant,
     },
     nsIID, Ensure, RefPtr,
 };
@@ -98,7 +105,7 @@ impl KeyValueService {
         GetOrCreate,
         get_or_create,
         { callback: *const nsIKeyValueDatabaseCallback, path: *const nsACString,
-            name: *const nsACString }
+            name: *const nsACString, safe_mode: bool }
     );
 
     fn get_or_create(
@@ -106,14 +113,18 @@ impl KeyValueService {
         callback: &nsIKeyValueDatabaseCallback,
         path: &nsACString,
         name: &nsACString,
+        safe_mode: bool,
     ) -> Result<(), nsresult> {
-        let target = create_thread("KeyValDB")?;
+        let target = create_background_task_queue("KeyValDB")?
+            .query_interface::<nsIEventTarget>()
+            .ok_or(NS_ERROR_NO_INTERFACE)?;
 
         let task = Box::new(GetOrCreateTask::new(
             RefPtr::new(callback),
             target.clone(),
             nsCString::from(path),
             nsCString::from(name),
+            safe_mode,
         ));
 
         TaskRunnable::new("KVService::GetOrCreate", task)?.dispatch(target)
@@ -124,18 +135,24 @@ impl KeyValueService {
 #[xpimplements(nsIKeyValueDatabase)]
 #[refcnt = "atomic"]
 pub struct InitKeyValueDatabase {
-    rkv: Arc<RwLock<Rkv>>,
-    store: Store,
-    thread: RefPtr<nsIThread>,
+    env: Env,
+    queue: RefPtr<nsIEventTarget>,
 }
 
 impl KeyValueDatabase {
-    fn new(
-        rkv: Arc<RwLock<Rkv>>,
-        store: Store,
-        thread: RefPtr<nsIThread>,
-    ) -> RefPtr<KeyValueDatabase> {
-        KeyValueDatabase::allocate(InitKeyValueDatabase { rkv, store, thread })
+    fn new(env: Env, queue: RefPtr<nsIEventTarget>) -> RefPtr<KeyValueDatabase> {
+        KeyValueDatabase::allocate(InitKeyValueDatabase { env, queue })
+    }
+
+    /// Gives the Lmdb-backed `Rkv`/`Store` pair behind this database, for
+    /// the operations that haven't been generalized over `Env` yet (see
+    /// `task::Env`'s doc comment). Fails for a SafeMode-backed database,
+    /// rather than silently falling back to a different store.
+    fn as_lmdb(&self) -> Result<(Arc<RwLock<Rkv>>, Store), KeyValueError> {
+        match &self.env {
+            Env::Lmdb(rkv, store) => Ok((Arc::clone(rkv), *store)),
+            Env::SafeMode(..) => Err(KeyValueErrorKind::UnsupportedBackend.into()),
+        }
     }
 
     xpcom_method!(
@@ -153,18 +170,118 @@ impl KeyValueDatabase {
     ) -> Result<(), nsresult> {
         let value = match variant_to_owned(value)? {
             Some(value) => Ok(value),
-            None => Err(KeyValueError::UnexpectedValue),
+            None => Err(KeyValueErrorKind::UnexpectedValue),
         }?;
 
         let task = Box::new(PutTask::new(
             RefPtr::new(callback),
-            Arc::clone(&self.rkv),
-            self.store,
+            self.env.clone(),
             nsCString::from(key),
             value,
         ));
 
-        TaskRunnable::new("KVDatabase::Put", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVDatabase::Put", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(
+        WriteMany,
+        write_many,
+        { callback: *const nsIKeyValueVoidCallback, count: u32,
+            keys: *const *const nsACString, values: *const *const nsIVariant }
+    );
+
+    // Writes or deletes several key/value pairs as a single atomic rkv
+    // transaction: a null `values[i]` deletes `keys[i]` rather than setting
+    // it.  We parse the incoming arrays into owned values here, on the main
+    // thread, so the task itself doesn't need to touch the nsIVariants.
+    fn write_many(
+        &self,
+        callback: &nsIKeyValueVoidCallback,
+        count: u32,
+        keys: *const *const nsACString,
+        values: *const *const nsIVariant,
+    ) -> Result<(), nsresult> {
+        let keys = unsafe { slice::from_raw_parts(keys, count as usize) };
+        let values = unsafe { slice::from_raw_parts(values, count as usize) };
+
+        let ops = keys
+            .iter()
+            .zip(values.iter())
+            .map(|(&key, &value)| {
+                let key = nsCString::from(unsafe { &*key });
+                Ok(match variant_to_owned(unsafe { &*value })? {
+                    Some(value) => WriteOp::Put(key, value),
+                    None => WriteOp::Delete(key),
+                })
+            }).collect::<Result<Vec<_>, KeyValueError>>()?;
+
+        let (rkv, store) = self.as_lmdb()?;
+        let task = Box::new(WriteManyTask::new(RefPtr::new(callback), rkv, store, ops));
+
+        TaskRunnable::new("KVDatabase::WriteMany", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(
+        PutBag,
+        put_bag,
+        { callback: *const nsIKeyValueVoidCallback, key: *const nsACString,
+            bag: *const nsIPropertyBag }
+    );
+
+    // Stores a property bag's scalar properties as a single key's value, so
+    // callers don't have to invent their own key-prefix encoding for small
+    // records.  As with write_many, we walk the bag -- which, being an
+    // nsIPropertyBag, may not be safe to touch off the main thread -- here,
+    // converting each property through variant_to_owned before dispatching.
+    fn put_bag(
+        &self,
+        callback: &nsIKeyValueVoidCallback,
+        key: &nsACString,
+        bag: &nsIPropertyBag,
+    ) -> Result<(), nsresult> {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation, same as
+        // write_many above.
+        let properties = (|| -> Result<Vec<(String, OwnedValue)>, KeyValueError> {
+            let enumerator: RefPtr<nsISimpleEnumerator> =
+                getter_addrefs(|p| unsafe { bag.GetEnumerator(p) })?;
+
+            let mut properties = Vec::new();
+            loop {
+                let mut has_more = false;
+                unsafe { enumerator.HasMoreElements(&mut has_more) }.to_result()?;
+                if !has_more {
+                    break;
+                }
+
+                let element: RefPtr<nsISupports> =
+                    getter_addrefs(|p| unsafe { enumerator.GetNext(p) })?;
+                let property: RefPtr<nsIProperty> = element
+                    .query_interface()
+                    .ok_or(KeyValueErrorKind::NoInterface("nsIProperty"))?;
+
+                let mut name = nsString::new();
+                unsafe { property.GetName(&mut *name) }.to_result()?;
+                let value: RefPtr<nsIVariant> =
+                    getter_addrefs(|p| unsafe { property.GetValue(p) })?;
+                let value = variant_to_owned(&value)?.ok_or(KeyValueErrorKind::UnexpectedValue)?;
+
+                properties.push((String::from_utf16(&name)?, value));
+            }
+
+            Ok(properties)
+        })()?;
+
+        let (rkv, store) = self.as_lmdb()?;
+        let task = Box::new(PutBagTask::new(
+            RefPtr::new(callback),
+            rkv,
+            store,
+            nsCString::from(key),
+            properties,
+        ));
+
+        TaskRunnable::new("KVDatabase::PutBag", task)?.dispatch(self.queue.clone())
     }
 
     xpcom_method!(
@@ -182,13 +299,34 @@ impl KeyValueDatabase {
     ) -> Result<(), nsresult> {
         let task = Box::new(GetTask::new(
             RefPtr::new(callback),
-            Arc::clone(&self.rkv),
-            self.store,
+            self.env.clone(),
             nsCString::from(key),
             variant_to_owned(default_value)?,
         ));
 
-        TaskRunnable::new("KVDatabase::Get", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVDatabase::Get", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(
+        GetBag,
+        get_bag,
+        { callback: *const nsIKeyValuePropertyBagCallback, key: *const nsACString }
+    );
+
+    fn get_bag(
+        &self,
+        callback: &nsIKeyValuePropertyBagCallback,
+        key: &nsACString,
+    ) -> Result<(), nsresult> {
+        let (rkv, store) = self.as_lmdb()?;
+        let task = Box::new(GetBagTask::new(
+            RefPtr::new(callback),
+            rkv,
+            store,
+            nsCString::from(key),
+        ));
+
+        TaskRunnable::new("KVDatabase::GetBag", task)?.dispatch(self.queue.clone())
     }
 
     xpcom_method!(
@@ -200,12 +338,11 @@ impl KeyValueDatabase {
     fn has(&self, callback: &nsIKeyValueVariantCallback, key: &nsACString) -> Result<(), nsresult> {
         let task = Box::new(HasTask::new(
             RefPtr::new(callback),
-            Arc::clone(&self.rkv),
-            self.store,
+            self.env.clone(),
             nsCString::from(key),
         ));
 
-        TaskRunnable::new("KVDatabase::Has", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVDatabase::Has", task)?.dispatch(self.queue.clone())
     }
 
     xpcom_method!(
@@ -217,36 +354,142 @@ impl KeyValueDatabase {
     fn delete(&self, callback: &nsIKeyValueVoidCallback, key: &nsACString) -> Result<(), nsresult> {
         let task = Box::new(DeleteTask::new(
             RefPtr::new(callback),
-            Arc::clone(&self.rkv),
-            self.store,
+            self.env.clone(),
             nsCString::from(key),
         ));
 
-        TaskRunnable::new("KVDatabase::Delete", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVDatabase::Delete", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(Clear, clear, { callback: *const nsIKeyValueVoidCallback });
+
+    // Empties the store in one transaction, rather than requiring a caller
+    // to enumerate and Delete each key itself -- both slow and racy against
+    // concurrent writers for a store a caller treats as a periodically-reset
+    // cache.
+    fn clear(&self, callback: &nsIKeyValueVoidCallback) -> Result<(), nsresult> {
+        let task = Box::new(ClearTask::new(RefPtr::new(callback), self.env.clone()));
+
+        TaskRunnable::new("KVDatabase::Clear", task)?.dispatch(self.queue.clone())
     }
 
     xpcom_method!(
         Enumerate,
         enumerate,
         { callback: *const nsIKeyValueEnumeratorCallback, from_key: *const nsACString,
-            to_key: *const nsACString }
+            to_key: *const nsACString, limit: u64, offset: u64, reverse: bool }
     );
 
+    // limit/offset page through [from_key, to_key) without materializing the
+    // whole range more than once; 0 means "unbounded" for limit, matching
+    // the "empty string means unbounded" convention from_key/to_key already
+    // use.  reverse flips the already-bounded-and-paged page, rather than
+    // walking the cursor backwards -- see EnumerateTask::run's comment.
     fn enumerate(
         &self,
         callback: &nsIKeyValueEnumeratorCallback,
         from_key: &nsACString,
         to_key: &nsACString,
+        limit: u64,
+        offset: u64,
+        reverse: bool,
     ) -> Result<(), nsresult> {
         let task = Box::new(EnumerateTask::new(
             RefPtr::new(callback),
-            Arc::clone(&self.rkv),
-            self.store,
+            self.env.clone(),
+            nsCString::from(from_key),
+            nsCString::from(to_key),
+            limit,
+            offset,
+            reverse,
+            self.queue.clone(),
+        ));
+
+        TaskRunnable::new("KVDatabase::Enumerate", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(
+        EnumerateLazy,
+        enumerate_lazy,
+        { callback: *const nsIKeyValueEnumeratorCallback, from_key: *const nsACString,
+            to_key: *const nsACString }
+    );
+
+    // Like `enumerate`, but the resulting KeyValueEnumerator streams pairs
+    // from a live cursor instead of pre-collecting the whole range, so
+    // large scans don't have to fit in memory up front.
+    fn enumerate_lazy(
+        &self,
+        callback: &nsIKeyValueEnumeratorCallback,
+        from_key: &nsACString,
+        to_key: &nsACString,
+    ) -> Result<(), nsresult> {
+        let (rkv, store) = self.as_lmdb()?;
+        let task = Box::new(EnumerateLazyTask::new(
+            RefPtr::new(callback),
+            rkv,
+            store,
+            nsCString::from(from_key),
+            nsCString::from(to_key),
+            self.queue.clone(),
+        ));
+
+        TaskRunnable::new("KVDatabase::EnumerateLazy", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(
+        Count,
+        count,
+        { callback: *const nsIKeyValueVariantCallback, from_key: *const nsACString,
+            to_key: *const nsACString }
+    );
+
+    // Counts keys in [from_key, to_key) via a single read transaction,
+    // instead of making callers enumerate the range themselves just to
+    // total it up. The unbounded case (no from_key/to_key) skips iterating
+    // entirely -- see CountTask::run.
+    fn count(
+        &self,
+        callback: &nsIKeyValueVariantCallback,
+        from_key: &nsACString,
+        to_key: &nsACString,
+    ) -> Result<(), nsresult> {
+        let task = Box::new(CountTask::new(
+            RefPtr::new(callback),
+            self.env.clone(),
+            nsCString::from(from_key),
+            nsCString::from(to_key),
+        ));
+
+        TaskRunnable::new("KVDatabase::Count", task)?.dispatch(self.queue.clone())
+    }
+
+    xpcom_method!(
+        DeleteRange,
+        delete_range,
+        { callback: *const nsIKeyValueVoidCallback, from_key: *const nsACString,
+            to_key: *const nsACString }
+    );
+
+    // Deletes keys in [from_key, to_key) inside a single write transaction,
+    // instead of making callers enumerate the range and delete one key at
+    // a time.
+    fn delete_range(
+        &self,
+        callback: &nsIKeyValueVoidCallback,
+        from_key: &nsACString,
+        to_key: &nsACString,
+    ) -> Result<(), nsresult> {
+        let (rkv, store) = self.as_lmdb()?;
+        let task = Box::new(DeleteRangeTask::new(
+            RefPtr::new(callback),
+            rkv,
+            store,
             nsCString::from(from_key),
             nsCString::from(to_key),
         ));
 
-        TaskRunnable::new("KVDatabase::Enumerate", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVDatabase::DeleteRange", task)?.dispatch(self.queue.clone())
     }
 }
 
@@ -254,28 +497,28 @@ impl KeyValueDatabase {
 #[xpimplements(nsIKeyValueEnumerator)]
 #[refcnt = "atomic"]
 pub struct InitKeyValueEnumerator {
-    thread: RefPtr<nsIThread>,
-    iter: Arc<
-        RefCell<
-            IntoIter<(
-                Result<String, KeyValueError>,
-                Result<OwnedValue, KeyValueError>,
-            )>,
-        >,
-    >,
+    queue: RefPtr<nsIEventTarget>,
+    iter: Arc<RefCell<EnumeratorIter>>,
 }
 
 impl KeyValueEnumerator {
     fn new(
-        thread: RefPtr<nsIThread>,
+        queue: RefPtr<nsIEventTarget>,
         pairs: Vec<(
             Result<String, KeyValueError>,
             Result<OwnedValue, KeyValueError>,
         )>,
     ) -> RefPtr<KeyValueEnumerator> {
         KeyValueEnumerator::allocate(InitKeyValueEnumerator {
-            thread,
-            iter: Arc::new(RefCell::new(pairs.into_iter())),
+            queue,
+            iter: Arc::new(RefCell::new(EnumeratorIter::Eager(pairs.into_iter()))),
+        })
+    }
+
+    fn new_lazy(queue: RefPtr<nsIEventTarget>, cursor: LazyCursor) -> RefPtr<KeyValueEnumerator> {
+        KeyValueEnumerator::allocate(InitKeyValueEnumerator {
+            queue,
+            iter: Arc::new(RefCell::new(EnumeratorIter::Lazy(cursor))),
         })
     }
 
@@ -289,7 +532,7 @@ impl KeyValueEnumerator {
             self.iter.clone(),
         ));
 
-        TaskRunnable::new("KVEnumerator::HasMoreElements", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVEnumerator::HasMoreElements", task)?.dispatch(self.queue.clone())
     }
 
     xpcom_method!(GetNext, get_next, {
@@ -299,7 +542,7 @@ impl KeyValueEnumerator {
     fn get_next(&self, callback: &nsIKeyValuePairCallback) -> Result<(), nsresult> {
         let task = Box::new(GetNextTask::new(RefPtr::new(callback), self.iter.clone()));
 
-        TaskRunnable::new("KVEnumerator::GetNext", task)?.dispatch(self.thread.clone())
+        TaskRunnable::new("KVEnumerator::GetNext", task)?.dispatch(self.queue.clone())
     }
 }
 
@@ -328,7 +571,7 @@ impl KeyValuePair {
             .value
             .clone()
             .into_variant()
-            .ok_or(KeyValueError::from(NS_ERROR_FAILURE))?
+            .ok_or(KeyValueErrorKind::from(NS_ERROR_FAILURE))?
             .take())
     }
 }
@@ -2,46 +2,208 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use error::KeyValueError;
-use libc::int32_t;
+use error::{KeyValueError, KeyValueErrorKind};
+use libc::{c_void, free, int32_t, int64_t, uint16_t, uint32_t, uint64_t};
 use nserror::NsresultExt;
 use nsstring::nsString;
 use ordered_float::OrderedFloat;
-use rkv::{OwnedValue, Value};
+use rkv::Value;
+use std::{ptr, slice};
 use storage_variant::{
-    GetDataType, VariantType, DATA_TYPE_BOOL, DATA_TYPE_DOUBLE, DATA_TYPE_EMPTY, DATA_TYPE_INT32,
+    GetDataType, IntoVariant, Variant, VariantType, DATA_TYPE_ARRAY, DATA_TYPE_BOOL,
+    DATA_TYPE_DOUBLE, DATA_TYPE_EMPTY, DATA_TYPE_INT32, DATA_TYPE_INT64, DATA_TYPE_UINT64,
     DATA_TYPE_VOID, DATA_TYPE_WSTRING,
 };
-use xpcom::{interfaces::nsIVariant, RefPtr};
+use xpcom::{interfaces::nsIVariant, nsIID};
+
+// The per-element type tags nsIVariant::GetAsArray reports for an array of
+// signed or unsigned bytes, i.e. the shape a byte array (blob) or JS typed
+// array (Int8Array/Uint8Array) takes once it crosses into an nsIVariant.
+const ARRAY_TYPE_INT8: uint16_t = 0; // nsIDataType::VTYPE_INT8
+const ARRAY_TYPE_UINT8: uint16_t = 4; // nsIDataType::VTYPE_UINT8
+
+// This is implemented in rkv but is incomplete there. We implement a subset
+// to give KeyValuePair ownership over its value, so it can #[derive(xpcom)].
+//
+// Blob rounds trips through rkv's Value::Blob (see owned_to_value/
+// value_to_owned below) and through nsIVariant as a uint8 byte array (see
+// variant_to_owned and the IntoVariant impl below), so Get/Put/Enumerate
+// already support storing and retrieving arbitrary bytes, not just the
+// scalar types.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OwnedValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(OrderedFloat<f64>),
+    Str(String),
+    Blob(Vec<u8>),
+    Bag(Vec<(String, OwnedValue)>),
+}
 
 pub fn value_to_owned(value: Option<Value>) -> Result<OwnedValue, KeyValueError> {
     match value {
         Some(Value::Bool(val)) => Ok(OwnedValue::Bool(val)),
         Some(Value::I64(val)) => Ok(OwnedValue::I64(val)),
+        Some(Value::U64(val)) => Ok(OwnedValue::U64(val)),
         Some(Value::F64(val)) => Ok(OwnedValue::F64(val)),
         Some(Value::Str(val)) => Ok(OwnedValue::Str(val.to_owned())),
-        Some(_value) => Err(KeyValueError::UnexpectedValue),
-        None => Err(KeyValueError::UnexpectedValue),
+        Some(Value::Blob(val)) => Ok(OwnedValue::Blob(val.to_owned())),
+        Some(_value) => Err(KeyValueErrorKind::UnexpectedValue.into()),
+        None => Err(KeyValueErrorKind::UnexpectedValue.into()),
     }
 }
 
-pub fn owned_to_variant(owned: OwnedValue) -> RefPtr<nsIVariant> {
+/// The opposite of `value_to_owned`: borrows out of an `OwnedValue` rather
+/// than allocating, so callers (e.g. `PutTask`) can write an `OwnedValue`
+/// back to the store without re-deriving its `Value` by hand.  Fails for
+/// `OwnedValue::Bag`, which has no single-`Value` representation --
+/// `PutBagTask` serializes a bag's properties into a blob itself instead.
+pub fn owned_to_value(owned: &OwnedValue) -> Result<Value, KeyValueError> {
     match owned {
-        OwnedValue::Bool(val) => val.into_variant(),
-        OwnedValue::I64(val) => val.into_variant(),
-        OwnedValue::F64(OrderedFloat(val)) => val.into_variant(),
-        OwnedValue::Str(ref val) => nsString::from(val).into_variant(),
-
-        // NB: kvstore doesn't support these types of OwnedValue, but we still
-        // have to match them in order to be an exhaustive pattern.
-        OwnedValue::Instant(val) => val.into_variant(),
-        OwnedValue::Json(ref val) => nsString::from(val).into_variant(),
-        OwnedValue::U64(_) => panic!("not supported; shouldn't happen"),
-        OwnedValue::Uuid(_) => panic!("not supported; shouldn't happen"),
-        OwnedValue::Blob(_) => panic!("not supported; shouldn't happen"),
+        OwnedValue::Bool(val) => Ok(Value::Bool(*val)),
+        OwnedValue::I64(val) => Ok(Value::I64(*val)),
+        OwnedValue::U64(val) => Ok(Value::U64(*val)),
+        OwnedValue::F64(val) => Ok(Value::F64(*val)),
+        OwnedValue::Str(val) => Ok(Value::Str(val)),
+        OwnedValue::Blob(val) => Ok(Value::Blob(val)),
+        OwnedValue::Bag(_) => Err(KeyValueErrorKind::UnsupportedValue(owned.clone()).into()),
+    }
+}
+
+impl IntoVariant for OwnedValue {
+    fn into_variant(self) -> Option<Variant> {
+        match self {
+            OwnedValue::Bool(val) => Some(val.into_variant().into()),
+            OwnedValue::I64(val) => Some(val.into_variant().into()),
+            OwnedValue::U64(val) => Some(val.into_variant().into()),
+            OwnedValue::F64(OrderedFloat(val)) => Some(val.into_variant().into()),
+            OwnedValue::Str(ref val) => Some(nsString::from(val).into_variant().into()),
+            OwnedValue::Blob(ref val) => Some(val.into_variant().into()),
+            // A property bag has no single-variant representation; GetBag
+            // returns it as an nsIPropertyBag instead of an nsIVariant.
+            OwnedValue::Bag(_) => None,
+        }
     }
 }
 
+/// Serializes a bag's scalar properties into a single byte blob, so `PutBag`
+/// can store the whole record under one rkv key.  No serialization crate is
+/// vendored here, so the format is a deliberately simple one: a `u32` count,
+/// followed by, per property, a length-prefixed name, a one-byte type tag,
+/// and that type's payload.  Properties that are themselves bags are
+/// dropped; `PutBag` only accepts scalar properties.
+pub fn encode_bag(properties: &[(String, OwnedValue)]) -> Vec<u8> {
+    let is_scalar = |value: &OwnedValue| match value {
+        OwnedValue::Bag(_) => false,
+        _ => true,
+    };
+    let count = properties.iter().filter(|(_, value)| is_scalar(value)).count();
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(count as u32).to_le_bytes());
+
+    for (name, value) in properties.iter().filter(|(_, value)| is_scalar(value)) {
+        encode_bytes(&mut bytes, name.as_bytes());
+        match value {
+            OwnedValue::Bool(val) => {
+                bytes.push(0);
+                bytes.push(*val as u8);
+            }
+            OwnedValue::I64(val) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&val.to_le_bytes());
+            }
+            OwnedValue::U64(val) => {
+                bytes.push(2);
+                bytes.extend_from_slice(&val.to_le_bytes());
+            }
+            OwnedValue::F64(OrderedFloat(val)) => {
+                bytes.push(3);
+                bytes.extend_from_slice(&val.to_bits().to_le_bytes());
+            }
+            OwnedValue::Str(val) => {
+                bytes.push(4);
+                encode_bytes(&mut bytes, val.as_bytes());
+            }
+            OwnedValue::Blob(val) => {
+                bytes.push(5);
+                encode_bytes(&mut bytes, val);
+            }
+            OwnedValue::Bag(_) => {}
+        }
+    }
+
+    bytes
+}
+
+/// The opposite of `encode_bag`.
+pub fn decode_bag(bytes: &[u8]) -> Result<Vec<(String, OwnedValue)>, KeyValueError> {
+    let mut pos = 0;
+    let count = read_u32(bytes, &mut pos)? as usize;
+    let mut properties = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let name = read_string(bytes, &mut pos)?;
+        let value = match read_u8(bytes, &mut pos)? {
+            0 => OwnedValue::Bool(read_u8(bytes, &mut pos)? != 0),
+            1 => OwnedValue::I64(read_u64(bytes, &mut pos)? as i64),
+            2 => OwnedValue::U64(read_u64(bytes, &mut pos)?),
+            3 => OwnedValue::F64(OrderedFloat(f64::from_bits(read_u64(bytes, &mut pos)?))),
+            4 => OwnedValue::Str(read_string(bytes, &mut pos)?),
+            5 => OwnedValue::Blob(read_bytes(bytes, &mut pos)?.to_vec()),
+            _tag => return Err(KeyValueErrorKind::read().into()),
+        };
+        properties.push((name, value));
+    }
+
+    Ok(properties)
+}
+
+fn encode_bytes(bytes: &mut Vec<u8>, val: &[u8]) {
+    bytes.extend_from_slice(&(val.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(val);
+}
+
+fn read_u8(bytes: &[u8], pos: &mut usize) -> Result<u8, KeyValueError> {
+    let byte = *bytes.get(*pos).ok_or(KeyValueErrorKind::read())?;
+    *pos += 1;
+    Ok(byte)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, KeyValueError> {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(read_bytes_of_len(bytes, pos, 4)?);
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(bytes: &[u8], pos: &mut usize) -> Result<u64, KeyValueError> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(read_bytes_of_len(bytes, pos, 8)?);
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes_of_len<'a>(
+    bytes: &'a [u8],
+    pos: &mut usize,
+    len: usize,
+) -> Result<&'a [u8], KeyValueError> {
+    let end = pos.checked_add(len).ok_or(KeyValueErrorKind::read())?;
+    let slice = bytes.get(*pos..end).ok_or(KeyValueErrorKind::read())?;
+    *pos = end;
+    Ok(slice)
+}
+
+fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize) -> Result<&'a [u8], KeyValueError> {
+    let len = read_u32(bytes, pos)? as usize;
+    read_bytes_of_len(bytes, pos, len)
+}
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> Result<String, KeyValueError> {
+    String::from_utf8(read_bytes(bytes, pos)?.to_vec())
+        .map_err(|err| KeyValueErrorKind::ConvertBytes(err.utf8_error()).into())
+}
+
 pub fn variant_to_owned(variant: &nsIVariant) -> Result<Option<OwnedValue>, KeyValueError> {
     let data_type = variant.get_data_type();
 
@@ -51,6 +213,16 @@ pub fn variant_to_owned(variant: &nsIVariant) -> Result<Option<OwnedValue>, KeyV
             unsafe { variant.GetAsInt32(&mut val) }.to_result()?;
             Ok(Some(OwnedValue::I64(val.into())))
         }
+        DATA_TYPE_INT64 => {
+            let mut val: int64_t = 0;
+            unsafe { variant.GetAsInt64(&mut val) }.to_result()?;
+            Ok(Some(OwnedValue::I64(val)))
+        }
+        DATA_TYPE_UINT64 => {
+            let mut val: uint64_t = 0;
+            unsafe { variant.GetAsUint64(&mut val) }.to_result()?;
+            Ok(Some(OwnedValue::U64(val)))
+        }
         DATA_TYPE_DOUBLE => {
             let mut val: f64 = 0.0;
             unsafe { variant.GetAsDouble(&mut val) }.to_result()?;
@@ -67,7 +239,28 @@ pub fn variant_to_owned(variant: &nsIVariant) -> Result<Option<OwnedValue>, KeyV
             unsafe { variant.GetAsBool(&mut val) }.to_result()?;
             Ok(Some(OwnedValue::Bool(val)))
         }
+        // A byte array (a blob, or a JS typed array such as Uint8Array)
+        // arrives as DATA_TYPE_ARRAY with a uint8 (or int8) element type;
+        // anything else we don't have an OwnedValue representation for.
+        DATA_TYPE_ARRAY => {
+            let mut element_type: uint16_t = 0;
+            let mut iid = nsIID::default();
+            let mut count: uint32_t = 0;
+            let mut elements: *mut c_void = ptr::null_mut();
+            unsafe { variant.GetAsArray(&mut element_type, &mut iid, &mut count, &mut elements) }
+                .to_result()?;
+
+            if element_type != ARRAY_TYPE_UINT8 && element_type != ARRAY_TYPE_INT8 {
+                unsafe { free(elements) };
+                return Err(KeyValueErrorKind::UnsupportedType(element_type).into());
+            }
+
+            let bytes =
+                unsafe { slice::from_raw_parts(elements as *const u8, count as usize) }.to_vec();
+            unsafe { free(elements) };
+            Ok(Some(OwnedValue::Blob(bytes)))
+        }
         DATA_TYPE_EMPTY | DATA_TYPE_VOID => Ok(None),
-        unsupported_type => Err(KeyValueError::UnsupportedType(unsupported_type)),
+        unsupported_type => Err(KeyValueErrorKind::UnsupportedType(unsupported_type).into()),
     }
 }
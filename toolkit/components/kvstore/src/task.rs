@@ -5,27 +5,33 @@
 extern crate xpcom;
 
 use crossbeam_utils::atomic::AtomicCell;
-use error::KeyValueError;
-use moz_task::{get_main_thread, is_main_thread};
-use nserror::{nsresult, NsresultExt, NS_ERROR_FAILURE, NS_OK};
+use error::{KeyValueError, KeyValueErrorKind};
+pub use moz_task::create_background_task_queue;
+use moz_task::get_current_thread;
+use nserror::{nsresult, NsresultExt, NS_ERROR_FAILURE, NS_ERROR_NO_INTERFACE, NS_OK};
 use nsstring::{nsACString, nsCString, nsString};
-use owned_value::{value_to_owned, OwnedValue};
-use rkv::{Manager, Rkv, Store, StoreError, Value};
+use owned_value::{decode_bag, encode_bag, owned_to_value, value_to_owned, OwnedValue};
+use rkv::backend::{LmdbEnvironment, SafeModeDatabase, SafeModeEnvironment};
+use rkv::{Iter, Manager, Reader, Rkv, Store, StoreError, Value};
 use std::{
     cell::Cell,
+    mem,
     path::Path,
     str,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        Arc, RwLock, RwLockReadGuard,
     },
+    vec::IntoIter,
 };
 use storage_variant::VariantType;
 use threadbound::ThreadBound;
 use xpcom::{
+    create_instance,
     interfaces::{
         nsIEventTarget, nsIKeyValueDatabaseCallback, nsIKeyValueEnumeratorCallback,
-        nsIKeyValueVariantCallback, nsIKeyValueVoidCallback, nsIThread, nsIVariant,
+        nsIKeyValuePropertyBagCallback, nsIKeyValueVariantCallback, nsIKeyValueVoidCallback,
+        nsIVariant, nsIWritablePropertyBag,
     },
     RefPtr,
 };
@@ -76,6 +82,33 @@ macro_rules! task_done {
     };
 }
 
+/// Which storage engine backs a `KeyValueDatabase`: the default, mmap-based
+/// `LmdbEnvironment`, or the pure-Rust `SafeModeEnvironment`, for platforms
+/// or sandboxes where mmap-based LMDB is problematic. `KeyValueDatabase`
+/// can't be generic over this itself -- `#[derive(xpcom)]` doesn't support
+/// generic types -- so it, and the Tasks that operate on it, carry this enum
+/// instead of picking a backend at the type level. Each variant bundles the
+/// `Rkv` handle with the `Store` opened from it, so the two can never be
+/// mismatched.
+#[derive(Clone)]
+pub enum Env {
+    Lmdb(Arc<RwLock<Rkv<LmdbEnvironment>>>, Store),
+    SafeMode(Arc<RwLock<Rkv<SafeModeEnvironment>>>, Store<SafeModeDatabase>),
+}
+
+/// Runs `$body` with `$rkv`/`$store` bound to the opened `Rkv`/`Store` pair
+/// for whichever backend `$env` holds. Both arms run the same code, just
+/// monomorphized over a different backend, since `Env`'s variants can't
+/// share a match arm (their bound types differ).
+macro_rules! with_env {
+    ($env:expr, |$rkv:ident, $store:ident| $body:expr) => {
+        match $env {
+            Env::Lmdb($rkv, $store) => $body,
+            Env::SafeMode($rkv, $store) => $body,
+        }
+    };
+}
+
 /// A database operation that is executed asynchronously on a database thread
 /// and returns its result to the original thread from which it was dispatched.
 pub trait Task {
@@ -84,8 +117,8 @@ pub trait Task {
 }
 
 /// The struct responsible for dispatching a Task by calling its run() method
-/// on the target thread and returning its result by calling its done() method
-/// on the original thread.
+/// on the target queue and returning its result by calling its done() method
+/// on the thread that dispatched it.
 ///
 /// The struct uses its has_run field to determine whether it should call
 /// run() or done().  It could instead check if task.result is Some or None,
@@ -96,37 +129,41 @@ pub trait Task {
 pub struct InitTaskRunnable {
     name: &'static str,
     task: Box<Task>,
+    origin: RefPtr<nsIEventTarget>,
     has_run: AtomicBool,
 }
 
 impl TaskRunnable {
     pub fn new(name: &'static str, task: Box<Task>) -> Result<RefPtr<TaskRunnable>, nsresult> {
-        debug_assert!(is_main_thread());
+        // Capture the calling thread so run() can hand the result back to it
+        // after dispatching to a database's task queue, rather than assuming
+        // it's always the main thread -- mirrors moz_task::spawn's origin
+        // capture, and lets callers dispatch from any thread.
+        let origin = get_current_thread()?
+            .query_interface::<nsIEventTarget>()
+            .ok_or(NS_ERROR_NO_INTERFACE)?;
+
         Ok(TaskRunnable::allocate(InitTaskRunnable {
             name,
             task,
+            origin,
             has_run: AtomicBool::new(false),
         }))
     }
-    pub fn dispatch(&self, target_thread: RefPtr<nsIThread>) -> Result<(), nsresult> {
-        unsafe {
-            target_thread.DispatchFromScript(self.coerce(), nsIEventTarget::DISPATCH_NORMAL as u32)
-        }.to_result()
+    pub fn dispatch(&self, target: RefPtr<nsIEventTarget>) -> Result<(), nsresult> {
+        unsafe { target.DispatchFromScript(self.coerce(), nsIEventTarget::DISPATCH_NORMAL as u32) }
+            .to_result()
     }
 
     xpcom_method!(Run, run, {});
     fn run(&self) -> Result<(), nsresult> {
         match self.has_run.load(Ordering::Acquire) {
             false => {
-                debug_assert!(!is_main_thread());
                 self.has_run.store(true, Ordering::Release);
                 self.task.run();
-                self.dispatch(get_main_thread()?)
-            }
-            true => {
-                debug_assert!(is_main_thread());
-                self.task.done()
+                self.dispatch(self.origin.clone())
             }
+            true => self.task.done(),
         }
     }
 
@@ -138,24 +175,27 @@ impl TaskRunnable {
 
 pub struct GetOrCreateTask {
     callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueDatabaseCallback>>>>,
-    thread: RefPtr<nsIThread>,
+    queue: RefPtr<nsIEventTarget>,
     path: nsCString,
     name: nsCString,
+    safe_mode: bool,
     result: AtomicCell<Option<Result<RefPtr<KeyValueDatabase>, KeyValueError>>>,
 }
 
 impl GetOrCreateTask {
     pub fn new(
         callback: RefPtr<nsIKeyValueDatabaseCallback>,
-        thread: RefPtr<nsIThread>,
+        queue: RefPtr<nsIEventTarget>,
         path: nsCString,
         name: nsCString,
+        safe_mode: bool,
     ) -> GetOrCreateTask {
         GetOrCreateTask {
             callback: ThreadBound::new(AtomicCell::new(Some(callback))),
-            thread,
+            queue,
             path,
             name,
+            safe_mode,
             result: AtomicCell::default(),
         }
     }
@@ -167,15 +207,32 @@ impl Task for GetOrCreateTask {
         // use the ? operator to simplify the implementation.
         self.result.store(Some(
             || -> Result<RefPtr<KeyValueDatabase>, KeyValueError> {
-                let mut writer = Manager::singleton().write()?;
-                let rkv = writer.get_or_create(Path::new(str::from_utf8(&self.path)?), Rkv::new)?;
-                let store = if self.name.is_empty() {
-                    rkv.write()?.open_or_create_default()
+                let path = Path::new(str::from_utf8(&self.path)?);
+                let name = if self.name.is_empty() {
+                    None
+                } else {
+                    Some(str::from_utf8(&self.name)?)
+                };
+
+                let env = if self.safe_mode {
+                    let mut writer = Manager::<SafeModeEnvironment>::singleton().write()?;
+                    let rkv = writer.get_or_create(path, Rkv::new_safe)?;
+                    let store = match name {
+                        Some(name) => rkv.write()?.open_or_create(Some(name)),
+                        None => rkv.write()?.open_or_create_default(),
+                    }?;
+                    Env::SafeMode(rkv, store)
                 } else {
-                    rkv.write()?
-                        .open_or_create(Some(str::from_utf8(&self.name)?))
-                }?;
-                Ok(KeyValueDatabase::new(rkv, store, self.thread.clone()))
+                    let mut writer = Manager::<LmdbEnvironment>::singleton().write()?;
+                    let rkv = writer.get_or_create(path, Rkv::new)?;
+                    let store = match name {
+                        Some(name) => rkv.write()?.open_or_create(Some(name)),
+                        None => rkv.write()?.open_or_create_default(),
+                    }?;
+                    Env::Lmdb(rkv, store)
+                };
+
+                Ok(KeyValueDatabase::new(env, self.queue.clone()))
             }(),
         ));
     }
@@ -185,8 +242,7 @@ impl Task for GetOrCreateTask {
 
 pub struct PutTask {
     callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVoidCallback>>>>,
-    rkv: Arc<RwLock<Rkv>>,
-    store: Store,
+    env: Env,
     key: nsCString,
     value: OwnedValue,
     result: AtomicCell<Option<Result<(), KeyValueError>>>,
@@ -195,15 +251,13 @@ pub struct PutTask {
 impl PutTask {
     pub fn new(
         callback: RefPtr<nsIKeyValueVoidCallback>,
-        rkv: Arc<RwLock<Rkv>>,
-        store: Store,
+        env: Env,
         key: nsCString,
         value: OwnedValue,
     ) -> PutTask {
         PutTask {
             callback: ThreadBound::new(AtomicCell::new(Some(callback))),
-            rkv,
-            store,
+            env,
             key,
             value,
             result: AtomicCell::default(),
@@ -217,20 +271,15 @@ impl Task for PutTask {
         // use the ? operator to simplify the implementation.
         self.result.store(Some(|| -> Result<(), KeyValueError> {
             let key = str::from_utf8(&self.key)?;
-            let env = self.rkv.read()?;
-            let mut writer = env.write()?;
-
-            let value = match self.value {
-                OwnedValue::Bool(val) => Value::Bool(val),
-                OwnedValue::I64(val) => Value::I64(val),
-                OwnedValue::F64(val) => Value::F64(val),
-                OwnedValue::Str(ref val) => Value::Str(&val),
-            };
+            let value = owned_to_value(&self.value)?;
 
-            writer.put(&self.store, key, &value)?;
-            writer.commit()?;
-
-            Ok(())
+            with_env!(&self.env, |rkv, store| {
+                let env = rkv.read()?;
+                let mut writer = env.write()?;
+                writer.put(store, key, &value)?;
+                writer.commit()?;
+                Ok(())
+            })
         }()));
     }
 
@@ -239,8 +288,7 @@ impl Task for PutTask {
 
 pub struct GetTask {
     callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVariantCallback>>>>,
-    rkv: Arc<RwLock<Rkv>>,
-    store: Store,
+    env: Env,
     key: nsCString,
     default_value: Option<OwnedValue>,
     result: AtomicCell<Option<Result<RefPtr<nsIVariant>, KeyValueError>>>,
@@ -249,15 +297,13 @@ pub struct GetTask {
 impl GetTask {
     pub fn new(
         callback: RefPtr<nsIKeyValueVariantCallback>,
-        rkv: Arc<RwLock<Rkv>>,
-        store: Store,
+        env: Env,
         key: nsCString,
         default_value: Option<OwnedValue>,
     ) -> GetTask {
         GetTask {
             callback: ThreadBound::new(AtomicCell::new(Some(callback))),
-            rkv,
-            store,
+            env,
             key,
             default_value,
             result: AtomicCell::default(),
@@ -272,26 +318,40 @@ impl Task for GetTask {
         self.result
             .store(Some(|| -> Result<RefPtr<nsIVariant>, KeyValueError> {
                 let key = str::from_utf8(&self.key)?;
-                let env = self.rkv.read()?;
-                let reader = env.read()?;
-                let value = reader.get(&self.store, key)?;
-
-                Ok(if let Some(value) = value {
-                    match value {
-                        Value::I64(value) => value.into_variant(),
-                        Value::F64(value) => value.into_variant(),
-                        Value::Str(value) => nsString::from(value).into_variant(),
-                        Value::Bool(value) => value.into_variant(),
-                        _ => return Err(KeyValueError::UnexpectedValue),
-                    }
-                } else {
-                    match self.default_value {
-                        Some(OwnedValue::Bool(value)) => value.into_variant(),
-                        Some(OwnedValue::I64(value)) => value.into_variant(),
-                        Some(OwnedValue::F64(value)) => value.into_variant(),
-                        Some(OwnedValue::Str(ref value)) => nsString::from(value).into_variant(),
-                        None => ().into_variant(),
-                    }
+
+                // The whole match-on-Value has to happen inside the
+                // with_env! arm, rather than after it: a Str/Blob Value
+                // borrows from `reader`'s transaction, which doesn't
+                // outlive the arm.
+                with_env!(&self.env, |rkv, store| {
+                    let env = rkv.read()?;
+                    let reader = env.read()?;
+                    let value = reader.get(store, key)?;
+
+                    Ok(if let Some(value) = value {
+                        match value {
+                            Value::I64(value) => value.into_variant(),
+                            Value::U64(value) => value.into_variant(),
+                            Value::F64(value) => value.into_variant(),
+                            Value::Str(value) => nsString::from(value).into_variant(),
+                            Value::Bool(value) => value.into_variant(),
+                            Value::Blob(value) => value.into_variant(),
+                            _ => return Err(KeyValueErrorKind::UnexpectedValue.into()),
+                        }
+                    } else {
+                        match self.default_value {
+                            Some(OwnedValue::Bool(value)) => value.into_variant(),
+                            Some(OwnedValue::I64(value)) => value.into_variant(),
+                            Some(OwnedValue::U64(value)) => value.into_variant(),
+                            Some(OwnedValue::F64(value)) => value.into_variant(),
+                            Some(OwnedValue::Str(ref value)) => nsString::from(value).into_variant(),
+                            Some(OwnedValue::Blob(ref value)) => value.into_variant(),
+                            Some(OwnedValue::Bag(_)) => {
+                                return Err(KeyValueErrorKind::UnexpectedValue.into())
+                            }
+                            None => ().into_variant(),
+                        }
+                    })
                 })
             }()));
     }
@@ -299,25 +359,114 @@ impl Task for GetTask {
     task_done!(value);
 }
 
-pub struct HasTask {
-    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVariantCallback>>>>,
+pub struct GetBagTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValuePropertyBagCallback>>>>,
     rkv: Arc<RwLock<Rkv>>,
     store: Store,
     key: nsCString,
+    result: AtomicCell<Option<Result<Vec<(String, OwnedValue)>, KeyValueError>>>,
+}
+
+impl GetBagTask {
+    pub fn new(
+        callback: RefPtr<nsIKeyValuePropertyBagCallback>,
+        rkv: Arc<RwLock<Rkv>>,
+        store: Store,
+        key: nsCString,
+    ) -> GetBagTask {
+        GetBagTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            rkv,
+            store,
+            key,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for GetBagTask {
+    fn run(&self) {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation.  The bag isn't
+        // rebuilt here -- nsIWritablePropertyBag and its variants aren't
+        // necessarily safe to create off the thread that called GetBag, so
+        // we only decode the raw properties here and build the bag in
+        // done(), on that thread.
+        self.result.store(Some(
+            || -> Result<Vec<(String, OwnedValue)>, KeyValueError> {
+                let key = str::from_utf8(&self.key)?;
+                let env = self.rkv.read()?;
+                let reader = env.read()?;
+
+                match reader.get(&self.store, key)? {
+                    Some(Value::Blob(bytes)) => decode_bag(bytes),
+                    Some(_value) => Err(KeyValueErrorKind::UnexpectedValue.into()),
+                    None => Ok(Vec::new()),
+                }
+            }(),
+        ));
+    }
+
+    fn done(&self) -> Result<(), nsresult> {
+        // If TaskRunnable.run() calls Task.done() to return a result
+        // on the main thread before TaskRunnable.run() returns on the database
+        // thread, then the Task will get dropped on the database thread.
+        //
+        // But the callback is an nsXPCWrappedJS that isn't safe to release
+        // on the database thread.  So we move it out of the Task here to ensure
+        // it gets released on the main thread.
+        let callback = self
+            .callback
+            .get_ref()
+            .ok_or(NS_ERROR_FAILURE)?
+            .swap(None)
+            .ok_or(NS_ERROR_FAILURE)?;
+
+        match self.result.swap(None) {
+            Some(Ok(properties)) => match build_property_bag(properties) {
+                Ok(bag) => unsafe { callback.Resolve(bag.coerce()) },
+                Err(err) => unsafe { callback.Reject(&*nsCString::from(err.to_string())) },
+            },
+            Some(Err(err)) => unsafe { callback.Reject(&*nsCString::from(err.to_string())) },
+            None => unsafe { callback.Reject(&*nsCString::from("unexpected")) },
+        }.to_result()
+    }
+}
+
+/// Builds an `nsIWritablePropertyBag` whose properties are the given
+/// scalar `OwnedValue`s converted to `Storage*Variant`s, for `GetBagTask`
+/// to hand back to its caller.
+fn build_property_bag(
+    properties: Vec<(String, OwnedValue)>,
+) -> Result<RefPtr<nsIWritablePropertyBag>, KeyValueError> {
+    let bag: RefPtr<nsIWritablePropertyBag> =
+        create_instance(&nsCString::from("@mozilla.org/hash-property-bag;1"))
+            .ok_or(KeyValueErrorKind::NoInterface("nsIWritablePropertyBag"))?;
+
+    for (name, value) in properties {
+        let variant = value.into_variant().ok_or(KeyValueErrorKind::UnexpectedValue)?.take();
+        unsafe { bag.SetProperty(&*nsString::from(&name), variant.coerce()) }.to_result()?;
+    }
+
+    Ok(bag)
+}
+
+pub struct HasTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVariantCallback>>>>,
+    env: Env,
+    key: nsCString,
     result: AtomicCell<Option<Result<RefPtr<nsIVariant>, KeyValueError>>>,
 }
 
 impl HasTask {
     pub fn new(
         callback: RefPtr<nsIKeyValueVariantCallback>,
-        rkv: Arc<RwLock<Rkv>>,
-        store: Store,
+        env: Env,
         key: nsCString,
     ) -> HasTask {
         HasTask {
             callback: ThreadBound::new(AtomicCell::new(Some(callback))),
-            rkv,
-            store,
+            env,
             key,
             result: AtomicCell::default(),
         }
@@ -331,9 +480,13 @@ impl Task for HasTask {
         self.result
             .store(Some(|| -> Result<RefPtr<nsIVariant>, KeyValueError> {
                 let key = str::from_utf8(&self.key)?;
-                let env = self.rkv.read()?;
-                let reader = env.read()?;
-                let value = reader.get(&self.store, key)?;
+
+                let value = with_env!(&self.env, |rkv, store| {
+                    let env = rkv.read()?;
+                    let reader = env.read()?;
+                    reader.get(store, key)
+                })?;
+
                 Ok(value.is_some().into_variant())
             }()));
     }
@@ -341,50 +494,361 @@ impl Task for HasTask {
     task_done!(value);
 }
 
+/// Whether `key` falls within the half-open `[from_key, to_key)` range that
+/// `CountTask`, `DeleteRangeTask`, and `EnumerateTask` all page forward
+/// through -- shared so the three can't drift apart on whether `to_key`
+/// itself is included (it isn't: an empty `to_key` means unbounded, anything
+/// else is an exclusive upper bound).
+fn key_before_to_key(key: &str, to_key: &str) -> bool {
+    to_key.is_empty() || key < to_key
+}
+
+pub struct CountTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVariantCallback>>>>,
+    env: Env,
+    from_key: nsCString,
+    to_key: nsCString,
+    result: AtomicCell<Option<Result<RefPtr<nsIVariant>, KeyValueError>>>,
+}
+
+impl CountTask {
+    pub fn new(
+        callback: RefPtr<nsIKeyValueVariantCallback>,
+        env: Env,
+        from_key: nsCString,
+        to_key: nsCString,
+    ) -> CountTask {
+        CountTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            env,
+            from_key,
+            to_key,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for CountTask {
+    fn run(&self) {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation.
+        self.result
+            .store(Some(|| -> Result<RefPtr<nsIVariant>, KeyValueError> {
+                let from_key = str::from_utf8(&self.from_key)?;
+                let to_key = str::from_utf8(&self.to_key)?;
+
+                let count = with_env!(&self.env, |rkv, store| {
+                    let env = rkv.read()?;
+                    let reader = env.read()?;
+
+                    // Unbounded counts don't need to look at a single key:
+                    // the backend already tracks its own entry count.
+                    if from_key.is_empty() && to_key.is_empty() {
+                        return Ok::<_, KeyValueError>(reader.entries(store)?);
+                    }
+
+                    let iterator = if from_key.is_empty() {
+                        reader.iter_start(store)?
+                    } else {
+                        reader.iter_from(store, &from_key)?
+                    };
+
+                    // Same [from_key, to_key) bound logic as EnumerateTask: we
+                    // only need the keys here, so there's no need to decode
+                    // values at all, just count how many keys fall in range.
+                    Ok(iterator
+                        .map(|(key, _val)| str::from_utf8(&key))
+                        .take_while(|key| match key {
+                            Ok(key) => key_before_to_key(key, to_key),
+                            Err(_err) => true,
+                        })
+                        .count())
+                })?;
+
+                Ok((count as u64).into_variant())
+            }()));
+    }
+
+    task_done!(value);
+}
+
 pub struct DeleteTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVoidCallback>>>>,
+    env: Env,
+    key: nsCString,
+    result: AtomicCell<Option<Result<(), KeyValueError>>>,
+}
+
+impl DeleteTask {
+    pub fn new(
+        callback: RefPtr<nsIKeyValueVoidCallback>,
+        env: Env,
+        key: nsCString,
+    ) -> DeleteTask {
+        DeleteTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            env,
+            key,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for DeleteTask {
+    fn run(&self) {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation.
+        self.result.store(Some(|| -> Result<(), KeyValueError> {
+            let key = str::from_utf8(&self.key)?;
+
+            with_env!(&self.env, |rkv, store| {
+                let env = rkv.read()?;
+                let mut writer = env.write()?;
+
+                match writer.delete(store, key) {
+                    Ok(_) => (),
+
+                    // LMDB fails with an error if the key to delete wasn't found,
+                    // and Rkv returns that error, but we ignore it, as we expect most
+                    // of our consumers to want this behavior.
+                    Err(StoreError::LmdbError(lmdb::Error::NotFound)) => (),
+
+                    Err(err) => return Err(KeyValueErrorKind::store_error(err).into()),
+                };
+
+                writer.commit()?;
+
+                Ok(())
+            })
+        }()));
+    }
+
+    task_done!(void);
+}
+
+pub struct ClearTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVoidCallback>>>>,
+    env: Env,
+    result: AtomicCell<Option<Result<(), KeyValueError>>>,
+}
+
+impl ClearTask {
+    pub fn new(callback: RefPtr<nsIKeyValueVoidCallback>, env: Env) -> ClearTask {
+        ClearTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            env,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for ClearTask {
+    fn run(&self) {
+        self.result.store(Some(|| -> Result<(), KeyValueError> {
+            with_env!(&self.env, |rkv, store| {
+                let env = rkv.read()?;
+                let mut writer = env.write()?;
+                writer.clear(store)?;
+                writer.commit()?;
+                Ok(())
+            })
+        }()));
+    }
+
+    task_done!(void);
+}
+
+pub struct DeleteRangeTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVoidCallback>>>>,
+    rkv: Arc<RwLock<Rkv>>,
+    store: Store,
+    from_key: nsCString,
+    to_key: nsCString,
+    result: AtomicCell<Option<Result<(), KeyValueError>>>,
+}
+
+impl DeleteRangeTask {
+    pub fn new(
+        callback: RefPtr<nsIKeyValueVoidCallback>,
+        rkv: Arc<RwLock<Rkv>>,
+        store: Store,
+        from_key: nsCString,
+        to_key: nsCString,
+    ) -> DeleteRangeTask {
+        DeleteRangeTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            rkv,
+            store,
+            from_key,
+            to_key,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for DeleteRangeTask {
+    fn run(&self) {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation.
+        self.result.store(Some(|| -> Result<(), KeyValueError> {
+            let env = self.rkv.read()?;
+            let mut writer = env.write()?;
+            let from_key = str::from_utf8(&self.from_key)?;
+            let to_key = str::from_utf8(&self.to_key)?;
+
+            // Collect the keys to delete before deleting any of them: a
+            // cursor returned by iter_from/iter_start borrows its
+            // transaction immutably, and we need writer mutably to delete,
+            // same reasoning as why EnumerateTask collects pairs into a Vec
+            // rather than deleting while iterating.
+            let keys = {
+                let iterator = if from_key.is_empty() {
+                    writer.iter_start(&self.store)?
+                } else {
+                    writer.iter_from(&self.store, &from_key)?
+                };
+
+                iterator
+                    .map(|(key, _val)| str::from_utf8(&key).map(|key| key.to_owned()))
+                    .take_while(|key| match key {
+                        Ok(key) => key_before_to_key(key, to_key),
+                        Err(_err) => true,
+                    })
+                    .collect::<Result<Vec<String>, _>>()?
+            };
+
+            for key in keys {
+                match writer.delete(&self.store, &key) {
+                    Ok(_) => (),
+
+                    // As in DeleteTask, ignore a key that's already gone.
+                    Err(StoreError::LmdbError(lmdb::Error::NotFound)) => (),
+
+                    Err(err) => return Err(KeyValueErrorKind::store_error(err).into()),
+                };
+            }
+
+            writer.commit()?;
+
+            Ok(())
+        }()));
+    }
+
+    task_done!(void);
+}
+
+pub struct PutBagTask {
     callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVoidCallback>>>>,
     rkv: Arc<RwLock<Rkv>>,
     store: Store,
     key: nsCString,
+    properties: Vec<(String, OwnedValue)>,
     result: AtomicCell<Option<Result<(), KeyValueError>>>,
 }
 
-impl DeleteTask {
+impl PutBagTask {
     pub fn new(
         callback: RefPtr<nsIKeyValueVoidCallback>,
         rkv: Arc<RwLock<Rkv>>,
         store: Store,
         key: nsCString,
-    ) -> DeleteTask {
-        DeleteTask {
+        properties: Vec<(String, OwnedValue)>,
+    ) -> PutBagTask {
+        PutBagTask {
             callback: ThreadBound::new(AtomicCell::new(Some(callback))),
             rkv,
             store,
             key,
+            properties,
             result: AtomicCell::default(),
         }
     }
 }
 
-impl Task for DeleteTask {
+impl Task for PutBagTask {
     fn run(&self) {
         // We do the work within a closure that returns a Result so we can
         // use the ? operator to simplify the implementation.
         self.result.store(Some(|| -> Result<(), KeyValueError> {
             let key = str::from_utf8(&self.key)?;
+            let bytes = encode_bag(&self.properties);
             let env = self.rkv.read()?;
             let mut writer = env.write()?;
 
-            match writer.delete(&self.store, key) {
-                Ok(_) => (),
+            writer.put(&self.store, key, &Value::Blob(&bytes))?;
+            writer.commit()?;
 
-                // LMDB fails with an error if the key to delete wasn't found,
-                // and Rkv returns that error, but we ignore it, as we expect most
-                // of our consumers to want this behavior.
-                Err(StoreError::LmdbError(lmdb::Error::NotFound)) => (),
+            Ok(())
+        }()));
+    }
 
-                Err(err) => return Err(KeyValueError::StoreError(err)),
-            };
+    task_done!(void);
+}
+
+/// One operation in a `WriteManyTask` batch: either set a key's value or,
+/// for parity with `DeleteTask`, remove it.
+pub enum WriteOp {
+    Put(nsCString, OwnedValue),
+    Delete(nsCString),
+}
+
+pub struct WriteManyTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueVoidCallback>>>>,
+    rkv: Arc<RwLock<Rkv>>,
+    store: Store,
+    ops: Vec<WriteOp>,
+    result: AtomicCell<Option<Result<(), KeyValueError>>>,
+}
+
+impl WriteManyTask {
+    pub fn new(
+        callback: RefPtr<nsIKeyValueVoidCallback>,
+        rkv: Arc<RwLock<Rkv>>,
+        store: Store,
+        ops: Vec<WriteOp>,
+    ) -> WriteManyTask {
+        WriteManyTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            rkv,
+            store,
+            ops,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for WriteManyTask {
+    fn run(&self) {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation.  If any op
+        // fails to apply, we return without committing, so the whole batch
+        // is rolled back along with the transaction.
+        self.result.store(Some(|| -> Result<(), KeyValueError> {
+            let env = self.rkv.read()?;
+            let mut writer = env.write()?;
+
+            for op in &self.ops {
+                match op {
+                    WriteOp::Put(key, value) => {
+                        let key = str::from_utf8(key)?;
+                        writer.put(&self.store, key, &owned_to_value(value)?)?;
+                    }
+
+                    WriteOp::Delete(key) => {
+                        let key = str::from_utf8(key)?;
+                        match writer.delete(&self.store, key) {
+                            Ok(_) => (),
+
+                            // LMDB fails with an error if the key to delete wasn't found,
+                            // and Rkv returns that error, but we ignore it, as we expect most
+                            // of our consumers to want this behavior.
+                            Err(StoreError::LmdbError(lmdb::Error::NotFound)) => (),
+
+                            Err(err) => return Err(KeyValueErrorKind::store_error(err).into()),
+                        }
+                    }
+                }
+            }
 
             writer.commit()?;
 
@@ -397,27 +861,36 @@ impl Task for DeleteTask {
 
 pub struct EnumerateTask {
     callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueEnumeratorCallback>>>>,
-    rkv: Arc<RwLock<Rkv>>,
-    store: Store,
+    env: Env,
     from_key: nsCString,
     to_key: nsCString,
+    limit: u64,
+    offset: u64,
+    reverse: bool,
+    queue: RefPtr<nsIEventTarget>,
     result: AtomicCell<Option<Result<RefPtr<KeyValueEnumerator>, KeyValueError>>>,
 }
 
 impl EnumerateTask {
     pub fn new(
         callback: RefPtr<nsIKeyValueEnumeratorCallback>,
-        rkv: Arc<RwLock<Rkv>>,
-        store: Store,
+        env: Env,
         from_key: nsCString,
         to_key: nsCString,
+        limit: u64,
+        offset: u64,
+        reverse: bool,
+        queue: RefPtr<nsIEventTarget>,
     ) -> EnumerateTask {
         EnumerateTask {
             callback: ThreadBound::new(AtomicCell::new(Some(callback))),
-            rkv,
-            store,
+            env,
             from_key,
             to_key,
+            limit,
+            offset,
+            reverse,
+            queue,
             result: AtomicCell::default(),
         }
     }
@@ -429,62 +902,272 @@ impl Task for EnumerateTask {
         // use the ? operator to simplify the implementation.
         self.result.store(Some(
             || -> Result<RefPtr<KeyValueEnumerator>, KeyValueError> {
-                let env = self.rkv.read()?;
-                let reader = env.read()?;
                 let from_key = str::from_utf8(&self.from_key)?;
                 let to_key = str::from_utf8(&self.to_key)?;
 
-                let iterator = if from_key.is_empty() {
-                    reader.iter_start(&self.store)?
-                } else {
-                    reader.iter_from(&self.store, &from_key)?
-                };
-
-                // Ideally, we'd enumerate pairs lazily, as the consumer calls
-                // nsIKeyValueEnumerator.getNext(), which calls our
-                // KeyValueEnumerator.get_next() implementation.  But KeyValueEnumerator
-                // can't reference the Iter because Rust "cannot #[derive(xpcom)]
-                // on a generic type," and the Iter requires a lifetime parameter,
-                // which would make KeyValueEnumerator generic.
-                //
-                // Our fallback approach is to eagerly collect the iterator
-                // into a collection that KeyValueEnumerator owns.  Fixing this so we
-                // enumerate pairs lazily is bug 1499252.
-                let pairs: Vec<(
+                let mut pairs: Vec<(
                     Result<String, KeyValueError>,
                     Result<OwnedValue, KeyValueError>,
-                )> = iterator
-                    // Convert the key to a string so we can compare it to the "to" key.
-                    // For forward compatibility, we don't fail here if we can't convert
-                    // a key to UTF-8.  Instead, we store the Err in the collection
-                    // and fail lazily in KeyValueEnumerator.get_next().
-                    .map(|(key, val)| (str::from_utf8(&key), val))
-                    .take_while(|(key, _val)| {
-                        if to_key.is_empty() {
-                            true
-                        } else {
-                            match *key {
-                                Ok(key) => key <= to_key,
+                )> = with_env!(&self.env, |rkv, store| {
+                    let env = rkv.read()?;
+                    let reader = env.read()?;
+
+                    let iterator = if from_key.is_empty() {
+                        reader.iter_start(store)?
+                    } else {
+                        reader.iter_from(store, &from_key)?
+                    };
+
+                    // Ideally, we'd enumerate pairs lazily, as the consumer calls
+                    // nsIKeyValueEnumerator.getNext(), which calls our
+                    // KeyValueEnumerator.get_next() implementation.  But KeyValueEnumerator
+                    // can't reference the Iter because Rust "cannot #[derive(xpcom)]
+                    // on a generic type," and the Iter requires a lifetime parameter,
+                    // which would make KeyValueEnumerator generic.
+                    //
+                    // Our fallback approach is to eagerly collect the iterator
+                    // into a collection that KeyValueEnumerator owns.  Bug 1499252.
+                    // Large-scan callers that don't want to pay that memory cost
+                    // up front should dispatch an EnumerateLazyTask instead, which
+                    // keeps the transaction and cursor open behind a LazyCursor.
+                    Ok::<_, KeyValueError>(
+                        iterator
+                            // Convert the key to a string so we can compare it to the "to" key.
+                            // For forward compatibility, we don't fail here if we can't convert
+                            // a key to UTF-8.  Instead, we store the Err in the collection
+                            // and fail lazily in KeyValueEnumerator.get_next().
+                            .map(|(key, val)| (str::from_utf8(&key), val))
+                            .take_while(|(key, _val)| match *key {
+                                Ok(key) => key_before_to_key(key, to_key),
                                 Err(_err) => true,
-                            }
-                        }
-                    }).map(|(key, val)| {
-                        (
-                            match key {
-                                Ok(key) => Ok(key.to_owned()),
-                                Err(err) => Err(err.into()),
-                            },
-                            match val {
-                                Ok(val) => value_to_owned(val),
-                                Err(err) => Err(KeyValueError::StoreError(err)),
-                            },
-                        )
-                    }).collect();
-
-                Ok(KeyValueEnumerator::new(pairs))
+                            })
+                            // Paging knobs apply on top of the [from_key, to_key)
+                            // bound above, while the cursor is still walking
+                            // forward -- reverse (below) only flips the order
+                            // of the page we've already bounded and sliced.
+                            .skip(self.offset as usize)
+                            .take(if self.limit == 0 {
+                                usize::max_value()
+                            } else {
+                                self.limit as usize
+                            }).map(|(key, val)| {
+                                (
+                                    match key {
+                                        Ok(key) => Ok(key.to_owned()),
+                                        Err(err) => Err(err.into()),
+                                    },
+                                    match val {
+                                        Ok(val) => value_to_owned(val),
+                                        Err(err) => Err(KeyValueErrorKind::store_error(err).into()),
+                                    },
+                                )
+                            }).collect(),
+                    )
+                })?;
+
+                if self.reverse {
+                    pairs.reverse();
+                }
+
+                Ok(KeyValueEnumerator::new(self.queue.clone(), pairs))
+            }(),
+        ));
+    }
+
+    task_done!(value);
+}
+
+/// A live cursor over a key range, backing the streaming variant of
+/// `enumerate`.  Unlike `EnumerateTask`, which collects every matching pair
+/// into a `Vec` up front, `LazyCursor` holds its rkv read transaction and
+/// `Iter` open and decodes one pair at a time as `GetNextTask` calls `next`.
+///
+/// `rkv::Reader`/`rkv::Iter` borrow from the `Rkv` environment they were
+/// opened against, so to keep them alive across the separate `get_next()`
+/// dispatches that drive a streaming enumeration, this struct has to own
+/// both the borrowed-from value and the thing borrowing from it.  We get
+/// there by boxing the read guard (so its address doesn't move even if
+/// `LazyCursor` itself does) and transmuting `reader`/`iter`'s borrow to
+/// `'static`.  That's sound as long as they never outlive the guard they
+/// were opened from, which struct field drop order (declaration order)
+/// guarantees here: `iter` and `reader` are dropped before `guard`.
+pub struct LazyCursor {
+    iter: Iter<'static, LmdbEnvironment>,
+    reader: Reader<'static, LmdbEnvironment, &'static str>,
+    guard: Box<RwLockReadGuard<'static, Rkv>>,
+    to_key: Option<String>,
+    done: bool,
+}
+
+impl LazyCursor {
+    fn open(
+        rkv: &Arc<RwLock<Rkv>>,
+        store: Store,
+        from_key: &nsCString,
+        to_key: nsCString,
+    ) -> Result<LazyCursor, KeyValueError> {
+        let guard: RwLockReadGuard<Rkv> = rkv.read()?;
+
+        // Safety: we box the guard so its address is stable regardless of
+        // where `LazyCursor` itself ends up, then widen its lifetime to
+        // `'static` so `reader`/`iter` (which borrow from it) can live in
+        // the same struct.  `reader` and `iter` are dropped before `guard`
+        // (struct fields drop in declaration order), so nothing derived
+        // from the guard outlives it.
+        let guard: Box<RwLockReadGuard<'static, Rkv>> = Box::new(unsafe { mem::transmute(guard) });
+        let env: &'static Rkv = unsafe { mem::transmute::<&Rkv, &'static Rkv>(&**guard) };
+
+        let reader: Reader<'static, LmdbEnvironment, &'static str> = env.read()?;
+        let from_key_str = str::from_utf8(from_key)?;
+        let iter = if from_key_str.is_empty() {
+            reader.iter_start(&store)?
+        } else {
+            reader.iter_from(&store, from_key_str)?
+        };
+
+        let to_key = str::from_utf8(&to_key)?;
+        let to_key = if to_key.is_empty() {
+            None
+        } else {
+            Some(to_key.to_owned())
+        };
+
+        Ok(LazyCursor {
+            iter,
+            reader,
+            guard,
+            to_key,
+            done: false,
+        })
+    }
+}
+
+impl Iterator for LazyCursor {
+    type Item = (Result<String, KeyValueError>, Result<OwnedValue, KeyValueError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (key, val) = self.iter.next()?;
+        let key = str::from_utf8(key);
+
+        // For forward compatibility, we don't fail here if we can't convert
+        // a key to UTF-8.  Instead, we store the Err in the pair and fail
+        // lazily in KeyValueEnumerator.get_next(), same as EnumerateTask.
+        if let (Some(to_key), Ok(key)) = (&self.to_key, key) {
+            if !key_before_to_key(key, to_key) {
+                self.done = true;
+                return None;
+            }
+        }
+
+        Some((
+            match key {
+                Ok(key) => Ok(key.to_owned()),
+                Err(err) => Err(err.into()),
+            },
+            match val {
+                Ok(val) => value_to_owned(val),
+                Err(err) => Err(KeyValueErrorKind::store_error(err).into()),
+            },
+        ))
+    }
+}
+
+/// An iterator over key/value pairs backing a `KeyValueEnumerator`:
+/// `Eager` wraps the `Vec` that `EnumerateTask` collects up front, `Lazy`
+/// wraps the open cursor that `EnumerateLazyTask` decodes on demand.
+pub enum EnumeratorIter {
+    Eager(IntoIter<(Result<String, KeyValueError>, Result<OwnedValue, KeyValueError>)>),
+    Lazy(LazyCursor),
+}
+
+impl Iterator for EnumeratorIter {
+    type Item = (Result<String, KeyValueError>, Result<OwnedValue, KeyValueError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            EnumeratorIter::Eager(iter) => iter.next(),
+            EnumeratorIter::Lazy(iter) => iter.next(),
+        }
+    }
+}
+
+pub struct EnumerateLazyTask {
+    callback: ThreadBound<AtomicCell<Option<RefPtr<nsIKeyValueEnumeratorCallback>>>>,
+    rkv: Arc<RwLock<Rkv>>,
+    store: Store,
+    from_key: nsCString,
+    to_key: nsCString,
+    queue: RefPtr<nsIEventTarget>,
+    result: AtomicCell<Option<Result<RefPtr<KeyValueEnumerator>, KeyValueError>>>,
+}
+
+impl EnumerateLazyTask {
+    pub fn new(
+        callback: RefPtr<nsIKeyValueEnumeratorCallback>,
+        rkv: Arc<RwLock<Rkv>>,
+        store: Store,
+        from_key: nsCString,
+        to_key: nsCString,
+        queue: RefPtr<nsIEventTarget>,
+    ) -> EnumerateLazyTask {
+        EnumerateLazyTask {
+            callback: ThreadBound::new(AtomicCell::new(Some(callback))),
+            rkv,
+            store,
+            from_key,
+            to_key,
+            queue,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for EnumerateLazyTask {
+    fn run(&self) {
+        // We do the work within a closure that returns a Result so we can
+        // use the ? operator to simplify the implementation.
+        self.result.store(Some(
+            || -> Result<RefPtr<KeyValueEnumerator>, KeyValueError> {
+                let cursor = LazyCursor::open(
+                    &self.rkv,
+                    self.store,
+                    &self.from_key,
+                    self.to_key.clone(),
+                )?;
+
+                Ok(KeyValueEnumerator::new_lazy(self.queue.clone(), cursor))
             }(),
         ));
     }
 
     task_done!(value);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::key_before_to_key;
+
+    #[test]
+    fn empty_to_key_means_unbounded() {
+        assert!(key_before_to_key("anything", ""));
+    }
+
+    #[test]
+    fn to_key_itself_is_excluded() {
+        assert!(!key_before_to_key("b", "b"));
+    }
+
+    #[test]
+    fn keys_before_to_key_are_included() {
+        assert!(key_before_to_key("a", "b"));
+    }
+
+    #[test]
+    fn keys_after_to_key_are_excluded() {
+        assert!(!key_before_to_key("c", "b"));
+    }
+}
@@ -0,0 +1,197 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use nserror::{
+    nsresult, NS_ERROR_FAILURE, NS_ERROR_ILLEGAL_VALUE, NS_ERROR_NOT_AVAILABLE,
+    NS_ERROR_UNEXPECTED, NS_OK,
+};
+use rkv::StoreError;
+use std::{
+    ffi::NulError,
+    fmt,
+    str::Utf8Error,
+    string::FromUtf16Error,
+    sync::PoisonError,
+};
+
+#[derive(Debug, Fail)]
+pub enum XULStoreError {
+    #[fail(display = "error converting string: {:?}", _0)]
+    ConvertBytes(Utf8Error),
+
+    #[fail(display = "error converting string: {:?}", _0)]
+    ConvertString(FromUtf16Error),
+
+    #[fail(display = "id or attribute name is too long")]
+    IdAttrNameTooLong,
+
+    #[fail(display = "error reading/writing JSON: {}", _0)]
+    Json(#[cause] serde_json::Error),
+
+    #[fail(display = "iteration finished")]
+    IterationFinished,
+
+    #[fail(display = "I/O error: {}", _0)]
+    Io(#[cause] std::io::Error),
+
+    #[fail(display = "string contains an interior null byte")]
+    NulError,
+
+    // TODO: use nsresult.error_name() to convert the number to its name.
+    #[fail(display = "error result {}", _0)]
+    Nsresult(nsresult),
+
+    #[fail(display = "poison error getting read/write lock")]
+    PoisonError,
+
+    #[fail(display = "store error: {}", _0)]
+    StoreError(#[cause] StoreError),
+
+    #[fail(display = "key doesn't have the form doc\\x09id\\x09attr: {:?}", _0)]
+    UnexpectedKey(String),
+
+    #[fail(display = "unexpected value type in store")]
+    UnexpectedValue,
+
+    #[fail(display = "XULStore is unavailable")]
+    Unavailable,
+}
+
+pub type XULStoreResult<T> = Result<T, XULStoreError>;
+
+impl From<XULStoreError> for nsresult {
+    fn from(err: XULStoreError) -> nsresult {
+        match err {
+            XULStoreError::ConvertBytes(_) => NS_ERROR_FAILURE,
+            XULStoreError::ConvertString(_) => NS_ERROR_FAILURE,
+            XULStoreError::IdAttrNameTooLong => NS_ERROR_ILLEGAL_VALUE,
+            XULStoreError::Json(_) => NS_ERROR_FAILURE,
+            XULStoreError::IterationFinished => NS_ERROR_FAILURE,
+            XULStoreError::Io(_) => NS_ERROR_FAILURE,
+            XULStoreError::NulError => NS_ERROR_ILLEGAL_VALUE,
+            XULStoreError::Nsresult(result) => result,
+            XULStoreError::PoisonError => NS_ERROR_UNEXPECTED,
+            XULStoreError::StoreError(_) => NS_ERROR_FAILURE,
+            XULStoreError::UnexpectedKey(_) => NS_ERROR_UNEXPECTED,
+            XULStoreError::UnexpectedValue => NS_ERROR_UNEXPECTED,
+            XULStoreError::Unavailable => NS_ERROR_NOT_AVAILABLE,
+        }
+    }
+}
+
+impl From<nsresult> for XULStoreError {
+    fn from(result: nsresult) -> XULStoreError {
+        XULStoreError::Nsresult(result)
+    }
+}
+
+impl From<StoreError> for XULStoreError {
+    fn from(err: StoreError) -> XULStoreError {
+        XULStoreError::StoreError(err)
+    }
+}
+
+impl From<serde_json::Error> for XULStoreError {
+    fn from(err: serde_json::Error) -> XULStoreError {
+        XULStoreError::Json(err)
+    }
+}
+
+impl From<std::io::Error> for XULStoreError {
+    fn from(err: std::io::Error) -> XULStoreError {
+        XULStoreError::Io(err)
+    }
+}
+
+impl From<NulError> for XULStoreError {
+    fn from(_: NulError) -> XULStoreError {
+        XULStoreError::NulError
+    }
+}
+
+impl From<Utf8Error> for XULStoreError {
+    fn from(err: Utf8Error) -> XULStoreError {
+        XULStoreError::ConvertBytes(err)
+    }
+}
+
+impl From<FromUtf16Error> for XULStoreError {
+    fn from(err: FromUtf16Error) -> XULStoreError {
+        XULStoreError::ConvertString(err)
+    }
+}
+
+impl<T> From<PoisonError<T>> for XULStoreError {
+    fn from(_: PoisonError<T>) -> XULStoreError {
+        XULStoreError::PoisonError
+    }
+}
+
+/// What `XULStore` was doing, and to which key, when a `XULStoreError`
+/// happened. Attached to an error via `ResultExt::context` so a single log
+/// line names both the failure and the call that caused it, instead of the
+/// task's `done()` handler logging the bare error with no idea which of its
+/// potentially several LMDB calls actually failed.
+#[derive(Debug)]
+pub struct ErrorContext {
+    operation: &'static str,
+    key: String,
+}
+
+/// A `XULStoreError` together with the `ErrorContext` (if any) that was
+/// attached to it via `ResultExt::context`. This is what the `SetValueTask`,
+/// `RemoveValueTask`, and `RemoveDocumentTask` `done()` handlers log, instead
+/// of a bare `XULStoreError`.
+#[derive(Debug)]
+pub struct TracedError {
+    kind: XULStoreError,
+    context: Option<ErrorContext>,
+}
+
+impl fmt::Display for TracedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.context {
+            Some(ctx) => write!(f, "{}({:?}): {}", ctx.operation, ctx.key, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl From<XULStoreError> for TracedError {
+    fn from(kind: XULStoreError) -> TracedError {
+        TracedError { kind, context: None }
+    }
+}
+
+/// Attaches an `ErrorContext` naming the operation and key in flight to a
+/// `XULStoreResult`'s error, turning it into a `TracedError`. Modeled on
+/// `failure::ResultExt::context`, but specialized to the operation/key shape
+/// `XULStore`'s tasks want in their logs rather than an arbitrary `Display`.
+pub trait ResultExt<T> {
+    fn context(self, operation: &'static str, key: impl Into<String>) -> Result<T, TracedError>;
+}
+
+impl<T> ResultExt<T> for XULStoreResult<T> {
+    fn context(self, operation: &'static str, key: impl Into<String>) -> Result<T, TracedError> {
+        self.map_err(|kind| TracedError {
+            kind,
+            context: Some(ErrorContext { operation, key: key.into() }),
+        })
+    }
+}
+
+/// A transparent wrapper around `nsresult` returned by `XULStore`'s `extern
+/// "C"` functions, so a `XULStoreResult<()>` can convert straight into a
+/// return value via `.into()` at the FFI boundary.
+#[repr(transparent)]
+pub struct XULStoreNsResult(pub nsresult);
+
+impl From<XULStoreResult<()>> for XULStoreNsResult {
+    fn from(result: XULStoreResult<()>) -> XULStoreNsResult {
+        match result {
+            Ok(()) => XULStoreNsResult(NS_OK),
+            Err(err) => XULStoreNsResult(err.into()),
+        }
+    }
+}
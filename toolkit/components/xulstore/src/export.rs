@@ -0,0 +1,255 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// JSON export/import of the entire XULStore, for backup/restore and
+// profile migration, and for seeding a store deterministically in tests.
+// Modeled on `persist.rs`'s `PersistTask`: the work happens on the
+// XULStore thread, and `done()` only logs the outcome, since there's no
+// synchronous caller waiting on the result.
+
+use crate::{
+    error::{XULStoreError, XULStoreResult},
+    make_key, unmake_key,
+    statics::{DATA, RKV, STORE},
+    GENERATION, THREAD,
+};
+use crossbeam_utils::atomic::AtomicCell;
+use moz_task::{Task, TaskRunnable};
+use nserror::nsresult;
+use rkv::Value;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::PathBuf,
+    sync::atomic::Ordering,
+};
+
+type XULStoreData = BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>;
+
+/// Whether an import overlays its entries onto the store's existing data
+/// (`Merge`) or wipes the store first (`Replace`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    Merge,
+    Replace,
+}
+
+pub(crate) fn export(path: PathBuf) -> XULStoreResult<()> {
+    let task = Box::new(ExportTask::new(path));
+    let thread = THREAD
+        .as_ref()
+        .ok_or(XULStoreError::Unavailable)?
+        .get_ref()
+        .ok_or(XULStoreError::Unavailable)?;
+    TaskRunnable::new("XULStore::Export", task)?.dispatch(thread)?;
+
+    Ok(())
+}
+
+pub(crate) fn import(path: PathBuf, mode: ImportMode) -> XULStoreResult<()> {
+    let task = Box::new(ImportTask::new(path, mode));
+    let thread = THREAD
+        .as_ref()
+        .ok_or(XULStoreError::Unavailable)?
+        .get_ref()
+        .ok_or(XULStoreError::Unavailable)?;
+    TaskRunnable::new("XULStore::Import", task)?.dispatch(thread)?;
+
+    Ok(())
+}
+
+pub struct ExportTask {
+    path: PathBuf,
+    result: AtomicCell<Option<XULStoreResult<()>>>,
+}
+
+impl ExportTask {
+    pub fn new(path: PathBuf) -> ExportTask {
+        ExportTask {
+            path,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for ExportTask {
+    fn run(&self) {
+        let result: XULStoreResult<()> = (|| {
+            let rkv_guard = RKV.read()?;
+            let rkv = rkv_guard
+                .as_ref()
+                .ok_or(XULStoreError::Unavailable)?
+                .read()?;
+            let reader = rkv.read()?;
+            let store = *STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
+            let mut all: XULStoreData = BTreeMap::new();
+            for result in store.iter_start(&reader)? {
+                let (key, value) = match result {
+                    Ok((key, value)) => (key, value),
+                    Err(err) => return Err(err.into()),
+                };
+
+                // Keys that don't decode as a doc/id/attr triple (e.g. an
+                // internal marker key) aren't XULStore data; skip them.
+                let (doc, id, attr) = match unmake_key(key) {
+                    Ok(parts) => parts,
+                    Err(_) => continue,
+                };
+                let value = match value {
+                    Some(Value::Str(value)) => value.to_owned(),
+                    _ => continue,
+                };
+
+                all.entry(doc)
+                    .or_insert_with(BTreeMap::new)
+                    .entry(id)
+                    .or_insert_with(BTreeMap::new)
+                    .insert(attr, value);
+            }
+
+            let file = File::create(&self.path)?;
+            serde_json::to_writer_pretty(file, &all)?;
+
+            Ok(())
+        })();
+
+        self.result.store(Some(result));
+    }
+
+    fn done(&self) -> Result<(), nsresult> {
+        match self.result.swap(None) {
+            Some(Ok(())) => info!("export succeeded"),
+            Some(Err(err)) => error!("export error: {}", err),
+            None => error!("export: unexpected result"),
+        };
+
+        Ok(())
+    }
+}
+
+pub struct ImportTask {
+    path: PathBuf,
+    mode: ImportMode,
+    result: AtomicCell<Option<XULStoreResult<()>>>,
+}
+
+impl ImportTask {
+    pub fn new(path: PathBuf, mode: ImportMode) -> ImportTask {
+        ImportTask {
+            path,
+            mode,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for ImportTask {
+    fn run(&self) {
+        let result: XULStoreResult<()> = (|| {
+            let file = File::open(&self.path)?;
+            let imported: XULStoreData = serde_json::from_reader(file)?;
+
+            let rkv_guard = RKV.read()?;
+            let rkv = rkv_guard
+                .as_ref()
+                .ok_or(XULStoreError::Unavailable)?
+                .read()?;
+            let store = *STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
+            // A Replace import wipes the store before writing the imported
+            // entries, so first collect the keys it currently holds.
+            let existing_keys: Vec<Vec<u8>> = if self.mode == ImportMode::Replace {
+                let reader = rkv.read()?;
+                let mut keys = Vec::new();
+                for result in store.iter_start(&reader)? {
+                    match result {
+                        Ok((key, _value)) => keys.push(key.to_vec()),
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+                keys
+            } else {
+                Vec::new()
+            };
+
+            let mut writer = rkv.write()?;
+
+            for key in &existing_keys {
+                store.delete(&mut writer, key.as_slice())?;
+            }
+
+            let mut data: XULStoreData = BTreeMap::new();
+            for (doc, ids) in imported {
+                // bug 319846 -- don't import really long attributes or values.
+                for (id, attrs) in ids {
+                    if id.len() > 512 {
+                        warn!("XULStore import: skipping id that's too long: {:?}", id);
+                        continue;
+                    }
+                    for (attr, value) in attrs {
+                        if attr.len() > 512 {
+                            warn!("XULStore import: skipping attr that's too long: {:?}", attr);
+                            continue;
+                        }
+                        let value = if value.len() > 4096 {
+                            warn!("XULStore import: truncating long attribute value");
+                            value[0..4096].to_owned()
+                        } else {
+                            value
+                        };
+
+                        let key = make_key(&doc, &id, &attr);
+                        store.put(&mut writer, key.as_slice(), &Value::Str(&value))?;
+
+                        data.entry(doc.clone())
+                            .or_insert_with(BTreeMap::new)
+                            .entry(id.clone())
+                            .or_insert_with(BTreeMap::new)
+                            .insert(attr, value);
+                    }
+                }
+            }
+
+            writer.commit()?;
+
+            // Refresh DATA to reflect the imported store, merging onto the
+            // existing cache for a Merge import or replacing it outright for
+            // a Replace import, then bump GENERATION so cached read
+            // snapshots pick up the change.
+            let mut data_guard = DATA.write()?;
+            match self.mode {
+                ImportMode::Replace => *data_guard = Some(data),
+                ImportMode::Merge => {
+                    let existing = data_guard.get_or_insert_with(BTreeMap::new);
+                    for (doc, ids) in data {
+                        let existing_ids = existing.entry(doc).or_insert_with(BTreeMap::new);
+                        for (id, attrs) in ids {
+                            existing_ids
+                                .entry(id)
+                                .or_insert_with(BTreeMap::new)
+                                .extend(attrs);
+                        }
+                    }
+                }
+            }
+            drop(data_guard);
+            GENERATION.fetch_add(1, Ordering::Relaxed);
+
+            Ok(())
+        })();
+
+        self.result.store(Some(result));
+    }
+
+    fn done(&self) -> Result<(), nsresult> {
+        match self.result.swap(None) {
+            Some(Ok(())) => info!("import succeeded"),
+            Some(Err(err)) => error!("import error: {}", err),
+            None => error!("import: unexpected result"),
+        };
+
+        Ok(())
+    }
+}
@@ -3,7 +3,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    error::XULStoreNsResult,
+    error::{XULStoreError, XULStoreNsResult, XULStoreResult},
+    export::{self, ImportMode},
     iter::XULStoreIterator,
     statics::{update_profile_dir, DATA},
     XULStore,
@@ -132,6 +133,23 @@ impl XULStoreService {
     ) -> Result<(), nsresult> {
         XULStore::remove_value(doc, id, attr).map_err(|err| err.into())
     }
+
+    xpcom_method!(
+        remove_value_array => RemoveValueArray(
+            doc: *const nsAString,
+            id: *const nsAString,
+            attr: *const nsAString
+        )
+    );
+
+    fn remove_value_array(
+        &self,
+        doc: &nsAString,
+        id: &nsAString,
+        attr: &nsAString,
+    ) -> Result<(), nsresult> {
+        XULStore::remove_value_array(doc, id, attr).map_err(|err| err.into())
+    }
 }
 
 #[derive(xpcom)]
@@ -206,6 +224,52 @@ pub unsafe extern "C" fn xulstore_remove_value(
     XULStore::remove_value(doc, id, attr).into()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn xulstore_get_value_array(
+    doc: &nsAString,
+    id: &nsAString,
+    attr: &nsAString,
+    result: *mut nsresult,
+) -> *mut XULStoreIterator {
+    match XULStore::get_value_array(doc, id, attr) {
+        Ok(iter) => {
+            *result = NS_OK;
+            Box::into_raw(Box::new(iter))
+        }
+        Err(err) => {
+            *result = err.into();
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn xulstore_set_value_array(
+    doc: &nsAString,
+    id: &nsAString,
+    attr: &nsAString,
+    values: *const *const nsAString,
+    values_len: usize,
+) -> XULStoreNsResult {
+    (|| -> XULStoreResult<()> {
+        let values = std::slice::from_raw_parts(values, values_len)
+            .iter()
+            .map(|value| String::from_utf16(&**value).map_err(XULStoreError::from))
+            .collect::<XULStoreResult<Vec<String>>>()?;
+        XULStore::set_value_array(doc, id, attr, values)
+    })()
+    .into()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn xulstore_remove_value_array(
+    doc: &nsAString,
+    id: &nsAString,
+    attr: &nsAString,
+) -> XULStoreNsResult {
+    XULStore::remove_value_array(doc, id, attr).into()
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn xulstore_get_ids(
     doc: &nsAString,
@@ -264,3 +328,22 @@ pub unsafe extern "C" fn xulstore_iter_get_next(
 pub unsafe extern "C" fn xulstore_iter_free(iter: *mut XULStoreIterator) {
     drop(Box::from_raw(iter));
 }
+
+#[no_mangle]
+pub unsafe extern "C" fn xulstore_export(path: &nsAString) -> XULStoreNsResult {
+    (|| -> XULStoreResult<()> {
+        let path = String::from_utf16(path)?;
+        export::export(path.into())
+    })()
+    .into()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn xulstore_import(path: &nsAString, merge: bool) -> XULStoreNsResult {
+    let mode = if merge { ImportMode::Merge } else { ImportMode::Replace };
+    (|| -> XULStoreResult<()> {
+        let path = String::from_utf16(path)?;
+        export::import(path.into(), mode)
+    })()
+    .into()
+}
@@ -20,14 +20,15 @@ extern crate serde_json;
 extern crate xpcom;
 
 mod error;
+mod export;
 mod ffi;
 mod iter;
 mod statics;
 
 use crate::{
-    error::{XULStoreError, XULStoreResult},
+    error::{ResultExt, TracedError, XULStoreError, XULStoreResult},
     iter::XULStoreIterator,
-    statics::{DATA, RKV, STORE},
+    statics::{DATA, LIST_STORE, RKV, STORE},
 };
 use crossbeam_utils::atomic::AtomicCell;
 use lmdb::Error as LmdbError;
@@ -36,14 +37,168 @@ use nserror::nsresult;
 use nsstring::nsAString;
 use rkv::{StoreError as RkvStoreError, Value};
 use std::{
+    cell::RefCell,
     collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread_local,
 };
 use xpcom::{interfaces::nsIThread, RefPtr, ThreadBoundRefPtr};
 
-const SEPARATOR: char = '\u{0009}';
+/// Appends `component` to `out`, escaping every `0x00` byte as `0x00 0xFF`
+/// and then terminating with an unescaped `0x00 0x00`. Used by `make_key` to
+/// join `doc`/`id`/`attr` into a single LMDB key without a separator byte
+/// that could collide with the data.
+///
+/// A length prefix (varint or otherwise) would sort components by byte
+/// length before content -- e.g. `"z"` would sort before `"aa"` even though
+/// `"aa" < "z"` lexicographically -- breaking `make_key`'s sort-by-`doc`-
+/// then-`id`-then-`attr` invariant. Escaping `0x00` and reserving it as a
+/// terminator keeps the encoding's byte order identical to the original
+/// string's.
+fn push_component(out: &mut Vec<u8>, component: &str) {
+    for &byte in component.as_bytes() {
+        out.push(byte);
+        if byte == 0 {
+            out.push(0xff);
+        }
+    }
+    out.push(0);
+    out.push(0);
+}
+
+/// Reads a `push_component`-encoded component off the front of `input`,
+/// returning it along with whatever bytes follow it.
+fn pop_component(input: &[u8]) -> XULStoreResult<(String, &[u8])> {
+    let mut component = Vec::new();
+    let mut pos = 0;
+    loop {
+        let byte = *input.get(pos).ok_or_else(|| XULStoreError::UnexpectedKey(format!("{:?}", input)))?;
+        if byte != 0 {
+            component.push(byte);
+            pos += 1;
+            continue;
+        }
+
+        match input.get(pos + 1) {
+            Some(0) => {
+                pos += 2;
+                break;
+            }
+            Some(0xff) => {
+                component.push(0);
+                pos += 2;
+            }
+            _ => return Err(XULStoreError::UnexpectedKey(format!("{:?}", input))),
+        }
+    }
 
-pub(crate) fn make_key<T: std::fmt::Display>(doc: &T, id: &T, attr: &T) -> String {
-    format!("{}{}{}{}{}", doc, SEPARATOR, id, SEPARATOR, attr)
+    let component = std::str::from_utf8(&component)?.to_owned();
+    Ok((component, &input[pos..]))
+}
+
+/// Joins `doc`, `id`, and `attr` into a single LMDB key by escaping and
+/// terminating each component in turn (see `push_component`), so keys sort
+/// by `doc` then `id` then `attr` and no byte value in any component (e.g. a
+/// tab) can corrupt or collide with another key, unlike the old
+/// TAB-separated scheme.
+pub(crate) fn make_key<T: std::fmt::Display>(doc: &T, id: &T, attr: &T) -> Vec<u8> {
+    let mut key = Vec::new();
+    push_component(&mut key, &doc.to_string());
+    push_component(&mut key, &id.to_string());
+    push_component(&mut key, &attr.to_string());
+    key
+}
+
+/// The inverse of `make_key`: decodes a stored key back into its `(doc, id,
+/// attr)` parts.
+pub(crate) fn unmake_key(key: &[u8]) -> XULStoreResult<(String, String, String)> {
+    let (doc, rest) = pop_component(key)?;
+    let (id, rest) = pop_component(rest)?;
+    let (attr, rest) = pop_component(rest)?;
+    if !rest.is_empty() {
+        return Err(XULStoreError::UnexpectedKey(format!("{:?}", key)));
+    }
+    Ok((doc, id, attr))
+}
+
+/// A prefix over the `doc` (or `doc` + `id`) component(s) of `make_key`'s
+/// encoding, for range-scanning `get_ids`/`get_attrs` directly against LMDB
+/// rather than the in-memory cache.
+fn make_key_prefix(doc: &nsAString, id: Option<&nsAString>) -> Vec<u8> {
+    let mut prefix = Vec::new();
+    push_component(&mut prefix, &doc.to_string());
+    if let Some(id) = id {
+        push_component(&mut prefix, &id.to_string());
+    }
+    prefix
+}
+
+/// Scans the LMDB store for every key sharing `prefix`, returning their raw
+/// bytes. `get_ids`/`get_attrs` decode these via `unmake_key` rather than
+/// walking and sorting the in-memory `DATA` cache, so they keep working even
+/// before that cache has been populated.
+fn scan_key_prefix(prefix: &[u8]) -> XULStoreResult<std::vec::IntoIter<Vec<u8>>> {
+    let rkv_guard = RKV.read()?;
+    let rkv = rkv_guard.as_ref().ok_or(XULStoreError::Unavailable)?.read()?;
+    let reader = rkv.read()?;
+    let store = *STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    for result in store.iter_prefix(&reader, prefix)? {
+        match result {
+            Ok((key, _value)) => keys.push(key.to_vec()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(keys.into_iter())
+}
+
+type XULStoreData = BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>;
+
+/// Bumped after every `DATA`-mutating call (`set_value`/`remove_value`/
+/// `remove_document`), so a thread's cached read snapshot can tell it's
+/// stale without taking `DATA`'s lock to find out.
+pub(crate) static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+struct Snapshot {
+    generation: u64,
+    data: Arc<XULStoreData>,
+}
+
+thread_local! {
+    /// Each thread's lazily-populated, immutable view of `DATA`. Read APIs
+    /// consult this instead of taking `DATA`'s `RwLock`, refreshing it only
+    /// when `GENERATION` has moved past the generation it was built from.
+    static SNAPSHOT: RefCell<Option<Snapshot>> = RefCell::new(None);
+}
+
+/// Returns the calling thread's current read snapshot of `DATA`, lazily
+/// populating or refreshing it under `DATA.read()` if it's missing or
+/// stale. The common case -- an already-current snapshot -- costs a single
+/// relaxed atomic load and an `Rc`-style clone, with no lock acquired.
+fn snapshot() -> XULStoreResult<Arc<XULStoreData>> {
+    let wanted_generation = GENERATION.load(Ordering::Relaxed);
+
+    let cached = SNAPSHOT.with(|cell| match cell.borrow().as_ref() {
+        Some(snapshot) if snapshot.generation == wanted_generation => Some(snapshot.data.clone()),
+        _ => None,
+    });
+    if let Some(data) = cached {
+        return Ok(data);
+    }
+
+    let data = Arc::new(DATA.read()?.clone().unwrap_or_default());
+    SNAPSHOT.with(|cell| {
+        *cell.borrow_mut() = Some(Snapshot {
+            generation: wanted_generation,
+            data: data.clone(),
+        });
+    });
+    Ok(data)
 }
 
 lazy_static! {
@@ -92,6 +247,8 @@ impl XULStore {
         data.entry(doc.to_string()).or_insert(BTreeMap::new())
            .entry(id.to_string()).or_insert(BTreeMap::new())
            .insert(attr.to_string(), value.clone());
+        drop(data_guard);
+        GENERATION.fetch_add(1, Ordering::Relaxed);
 
         let task = Box::new(SetValueTask::new(key, value));
         let thread = THREAD.as_ref().ok_or(XULStoreError::Unavailable)?.get_ref().ok_or(XULStoreError::Unavailable)?;
@@ -103,11 +260,7 @@ impl XULStore {
     fn has_value(doc: &nsAString, id: &nsAString, attr: &nsAString) -> XULStoreResult<bool> {
         debug!("XULStore has value: {} {} {}", doc, id, attr);
 
-        let data_guard = DATA.read()?;
-        let data = match data_guard.as_ref() {
-            Some(data) => data,
-            None => return Ok(false),
-        };
+        let data = snapshot()?;
 
         match data.get(&doc.to_string()) {
             Some(ids) => {
@@ -123,11 +276,7 @@ impl XULStore {
     fn get_value(doc: &nsAString, id: &nsAString, attr: &nsAString) -> XULStoreResult<String> {
         debug!("XULStore get value {} {} {}", doc, id, attr);
 
-        let data_guard = DATA.read()?;
-        let data = match data_guard.as_ref() {
-            Some(data) => data,
-            None => return Ok("".to_owned()),
-        };
+        let data = snapshot()?;
 
         match data.get(&doc.to_string()) {
             Some(ids) => {
@@ -179,6 +328,8 @@ impl XULStore {
         if ids_empty {
             data.remove(&doc.to_string());
         }
+        drop(data_guard);
+        GENERATION.fetch_add(1, Ordering::Relaxed);
 
         let key = make_key(doc, id, attr);
         let task = Box::new(RemoveValueTask::new(key));
@@ -197,7 +348,7 @@ impl XULStore {
             None => return Ok(()),
         };
 
-        let mut keys_to_remove: Vec<String> = Vec::new();
+        let mut keys_to_remove: Vec<Vec<u8>> = Vec::new();
         let doc = doc.to_string();
 
         // Build a list of keys to remove from the store.
@@ -214,6 +365,8 @@ impl XULStore {
 
         // We can remove the document from the data cache in one fell swoop.
         data.remove(&doc.to_string());
+        drop(data_guard);
+        GENERATION.fetch_add(1, Ordering::Relaxed);
 
         let task = Box::new(RemoveDocumentTask::new(keys_to_remove));
         let thread = THREAD.as_ref().ok_or(XULStoreError::Unavailable)?.get_ref().ok_or(XULStoreError::Unavailable)?;
@@ -225,64 +378,95 @@ impl XULStore {
     fn get_ids(doc: &nsAString) -> XULStoreResult<XULStoreIterator> {
         debug!("XULStore get IDs for {}", doc);
 
-        let data_guard = DATA.read()?;
-        let data = match data_guard.as_ref() {
-            Some(data) => data,
-            None => return Ok(XULStoreIterator::new(vec![].into_iter())),
-        };
-
-        match data.get(&doc.to_string()) {
-            Some(ids) => {
-                let mut ids: Vec<String> = ids.keys()
-                .map(|id| id.to_owned())
-                .collect();
-                // TODO: rather than sorting here, use a pre-sorted
-                // data structure, such as a BTreeMap, so the items
-                // are already in sorted order.
-                ids.sort();
-                Ok(XULStoreIterator::new(ids.into_iter()))
-            },
-            None => Ok(XULStoreIterator::new(vec![].into_iter())),
-        }
+        // `make_key`'s encoding sorts by doc, then id, then attr, so a
+        // straight prefix scan over `doc`'s keys already yields ids in
+        // sorted order -- no need to clone/sort the in-memory cache, and
+        // this works even before the cache has been populated.
+        let prefix = make_key_prefix(doc, None);
+        let mut ids = scan_key_prefix(&prefix)?
+            .map(|key| Ok(unmake_key(&key)?.1))
+            .collect::<XULStoreResult<Vec<String>>>()?;
+        // One key per attr, so an id with several attrs appears once per
+        // attr, consecutively; collapse those runs.
+        ids.dedup();
+        Ok(XULStoreIterator::new(ids.into_iter()))
     }
 
     fn get_attrs(doc: &nsAString, id: &nsAString) -> XULStoreResult<XULStoreIterator> {
         debug!("XULStore get attrs for doc, ID: {} {}", doc, id);
 
-        let data_guard = DATA.read()?;
-        let data = match data_guard.as_ref() {
-            Some(data) => data,
-            None => return Ok(XULStoreIterator::new(vec![].into_iter())),
-        };
+        let prefix = make_key_prefix(doc, Some(id));
+        let attrs = scan_key_prefix(&prefix)?
+            .map(|key| Ok(unmake_key(&key)?.2))
+            .collect::<XULStoreResult<Vec<String>>>()?;
+        Ok(XULStoreIterator::new(attrs.into_iter()))
+    }
 
-        match data.get(&doc.to_string()) {
-            Some(ids) => {
-                match ids.get(&id.to_string()) {
-                    Some(attrs) => {
-                        let mut attrs: Vec<String> = attrs.keys().map(|attr| attr.to_owned()).collect();
-                        // TODO: rather than sorting here, use a pre-sorted
-                        // data structure, such as a BTreeMap, so the items
-                        // are already in sorted order.
-                        attrs.sort();
-                        Ok(XULStoreIterator::new(attrs.into_iter()))
-                    },
-                    None => Ok(XULStoreIterator::new(vec![].into_iter())),
-                }
-            },
-            None => Ok(XULStoreIterator::new(vec![].into_iter())),
+    /// Reads every value under `(doc, id, attr)` in `LIST_STORE`, in
+    /// dup-sort order. Unlike `get_value`, this always goes straight to the
+    /// store rather than the `DATA` cache, since list-valued attributes
+    /// aren't mirrored there.
+    fn get_value_array(doc: &nsAString, id: &nsAString, attr: &nsAString) -> XULStoreResult<XULStoreIterator> {
+        debug!("XULStore get value array {} {} {}", doc, id, attr);
+
+        let key = make_key(doc, id, attr);
+
+        let rkv_guard = RKV.read()?;
+        let rkv = rkv_guard.as_ref().ok_or(XULStoreError::Unavailable)?.read()?;
+        let reader = rkv.read()?;
+        let list_store = *LIST_STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
+        let mut values: Vec<String> = Vec::new();
+        for (_key, value) in reader.get_multi(&list_store, key.as_slice())? {
+            match value {
+                Ok(Some(Value::Str(value))) => values.push(value.to_owned()),
+                Ok(_) => return Err(XULStoreError::UnexpectedValue),
+                Err(err) => return Err(err.into()),
+            }
         }
+
+        Ok(XULStoreIterator::new(values.into_iter()))
+    }
+
+    /// Replaces every value under `(doc, id, attr)` in `LIST_STORE` with
+    /// `values`, in the order given -- note that `get_value_array` returns
+    /// them back in dup-sort (not insertion) order.
+    fn set_value_array(doc: &nsAString, id: &nsAString, attr: &nsAString, values: Vec<String>) -> XULStoreResult<()> {
+        debug!("XULStore set value array {} {} {}", doc, id, attr);
+
+        if id.len() > 512 || attr.len() > 512 {
+            return Err(XULStoreError::IdAttrNameTooLong);
+        }
+
+        let key = make_key(doc, id, attr);
+        let task = Box::new(SetValueArrayTask::new(key, values));
+        let thread = THREAD.as_ref().ok_or(XULStoreError::Unavailable)?.get_ref().ok_or(XULStoreError::Unavailable)?;
+        TaskRunnable::new("XULStore::SetValueArray", task)?.dispatch(thread)?;
+
+        Ok(())
+    }
+
+    fn remove_value_array(doc: &nsAString, id: &nsAString, attr: &nsAString) -> XULStoreResult<()> {
+        debug!("XULStore remove value array {} {} {}", doc, id, attr);
+
+        let key = make_key(doc, id, attr);
+        let task = Box::new(RemoveValueArrayTask::new(key));
+        let thread = THREAD.as_ref().ok_or(XULStoreError::Unavailable)?.get_ref().ok_or(XULStoreError::Unavailable)?;
+        TaskRunnable::new("XULStore::RemoveValueArray", task)?.dispatch(thread)?;
+
+        Ok(())
     }
 }
 
 pub struct SetValueTask {
-    key: String,
+    key: Vec<u8>,
     value: String,
-    result: AtomicCell<Option<Result<(), XULStoreError>>>,
+    result: AtomicCell<Option<Result<(), TracedError>>>,
 }
 
 impl SetValueTask {
     pub fn new(
-        key: String,
+        key: Vec<u8>,
         value: String,
     ) -> SetValueTask {
         SetValueTask {
@@ -295,7 +479,7 @@ impl SetValueTask {
 
 impl Task for SetValueTask {
     fn run(&self) {
-        self.result.store(Some(|| -> Result<(), XULStoreError> {
+        let result: XULStoreResult<()> = (|| {
             let rkv_guard = RKV.read()?;
             let rkv = rkv_guard
                 .as_ref()
@@ -307,15 +491,19 @@ impl Task for SetValueTask {
             writer.commit()?;
 
             Ok(())
-        }()));
+        })();
+
+        self.result.store(Some(
+            result.context("setValue", String::from_utf8_lossy(&self.key).into_owned()),
+        ));
     }
 
     fn done(&self) -> Result<(), nsresult> {
         match self.result.swap(None) {
             // TODO: error! -> info!
             Some(Ok(())) => { error!("setValue succeeded")},
-            Some(Err(err)) => error!("setValue error: {}", err),
-            None => error!("setValue error: unexpected result"),
+            Some(Err(err)) => error!("{}", err),
+            None => error!("setValue: unexpected result"),
         };
 
         Ok(())
@@ -323,13 +511,13 @@ impl Task for SetValueTask {
 }
 
 pub struct RemoveValueTask {
-    key: String,
-    result: AtomicCell<Option<Result<(), XULStoreError>>>,
+    key: Vec<u8>,
+    result: AtomicCell<Option<Result<(), TracedError>>>,
 }
 
 impl RemoveValueTask {
     pub fn new(
-        key: String,
+        key: Vec<u8>,
     ) -> RemoveValueTask {
         RemoveValueTask {
             key,
@@ -340,7 +528,7 @@ impl RemoveValueTask {
 
 impl Task for RemoveValueTask {
     fn run(&self) {
-        self.result.store(Some(|| -> Result<(), XULStoreError> {
+        let result: XULStoreResult<()> = (|| {
             let rkv_guard = RKV.read()?;
             let rkv = rkv_guard
                 .as_ref()
@@ -362,15 +550,128 @@ impl Task for RemoveValueTask {
 
                 Err(err) => Err(err.into()),
             }
-        }()));
+        })();
+
+        self.result.store(Some(
+            result.context("removeValue", String::from_utf8_lossy(&self.key).into_owned()),
+        ));
     }
 
     fn done(&self) -> Result<(), nsresult> {
         match self.result.swap(None) {
             // TODO: error! -> info!
             Some(Ok(())) => { error!("removeValue succeeded")},
-            Some(Err(err)) => error!("removeValue error: {}", err),
-            None => error!("removeValue error: unexpected result"),
+            Some(Err(err)) => error!("{}", err),
+            None => error!("removeValue: unexpected result"),
+        };
+
+        Ok(())
+    }
+}
+
+pub struct SetValueArrayTask {
+    key: Vec<u8>,
+    values: Vec<String>,
+    result: AtomicCell<Option<Result<(), TracedError>>>,
+}
+
+impl SetValueArrayTask {
+    pub fn new(
+        key: Vec<u8>,
+        values: Vec<String>,
+    ) -> SetValueArrayTask {
+        SetValueArrayTask {
+            key,
+            values,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for SetValueArrayTask {
+    fn run(&self) {
+        let result: XULStoreResult<()> = (|| {
+            let rkv_guard = RKV.read()?;
+            let rkv = rkv_guard
+                .as_ref()
+                .ok_or(XULStoreError::Unavailable)?
+                .read()?;
+            let mut writer = rkv.write()?;
+            let list_store = *LIST_STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
+            // Replace the whole list in one transaction, rather than diffing
+            // the old and new sets of values, since a list-valued attribute
+            // is always written as a unit by its caller.
+            writer.delete_all(&list_store, &self.key)?;
+            for value in &self.values {
+                writer.put_multi(&list_store, &self.key, &Value::Str(value))?;
+            }
+            writer.commit()?;
+
+            Ok(())
+        })();
+
+        self.result.store(Some(
+            result.context("setValueArray", String::from_utf8_lossy(&self.key).into_owned()),
+        ));
+    }
+
+    fn done(&self) -> Result<(), nsresult> {
+        match self.result.swap(None) {
+            // TODO: error! -> info!
+            Some(Ok(())) => { error!("setValueArray succeeded")},
+            Some(Err(err)) => error!("{}", err),
+            None => error!("setValueArray: unexpected result"),
+        };
+
+        Ok(())
+    }
+}
+
+pub struct RemoveValueArrayTask {
+    key: Vec<u8>,
+    result: AtomicCell<Option<Result<(), TracedError>>>,
+}
+
+impl RemoveValueArrayTask {
+    pub fn new(
+        key: Vec<u8>,
+    ) -> RemoveValueArrayTask {
+        RemoveValueArrayTask {
+            key,
+            result: AtomicCell::default(),
+        }
+    }
+}
+
+impl Task for RemoveValueArrayTask {
+    fn run(&self) {
+        let result: XULStoreResult<()> = (|| {
+            let rkv_guard = RKV.read()?;
+            let rkv = rkv_guard
+                .as_ref()
+                .ok_or(XULStoreError::Unavailable)?
+                .read()?;
+            let mut writer = rkv.write()?;
+            let list_store = *LIST_STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
+            writer.delete_all(&list_store, &self.key)?;
+            writer.commit()?;
+
+            Ok(())
+        })();
+
+        self.result.store(Some(
+            result.context("removeValueArray", String::from_utf8_lossy(&self.key).into_owned()),
+        ));
+    }
+
+    fn done(&self) -> Result<(), nsresult> {
+        match self.result.swap(None) {
+            // TODO: error! -> info!
+            Some(Ok(())) => { error!("removeValueArray succeeded")},
+            Some(Err(err)) => error!("{}", err),
+            None => error!("removeValueArray: unexpected result"),
         };
 
         Ok(())
@@ -378,13 +679,13 @@ impl Task for RemoveValueTask {
 }
 
 pub struct RemoveDocumentTask {
-    keys_to_remove: Vec<String>,
-    result: AtomicCell<Option<Result<(), XULStoreError>>>,
+    keys_to_remove: Vec<Vec<u8>>,
+    result: AtomicCell<Option<Result<(), TracedError>>>,
 }
 
 impl RemoveDocumentTask {
     pub fn new(
-        keys_to_remove: Vec<String>,
+        keys_to_remove: Vec<Vec<u8>>,
     ) -> RemoveDocumentTask {
         RemoveDocumentTask {
             keys_to_remove,
@@ -395,46 +696,80 @@ impl RemoveDocumentTask {
 
 impl Task for RemoveDocumentTask {
     fn run(&self) {
-        self.result.store(Some(|| -> Result<(), XULStoreError> {
-            let rkv_guard = RKV.read()?;
+        let result: Result<(), TracedError> = (|| {
+            let rkv_guard = RKV.read().map_err(XULStoreError::from)?;
             let rkv = rkv_guard
                 .as_ref()
                 .ok_or(XULStoreError::Unavailable)?
-                .read()?;
-            let mut writer = rkv.write()?;
-            let store = *STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+                .read()
+                .map_err(XULStoreError::from)?;
+            let mut writer = rkv.write().map_err(XULStoreError::from)?;
+            let store = *STORE
+                .read()
+                .map_err(XULStoreError::from)?
+                .as_ref()
+                .ok_or(XULStoreError::Unavailable)?;
 
             // Removing the document from the store requires iterating the keys
-            // to remove.
-            self.keys_to_remove.iter().map(|key|
-                match store.delete(&mut writer, &key) {
-                    Ok(_) => Ok(()),
+            // to remove. Each key is traced individually, since the keys to
+            // remove come from different IDs/attrs and a failure partway
+            // through should say which one it was.
+            for key in &self.keys_to_remove {
+                match store.delete(&mut writer, key) {
+                    Ok(_) => (),
 
                     // The XULStore API doesn't care if a consumer tries to remove
                     // a value that doesn't actually exist, so we ignore that error,
                     // although in this case the key should exist since it was in
                     // the cache!
                     // TODO: warn if a key doesn't exist.
-                    Err(RkvStoreError::LmdbError(LmdbError::NotFound)) => Ok(()),
+                    Err(RkvStoreError::LmdbError(LmdbError::NotFound)) => (),
 
-                    Err(err) => Err(err.into()),
+                    Err(err) => {
+                        return Err(XULStoreError::from(err))
+                            .context("removeDocument", String::from_utf8_lossy(key).into_owned())
+                    }
                 }
-            ).collect::<Result<Vec<()>, XULStoreError>>()?;
+            }
 
-            writer.commit()?;
+            writer.commit().map_err(XULStoreError::from)?;
 
             Ok(())
-        }()));
+        })();
+
+        self.result.store(Some(result));
     }
 
     fn done(&self) -> Result<(), nsresult> {
         match self.result.swap(None) {
             // TODO: error! -> info!
             Some(Ok(())) => { error!("removeDocument succeeded")},
-            Some(Err(err)) => error!("removeDocument error: {}", err),
-            None => error!("removeDocument error: unexpected result"),
+            Some(Err(err)) => error!("{}", err),
+            None => error!("removeDocument: unexpected result"),
         };
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{make_key, push_component};
+
+    /// A longer component that is a lexicographically smaller byte string
+    /// must sort before a shorter one -- i.e. keys must sort by content, not
+    /// by component length.
+    #[test]
+    fn push_component_sorts_by_content_not_length() {
+        let mut shorter = Vec::new();
+        push_component(&mut shorter, "z");
+        let mut longer = Vec::new();
+        push_component(&mut longer, "aa");
+        assert!(longer < shorter, "\"aa\" should sort before \"z\"");
+    }
+
+    #[test]
+    fn make_key_sorts_docs_lexicographically() {
+        assert!(make_key(&"aa", &"id", &"attr") < make_key(&"z", &"id", &"attr"));
+    }
+}
@@ -4,7 +4,7 @@
 
 use crate::{
     error::{XULStoreError, XULStoreResult},
-    statics::{get_database, THREAD},
+    statics::{RKV, STORE, THREAD},
 };
 use crossbeam_utils::atomic::AtomicCell;
 use lmdb::Error as LmdbError;
@@ -52,8 +52,10 @@ impl PersistTask {
 impl Task for PersistTask {
     fn run(&self) {
         self.result.store(Some(|| -> Result<(), XULStoreError> {
-            let db = get_database()?;
-            let mut writer = db.env.write()?;
+            let rkv_guard = RKV.read()?;
+            let rkv = rkv_guard.as_ref().ok_or(XULStoreError::Unavailable)?.read()?;
+            let store = *STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+            let mut writer = rkv.write()?;
 
             // Get the map of key/value pairs from the mutex, replacing it
             // with None.
@@ -66,9 +68,9 @@ impl Task for PersistTask {
 
             for (key, value) in writes.iter() {
                 match value {
-                    Some(val) => db.store.put(&mut writer, &key, &Value::Str(val))?,
+                    Some(val) => store.put(&mut writer, &key, &Value::Str(val))?,
                     None => {
-                        match db.store.delete(&mut writer, &key) {
+                        match store.delete(&mut writer, &key) {
                             Ok(_) => (),
 
                             // The XULStore API doesn't care if a consumer tries
@@ -5,19 +5,23 @@
 use crate::{
     error::{XULStoreError, XULStoreResult},
     ffi::ProfileChangeObserver,
-    make_key, SEPARATOR,
+    make_key, unmake_key,
 };
+use lmdb::Error as LmdbError;
 use moz_task::create_thread;
 use nsstring::nsString;
-use rkv::{Rkv, SingleStore, StoreOptions, Value};
+use rkv::{
+    backend::{BackendEnvironment, LmdbEnvironment, SafeModeEnvironment},
+    MultiStore, Rkv, Store, StoreError, StoreOptions, Value,
+};
 use std::{
     collections::BTreeMap,
     ffi::CString,
     fs::{create_dir_all, remove_file, File},
     ops::DerefMut,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str,
-    sync::RwLock,
+    sync::{Arc, RwLock},
 };
 use xpcom::{
     interfaces::{nsIFile, nsIThread},
@@ -26,12 +30,44 @@ use xpcom::{
 
 type XULStoreData = BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>;
 
+// XULStore opens exactly one environment for the life of the process --
+// unlike kvstore, which juggles many databases and has to pick a backend
+// per call via its `Env` enum, XULStore picks once, at build time. Flip the
+// "xulstore-safe-mode" feature to get rkv's pure-Rust SafeMode backend
+// instead of LMDB, e.g. for platforms where LMDB's mmap semantics are
+// unreliable or sandboxing forbids the mapping.
+#[cfg(not(feature = "xulstore-safe-mode"))]
+type Backend = LmdbEnvironment;
+#[cfg(feature = "xulstore-safe-mode")]
+type Backend = SafeModeEnvironment;
+
+type BackendDatabase = <Backend as BackendEnvironment<'static>>::Database;
+
 lazy_static! {
     pub(crate) static ref PROFILE_DIR: RwLock<Option<PathBuf>> = {
         observe_profile_change();
         RwLock::new(get_profile_dir().ok())
     };
-    pub(crate) static ref CACHE: RwLock<Option<XULStoreData>> = { RwLock::new(cache_data().ok()) };
+
+    pub(crate) static ref RKV: RwLock<Option<Arc<RwLock<Rkv<Backend>>>>> = {
+        RwLock::new(get_env().ok().map(|env| Arc::new(RwLock::new(env))))
+    };
+
+    pub(crate) static ref STORE: RwLock<Option<Store<BackendDatabase>>> = {
+        RwLock::new(get_store().ok())
+    };
+
+    // A dup-sort sibling of STORE, for attributes that hold several ordered
+    // values under one (doc, id, attr) key (e.g. window-ordering, toolbar
+    // item lists) instead of forcing callers to pack a delimited string into
+    // a single Value::Str. Opened independently of STORE's migration dance,
+    // since there's no legacy on-disk format to migrate into it.
+    pub(crate) static ref LIST_STORE: RwLock<Option<MultiStore<BackendDatabase>>> = {
+        RwLock::new(get_list_store().ok())
+    };
+
+    pub(crate) static ref DATA: RwLock<Option<XULStoreData>> = { RwLock::new(cache_data().ok()) };
+
     pub(crate) static ref THREAD: Option<ThreadBoundRefPtr<nsIThread>> = {
         let thread: RefPtr<nsIThread> = match create_thread("XULStore") {
             Ok(thread) => thread,
@@ -81,39 +117,55 @@ fn get_xulstore_dir() -> XULStoreResult<PathBuf> {
     Ok(xulstore_dir)
 }
 
-pub(crate) struct Database {
-    pub env: Rkv,
-    pub store: SingleStore,
-}
-
-impl Database {
-    fn new(env: Rkv, store: SingleStore) -> Database {
-        Database { env, store }
+#[cfg(not(feature = "xulstore-safe-mode"))]
+fn open_backend(path: &Path) -> Result<Rkv<Backend>, StoreError> {
+    match Rkv::new(path) {
+        Ok(env) => Ok(env),
+        // A profile moved between a 32-bit and a 64-bit build leaves behind
+        // a data.mdb whose meta page LMDB can't make sense of, so the open
+        // above fails as if the file were corrupt. `migrate` re-parses the
+        // file by hand for the *other* word size and rewrites it in place;
+        // it's a no-op error (not a panic or corruption) when the file
+        // wasn't actually a word-size mismatch, so the original open error
+        // is what gets surfaced in that case.
+        Err(open_err) => match Rkv::<LmdbEnvironment>::migrate(path) {
+            Ok(_) => Rkv::new(path),
+            Err(_) => Err(open_err),
+        },
     }
 }
 
-pub(crate) fn get_database() -> XULStoreResult<Database> {
-    let env = get_env()?;
-    let store = get_store(&env)?;
-    Ok(Database::new(env, store))
+#[cfg(feature = "xulstore-safe-mode")]
+fn open_backend(path: &Path) -> Result<Rkv<Backend>, StoreError> {
+    Rkv::new_safe(path)
 }
 
-fn get_env() -> XULStoreResult<Rkv> {
+fn get_env() -> XULStoreResult<Rkv<Backend>> {
     let xulstore_dir = get_xulstore_dir()?;
-    Rkv::new(xulstore_dir.as_path()).map_err(|err| err.into())
+    open_backend(xulstore_dir.as_path()).map_err(|err| err.into())
 }
 
-fn get_store(env: &Rkv) -> XULStoreResult<SingleStore> {
-    match env.open_single("db", StoreOptions::create()) {
+fn get_store() -> XULStoreResult<Store<BackendDatabase>> {
+    let rkv_guard = RKV.read()?;
+    let rkv = rkv_guard.as_ref().ok_or(XULStoreError::Unavailable)?.read()?;
+
+    match rkv.open_single("db", StoreOptions::create()) {
         Ok(store) => {
-            maybe_migrate_data(env, store);
+            maybe_migrate_data(&rkv, store);
+            maybe_rekey_legacy_entries(&rkv, store);
             Ok(store)
         }
         Err(err) => Err(err.into()),
     }
 }
 
-fn maybe_migrate_data(env: &Rkv, store: SingleStore) {
+fn get_list_store() -> XULStoreResult<MultiStore<BackendDatabase>> {
+    let rkv_guard = RKV.read()?;
+    let rkv = rkv_guard.as_ref().ok_or(XULStoreError::Unavailable)?.read()?;
+    rkv.open_or_create_multi("list").map_err(|err| err.into())
+}
+
+fn maybe_migrate_data(env: &Rkv<Backend>, store: Store<BackendDatabase>) {
     // Failure to migrate data isn't fatal, so we don't return a result.
     // But we use a closure returning a result to enable use of the ? operator.
     (|| -> XULStoreResult<()> {
@@ -152,6 +204,84 @@ fn maybe_migrate_data(env: &Rkv, store: SingleStore) {
     .unwrap_or_else(|err| error!("error migrating data: {}", err));
 }
 
+// A marker key (not a valid `make_key` encoding, since in that encoding a
+// NUL byte is always followed by another NUL or by 0xFF, never by 'x') that
+// records which key format the store is in, so re-keying runs at most once.
+const KEY_FORMAT_KEY: &str = "\u{0}xulstore-key-format";
+const KEY_FORMAT_TAB_SEPARATED: i64 = 1;
+const KEY_FORMAT_LENGTH_PREFIXED: i64 = 2;
+
+// Re-keys entries written under the old TAB-separated `make_key` scheme
+// (see bug migrating off of it) to the current escaped-and-terminated
+// encoding (see `push_component`), the first time a store created under the old scheme is opened.
+fn maybe_rekey_legacy_entries(env: &Rkv<Backend>, store: Store<BackendDatabase>) {
+    // Failure to re-key isn't fatal, so we don't return a result. But we use
+    // a closure returning a result to enable use of the ? operator.
+    (|| -> XULStoreResult<()> {
+        let format = {
+            let reader = env.read()?;
+            match store.get(&reader, KEY_FORMAT_KEY.as_bytes())? {
+                Some(Value::I64(format)) => format,
+                // No marker yet: either a brand new, empty store, or one
+                // created before this marker existed, i.e. TAB-separated.
+                _ => KEY_FORMAT_TAB_SEPARATED,
+            }
+        };
+
+        if format >= KEY_FORMAT_LENGTH_PREFIXED {
+            return Ok(());
+        }
+
+        let legacy_entries = {
+            let reader = env.read()?;
+            let mut entries = Vec::new();
+            for result in store.iter_start(&reader)? {
+                let (key, value) = match result {
+                    Ok((key, value)) => (key, value),
+                    Err(err) => return Err(err.into()),
+                };
+                let key = match str::from_utf8(key) {
+                    Ok(key) => key,
+                    // Not a legacy TAB-separated key; leave it alone.
+                    Err(_) => continue,
+                };
+                let parts: Vec<&str> = key.splitn(3, '\u{0009}').collect();
+                if parts.len() == 3 {
+                    if let Some(Value::Str(value)) = value {
+                        entries.push((
+                            parts[0].to_owned(),
+                            parts[1].to_owned(),
+                            parts[2].to_owned(),
+                            value.to_owned(),
+                        ));
+                    }
+                }
+            }
+            entries
+        };
+
+        // All keys below are written as `&[u8]`, since the writer's put/
+        // delete calls must agree on a single key type and the old
+        // TAB-separated keys, the new escaped-and-terminated keys, and the marker
+        // key don't share one otherwise.
+        let mut writer = env.write()?;
+        for (doc, id, attr, value) in &legacy_entries {
+            let old_key = format!("{}\u{0009}{}\u{0009}{}", doc, id, attr);
+            match store.delete(&mut writer, old_key.as_bytes()) {
+                Ok(()) | Err(StoreError::LmdbError(LmdbError::NotFound)) => (),
+                Err(err) => return Err(err.into()),
+            }
+            let new_key = make_key(doc, id, attr);
+            store.put(&mut writer, new_key.as_slice(), &Value::Str(value))?;
+        }
+        store.put(&mut writer, KEY_FORMAT_KEY.as_bytes(), &Value::I64(KEY_FORMAT_LENGTH_PREFIXED))?;
+        writer.commit()?;
+
+        Ok(())
+    })()
+    .unwrap_or_else(|err| error!("error re-keying legacy XULStore entries: {}", err));
+}
+
 fn observe_profile_change() {
     // Failure to observe the change isn't fatal (although it means we won't
     // persist XULStore data for this session), so we don't return a result.
@@ -181,8 +311,27 @@ pub(crate) fn update_profile_dir() {
             *profile_dir_guard = get_profile_dir().ok();
         }
 
-        let mut cache_guard = CACHE.write()?;
-        *cache_guard = cache_data().ok();
+        // The env and store are profile-scoped too, so a profile change
+        // means reopening both before DATA can be refreshed against them.
+        {
+            let mut rkv_guard = RKV.write()?;
+            *rkv_guard = get_env().ok().map(|env| Arc::new(RwLock::new(env)));
+        }
+
+        {
+            let mut store_guard = STORE.write()?;
+            *store_guard = get_store().ok();
+        }
+
+        {
+            let mut list_store_guard = LIST_STORE.write()?;
+            *list_store_guard = get_list_store().ok();
+        }
+
+        let mut data_guard = DATA.write()?;
+        *data_guard = cache_data().ok();
+        drop(data_guard);
+        crate::GENERATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
         Ok(())
     })()
@@ -205,33 +354,32 @@ fn unwrap_value(value: &Option<Value>) -> XULStoreResult<String> {
 }
 
 fn cache_data() -> XULStoreResult<XULStoreData> {
-    let db = get_database()?;
-    let reader = db.env.read()?;
+    let rkv_guard = RKV.read()?;
+    let rkv = rkv_guard.as_ref().ok_or(XULStoreError::Unavailable)?.read()?;
+    let reader = rkv.read()?;
+    let store = *STORE.read()?.as_ref().ok_or(XULStoreError::Unavailable)?;
+
     let mut all = BTreeMap::new();
-    let iterator = db.store.iter_start(&reader)?;
+    let iterator = store.iter_start(&reader)?;
 
     for result in iterator {
-        let (key, value): (&str, String) = match result {
+        let (key, value) = match result {
             Ok((key, value)) => {
                 assert!(value.is_some(), "iterated key has value");
-                match (str::from_utf8(&key), unwrap_value(&value)) {
-                    (Ok(key), Ok(value)) => (key, value),
-                    (Err(err), _) => return Err(err.into()),
-                    (_, Err(err)) => return Err(err),
-                }
+                (key, value)
             }
             Err(err) => return Err(err.into()),
         };
 
-        let parts = key.split(SEPARATOR).collect::<Vec<&str>>();
-        if parts.len() != 3 {
-            return Err(XULStoreError::UnexpectedKey(key.to_owned()));
-        }
-        let (doc, id, attr) = (
-            parts[0].to_owned(),
-            parts[1].to_owned(),
-            parts[2].to_owned(),
-        );
+        // Keys that don't decode as a `doc`/`id`/`attr` triple aren't
+        // XULStore data -- e.g. the `KEY_FORMAT_KEY` marker used by
+        // `maybe_rekey_legacy_entries` -- so skip them rather than failing
+        // the whole cache population.
+        let (doc, id, attr) = match unmake_key(key) {
+            Ok(parts) => parts,
+            Err(_) => continue,
+        };
+        let value = unwrap_value(&value)?;
 
         all.entry(doc)
             .or_insert_with(BTreeMap::new)
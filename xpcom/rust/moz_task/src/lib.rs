@@ -5,16 +5,28 @@
 //! This module wraps XPCOM threading functions with Rust functions
 //! to make it safer and more convenient to call the XPCOM functions.
 
+extern crate crossbeam_utils;
 extern crate nserror;
 extern crate nsstring;
+#[macro_use]
 extern crate xpcom;
 
-use nserror::nsresult;
+use crossbeam_utils::atomic::AtomicCell;
+use nserror::{nsresult, NsresultExt, NS_ERROR_NO_INTERFACE};
 use nsstring::{nsACString, nsCString};
-use std::ptr;
+use std::{
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
 use xpcom::{
     getter_addrefs,
-    interfaces::{nsIRunnable, nsIThread},
+    interfaces::{nsIEventTarget, nsIRunnable, nsISerialEventTarget, nsIThread},
     RefPtr,
 };
 
@@ -27,6 +39,10 @@ extern "C" {
         result: *mut *const nsIThread,
         event: *const nsIRunnable,
     ) -> nsresult;
+    fn NS_CreateBackgroundTaskQueue(
+        name: *const nsACString,
+        result: *mut *const nsISerialEventTarget,
+    ) -> nsresult;
 }
 
 pub fn get_current_thread() -> Result<RefPtr<nsIThread>, nsresult> {
@@ -46,3 +62,139 @@ pub fn create_thread(name: &str) -> Result<RefPtr<nsIThread>, nsresult> {
         NS_NewNamedThreadWithDefaultStackSize(&*nsCString::from(name), p, ptr::null())
     })
 }
+
+/// Creates a serial background task queue: a lightweight event target that
+/// runs its runnables one at a time, like a dedicated thread would, but
+/// without the overhead of actually owning one. Callers that need work
+/// serialized with respect to itself, but not pinned to a specific thread,
+/// should prefer this over `create_thread`.
+pub fn create_background_task_queue(name: &str) -> Result<RefPtr<nsISerialEventTarget>, nsresult> {
+    getter_addrefs(|p| unsafe { NS_CreateBackgroundTaskQueue(&*nsCString::from(name), p) })
+}
+
+/// The state shared between a `TaskFuture` and the `SpawnRunnable` pair
+/// that resolves it: the closure's result, once it's ready, and the waker
+/// to notify once it is.
+struct SpawnState<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// A `Future` that resolves with the value a closure passed to `spawn`
+/// returns, once that closure has finished running on its target thread.
+pub struct TaskFuture<T> {
+    state: Arc<Mutex<SpawnState<T>>>,
+}
+
+impl<T> Future for TaskFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The runnable that carries a spawned closure to its target thread and,
+/// once the closure has run, carries its result back to the thread that
+/// called `spawn`.  Modeled on kvstore's `TaskRunnable`: `has_run` tells it
+/// which half of the round trip it's performing, and it redispatches itself
+/// to the origin thread to perform the other half.
+#[derive(xpcom)]
+#[xpimplements(nsIRunnable, nsINamed)]
+#[refcnt = "atomic"]
+pub struct InitSpawnRunnable {
+    name: &'static str,
+    origin: RefPtr<nsIEventTarget>,
+    work: AtomicCell<Option<Box<FnOnce() -> Box<FnOnce() + Send> + Send>>>,
+    done: AtomicCell<Option<Box<FnOnce() + Send>>>,
+    has_run: AtomicBool,
+}
+
+impl SpawnRunnable {
+    fn dispatch(&self, target: RefPtr<nsIEventTarget>) -> Result<(), nsresult> {
+        unsafe { target.DispatchFromScript(self.coerce(), nsIEventTarget::DISPATCH_NORMAL as u32) }
+            .to_result()
+    }
+
+    xpcom_method!(Run, run, {});
+    fn run(&self) -> Result<(), nsresult> {
+        match self.has_run.load(Ordering::Acquire) {
+            false => {
+                self.has_run.store(true, Ordering::Release);
+                if let Some(work) = self.work.swap(None) {
+                    self.done.store(Some(work()));
+                }
+                self.dispatch(self.origin.clone())
+            }
+            true => {
+                if let Some(done) = self.done.swap(None) {
+                    done();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    xpcom_method!(GetName, get_name, {}, *mut nsACString);
+    fn get_name(&self) -> Result<nsCString, nsresult> {
+        Ok(nsCString::from(self.name))
+    }
+}
+
+/// Runs `work` on `target` and returns a `Future` that resolves with its
+/// result once `work` completes, instead of going through an XPCOM
+/// callback.  `work` still runs on `target`, and the future still resolves
+/// on the thread `spawn` was called from (via the same waker that polled
+/// it), preserving the usual "do it on a background thread, finish up on
+/// the originating thread" threading model without per-call callback
+/// plumbing.  `target` can be a thread or a serial task queue -- anything
+/// that implements `nsIEventTarget`.
+pub fn spawn<T, F>(
+    name: &'static str,
+    target: RefPtr<nsIEventTarget>,
+    work: F,
+) -> Result<TaskFuture<T>, nsresult>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let origin = get_current_thread()?
+        .query_interface::<nsIEventTarget>()
+        .ok_or(NS_ERROR_NO_INTERFACE)?;
+    let state = Arc::new(Mutex::new(SpawnState {
+        result: None,
+        waker: None,
+    }));
+
+    let done_state = Arc::clone(&state);
+    let work: Box<FnOnce() -> Box<FnOnce() + Send> + Send> = Box::new(move || {
+        let result = work();
+        let done: Box<FnOnce() + Send> = Box::new(move || {
+            let mut state = done_state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                drop(state);
+                waker.wake();
+            }
+        });
+        done
+    });
+
+    let runnable = SpawnRunnable::allocate(InitSpawnRunnable {
+        name,
+        origin,
+        work: AtomicCell::new(Some(work)),
+        done: AtomicCell::new(None),
+        has_run: AtomicBool::new(false),
+    });
+    runnable.dispatch(target)?;
+
+    Ok(TaskFuture { state })
+}